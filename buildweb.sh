@@ -1,3 +1,5 @@
+set -e
+# Builds the wasm bundle into web/, rerun after changing the wasm crate
 cd wasm
 wasm-pack build --release --target web
 cp pkg/* ../web
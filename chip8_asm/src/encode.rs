@@ -0,0 +1,170 @@
+use crate::ErrorKind;
+
+const MNEMONICS: [&str; 32] = [
+    "NOP", "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+// An instruction operand after symbols have been resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    V(u8),
+    I,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    IndirectI, // [I]
+    R,
+    Long(i64), // LONG NNNN, only used by LD I
+    Value(i64),
+}
+
+pub fn is_mnemonic(name: &str) -> bool {
+    MNEMONICS.contains(&name)
+}
+
+// Bytes taken by an instruction, known before its operands are resolved
+pub fn size(mnemonic: &str, operands: &[String]) -> u16 {
+    let long = operands
+        .get(1)
+        .is_some_and(|operand| operand.to_ascii_uppercase().starts_with("LONG "));
+    if mnemonic == "LD" && long {
+        4
+    } else {
+        2
+    }
+}
+
+// Parses V0 to VF in either case
+pub fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['V', 'v'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// Parses decimal, 0x hex, $ hex or 0b binary, with an optional minus sign
+pub fn number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+        .or_else(|| digits.strip_prefix('$'))
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Encodes an instruction as one or two opcode words
+pub fn encode(mnemonic: &str, operands: &[Operand]) -> Result<Vec<u16>, ErrorKind> {
+    use Operand::*;
+
+    let opcode = match (mnemonic, operands) {
+        ("NOP", []) => 0x0000,
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("JP", [Value(nnn)]) => 0x1000 | address(*nnn)?,
+        ("JP", [V(0), Value(nnn)]) => 0xB000 | address(*nnn)?,
+        ("CALL", [Value(nnn)]) => 0x2000 | address(*nnn)?,
+        ("SE", [V(x), Value(nn)]) => 0x3000 | xy(*x, 0) | byte(*nn)?,
+        ("SNE", [V(x), Value(nn)]) => 0x4000 | xy(*x, 0) | byte(*nn)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y),
+        ("SAVE", [V(x), V(y)]) => 0x5002 | xy(*x, *y),
+        ("LOAD", [V(x), V(y)]) => 0x5003 | xy(*x, *y),
+        ("LD", [V(x), Value(nn)]) => 0x6000 | xy(*x, 0) | byte(*nn)?,
+        ("ADD", [V(x), Value(nn)]) => 0x7000 | xy(*x, 0) | byte(*nn)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y),
+        ("OR", [V(x), V(y)]) => 0x8001 | xy(*x, *y),
+        ("AND", [V(x), V(y)]) => 0x8002 | xy(*x, *y),
+        ("XOR", [V(x), V(y)]) => 0x8003 | xy(*x, *y),
+        ("ADD", [V(x), V(y)]) => 0x8004 | xy(*x, *y),
+        ("SUB", [V(x), V(y)]) => 0x8005 | xy(*x, *y),
+        ("SHR", [V(x)]) => 0x8006 | xy(*x, *x),
+        ("SHR", [V(x), V(y)]) => 0x8006 | xy(*x, *y),
+        ("SUBN", [V(x), V(y)]) => 0x8007 | xy(*x, *y),
+        ("SHL", [V(x)]) => 0x800E | xy(*x, *x),
+        ("SHL", [V(x), V(y)]) => 0x800E | xy(*x, *y),
+        ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y),
+        ("LD", [I, Value(nnn)]) => 0xA000 | address(*nnn)?,
+        ("RND", [V(x), Value(nn)]) => 0xC000 | xy(*x, 0) | byte(*nn)?,
+        ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | xy(*x, *y) | nibble(*n)?,
+        ("SKP", [V(x)]) => 0xE09E | xy(*x, 0),
+        ("SKNP", [V(x)]) => 0xE0A1 | xy(*x, 0),
+        ("LD", [I, Long(nnnn)]) => return Ok(vec![0xF000, long_address(*nnnn)?]),
+        ("PLANE", [Value(n)]) => 0xF001 | (nibble(*n)? << 8),
+        ("AUDIO", []) => 0xF002,
+        ("LD", [V(x), Dt]) => 0xF007 | xy(*x, 0),
+        ("LD", [V(x), K]) => 0xF00A | xy(*x, 0),
+        ("LD", [Dt, V(x)]) => 0xF015 | xy(*x, 0),
+        ("LD", [St, V(x)]) => 0xF018 | xy(*x, 0),
+        ("ADD", [I, V(x)]) => 0xF01E | xy(*x, 0),
+        ("LD", [F, V(x)]) => 0xF029 | xy(*x, 0),
+        ("LD", [Hf, V(x)]) => 0xF030 | xy(*x, 0),
+        ("LD", [B, V(x)]) => 0xF033 | xy(*x, 0),
+        ("PITCH", [V(x)]) => 0xF03A | xy(*x, 0),
+        ("LD", [IndirectI, V(x)]) => 0xF055 | xy(*x, 0),
+        ("LD", [V(x), IndirectI]) => 0xF065 | xy(*x, 0),
+        ("LD", [R, V(x)]) => 0xF075 | xy(*x, 0),
+        ("LD", [V(x), R]) => 0xF085 | xy(*x, 0),
+        _ if is_mnemonic(mnemonic) => return Err(ErrorKind::InvalidOperands(mnemonic.into())),
+        _ => return Err(ErrorKind::UnknownMnemonic(mnemonic.into())),
+    };
+    Ok(vec![opcode])
+}
+
+fn xy(x: u8, y: u8) -> u16 {
+    (x as u16) << 8 | (y as u16) << 4
+}
+
+fn ranged(value: i64, max: u32) -> Result<u16, ErrorKind> {
+    if (0..=max as i64).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(ErrorKind::OutOfRange { value, max })
+    }
+}
+
+fn nibble(value: i64) -> Result<u16, ErrorKind> {
+    ranged(value, 0xF)
+}
+
+// Negative bytes are stored as two's complement, so ADD VX, -1 works
+pub fn byte(value: i64) -> Result<u16, ErrorKind> {
+    if (-0x80..0).contains(&value) {
+        return Ok(value as u8 as u16);
+    }
+    ranged(value, 0xFF)
+}
+
+fn address(value: i64) -> Result<u16, ErrorKind> {
+    ranged(value, 0xFFF)
+}
+
+pub fn long_address(value: i64) -> Result<u16, ErrorKind> {
+    ranged(value, 0xFFFF)
+}
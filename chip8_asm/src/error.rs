@@ -0,0 +1,63 @@
+use std::fmt;
+
+// What went wrong on a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    // Not an instruction or directive
+    UnknownMnemonic(String),
+    // Known instruction that doesn't take these operands
+    InvalidOperands(String),
+    // Name that isn't a label, constant or alias
+    UnknownSymbol(String),
+    // Label or constant defined twice
+    DuplicateSymbol(String),
+    // Malformed number literal
+    InvalidNumber(String),
+    // Operand doesn't fit its field
+    OutOfRange { value: i64, max: u32 },
+    // Directive missing or with extra arguments
+    InvalidDirective(String),
+    // Expected V0 to VF or an alias of one
+    InvalidRegister(String),
+    // Included file couldn't be read
+    Include { path: String, error: String },
+    // Includes nested too deep, probably including themselves
+    IncludeTooDeep,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic `{}`", name),
+            ErrorKind::InvalidOperands(name) => write!(f, "invalid operands for `{}`", name),
+            ErrorKind::UnknownSymbol(name) => write!(f, "unknown symbol `{}`", name),
+            ErrorKind::DuplicateSymbol(name) => write!(f, "`{}` is already defined", name),
+            ErrorKind::InvalidNumber(text) => write!(f, "invalid number `{}`", text),
+            ErrorKind::OutOfRange { value, max } => {
+                write!(f, "value {:#X} out of range (max {:#X})", value, max)
+            }
+            ErrorKind::InvalidDirective(usage) => write!(f, "usage: {}", usage),
+            ErrorKind::InvalidRegister(text) => write!(f, "expected a register, found `{}`", text),
+            ErrorKind::Include { path, error } => {
+                write!(f, "unable to include {}: {}", path, error)
+            }
+            ErrorKind::IncludeTooDeep => write!(f, "includes nested too deep"),
+        }
+    }
+}
+
+// Error with the source line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.kind)
+    }
+}
+
+impl std::error::Error for AsmError {}
@@ -0,0 +1,431 @@
+use chip8_core::START_ADDRESS;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+mod encode;
+mod error;
+
+use encode::Operand;
+pub use error::{AsmError, ErrorKind};
+
+// Deepest chain of includes before giving up on a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// An assembled rom, ready for CPU::load_rom
+#[derive(Debug)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>, // Label addresses
+}
+
+impl Assembly {
+    // Lists labels by address, one `0x2A4 name` per line, for the desktop
+    // debugger's --symbols
+    pub fn symbol_map(&self) -> String {
+        let mut labels: Vec<(&u16, &String)> = self
+            .symbols
+            .iter()
+            .map(|(name, addr)| (addr, name))
+            .collect();
+        labels.sort();
+        labels
+            .iter()
+            .map(|(addr, name)| format!("{:#05X} {}\n", addr, name))
+            .collect()
+    }
+}
+
+// Assembles source text. Includes are looked up relative to the current
+// directory, name is only used in error messages.
+pub fn assemble(source: &str, name: &str) -> Result<Assembly, AsmError> {
+    let mut assembler = Assembler::new();
+    assembler.parse(source, name, Path::new(""), 0)?;
+    assembler.finish()
+}
+
+// Assembles a file, with includes relative to it
+pub fn assemble_file(path: &Path) -> Result<Assembly, AsmError> {
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        kind: ErrorKind::Include {
+            path: path.display().to_string(),
+            error: e.to_string(),
+        },
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut assembler = Assembler::new();
+    assembler.parse(&source, &path.display().to_string(), dir, 0)?;
+    assembler.finish()
+}
+
+// A line that emits bytes, kept from the first pass until every label is known
+struct Item {
+    file: usize, // Index into Assembler::files
+    line: usize,
+    address: u32,
+    kind: ItemKind,
+}
+
+enum ItemKind {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+// Result of reading one line
+enum Line {
+    Empty,
+    Item(u32, ItemKind),
+    Include(String),
+}
+
+enum Symbol {
+    Label(u16),
+    Const(i64),
+}
+
+// Two pass assembler. The first pass lays out every line and records labels,
+// the second encodes operands now that forward references can be resolved.
+struct Assembler {
+    files: Vec<String>,
+    items: Vec<Item>,
+    symbols: HashMap<String, Symbol>,
+    aliases: HashMap<String, u8>, // Register names set with :alias
+    address: u32,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            files: Vec::new(),
+            items: Vec::new(),
+            symbols: HashMap::new(),
+            aliases: HashMap::new(),
+            address: START_ADDRESS as u32,
+        }
+    }
+
+    fn parse(
+        &mut self,
+        source: &str,
+        name: &str,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let parsed = self
+                .parse_line(text)
+                .map_err(|kind| self.error(file, line, kind))?;
+            match parsed {
+                Line::Empty => (),
+                Line::Item(address, kind) => self.items.push(Item {
+                    file,
+                    line,
+                    address,
+                    kind,
+                }),
+                Line::Include(path) => {
+                    let path = dir.join(path);
+                    self.include(&path, depth)
+                        .map_err(|kind| self.error(file, line, kind))
+                        .and_then(|source| {
+                            let dir = path.parent().unwrap_or(Path::new(""));
+                            self.parse(&source, &path.display().to_string(), dir, depth + 1)
+                        })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Handles labels and directives, returning what is left for parse to do
+    fn parse_line(&mut self, text: &str) -> Result<Line, ErrorKind> {
+        let mut rest = strip_comment(text).trim();
+
+        // Any number of `name:` or Octo style `: name` labels
+        loop {
+            if let Some(after) = rest.strip_prefix(": ") {
+                let (name, after) = split_word(after.trim_start());
+                self.define(name, Symbol::Label(self.address as u16))?;
+                rest = after;
+            } else {
+                let (word, after) = split_word(rest);
+                match word.strip_suffix(':') {
+                    Some(name) if !name.is_empty() && !name.starts_with(':') => {
+                        self.define(name, Symbol::Label(self.address as u16))?;
+                        rest = after;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        if rest.is_empty() {
+            return Ok(Line::Empty);
+        }
+
+        let (word, args) = split_word(rest);
+        let words: Vec<&str> = args.split_whitespace().collect();
+        match word.to_ascii_lowercase().as_str() {
+            ":alias" => {
+                let [name, register] = words[..] else {
+                    return Err(ErrorKind::InvalidDirective(":alias NAME VX".into()));
+                };
+                let x = self.register(register)?;
+                self.aliases.insert(name.to_string(), x);
+            }
+            ":const" => {
+                let [name, value] = words[..] else {
+                    return Err(ErrorKind::InvalidDirective(":const NAME VALUE".into()));
+                };
+                let value = self.eval(value)?;
+                self.define(name, Symbol::Const(value))?;
+            }
+            ":org" | "org" => {
+                let [value] = words[..] else {
+                    return Err(ErrorKind::InvalidDirective("org ADDRESS".into()));
+                };
+                let value = self.eval(value)?;
+                if value < START_ADDRESS as i64 {
+                    return Err(ErrorKind::InvalidDirective(
+                        "org ADDRESS, from 0x200".into(),
+                    ));
+                }
+                self.address = encode::long_address(value)? as u32;
+            }
+            ":include" | "include" => {
+                let path = args.trim().trim_matches('"');
+                if path.is_empty() {
+                    return Err(ErrorKind::InvalidDirective("include \"FILE\"".into()));
+                }
+                return Ok(Line::Include(path.to_string()));
+            }
+            "db" | ":byte" => {
+                let values = list(args);
+                let size = values.len();
+                return self.emit(ItemKind::Bytes(values), size);
+            }
+            "dw" => {
+                let values = list(args);
+                let size = values.len() * 2;
+                return self.emit(ItemKind::Words(values), size);
+            }
+            _ => match split_word(args) {
+                // Classic `NAME EQU VALUE` constants
+                (op, value) if op.eq_ignore_ascii_case("equ") => {
+                    let value = self.eval(value)?;
+                    self.define(word, Symbol::Const(value))?;
+                }
+                _ => {
+                    let mnemonic = word.to_ascii_uppercase();
+                    let operands: Vec<String> = list(args)
+                        .iter()
+                        .map(|operand| self.substitute_aliases(operand))
+                        .collect();
+                    let size = encode::size(&mnemonic, &operands);
+                    let kind = ItemKind::Instruction { mnemonic, operands };
+                    return self.emit(kind, size as usize);
+                }
+            },
+        }
+        Ok(Line::Empty)
+    }
+
+    // Reserves size bytes at the current address for an item
+    fn emit(&mut self, kind: ItemKind, size: usize) -> Result<Line, ErrorKind> {
+        let address = self.address;
+        self.address += size as u32;
+        if self.address > 0x10000 {
+            return Err(ErrorKind::OutOfRange {
+                value: address as i64,
+                max: 0x10000u32.saturating_sub(size as u32),
+            });
+        }
+        Ok(Line::Item(address, kind))
+    }
+
+    // Reads an included file. Errors inside it carry their own location.
+    fn include(&self, path: &Path, depth: usize) -> Result<String, ErrorKind> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(ErrorKind::IncludeTooDeep);
+        }
+        fs::read_to_string(path).map_err(|e| ErrorKind::Include {
+            path: path.display().to_string(),
+            error: e.to_string(),
+        })
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), ErrorKind> {
+        if self.symbols.contains_key(name) {
+            return Err(ErrorKind::DuplicateSymbol(name.to_string()));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    // Aliases are swapped for registers as lines are read, so they can be
+    // redefined part way through a program
+    fn substitute_aliases(&self, operand: &str) -> String {
+        let words: Vec<String> = operand
+            .split_whitespace()
+            .map(|word| match self.aliases.get(word) {
+                Some(x) => format!("V{:X}", x),
+                None => word.to_string(),
+            })
+            .collect();
+        words.join(" ")
+    }
+
+    fn register(&self, text: &str) -> Result<u8, ErrorKind> {
+        encode::register(text)
+            .or_else(|| self.aliases.get(text).copied())
+            .ok_or(ErrorKind::InvalidRegister(text.to_string()))
+    }
+
+    // Evaluates terms joined by + and -, each a number or a symbol
+    fn eval(&self, text: &str) -> Result<i64, ErrorKind> {
+        let mut total = 0;
+        let mut sign = 1;
+        let mut start = 0;
+        for (index, c) in text.char_indices() {
+            // A leading minus belongs to the number
+            if (c == '+' || c == '-') && !text[start..index].trim().is_empty() {
+                total += sign * self.term(text[start..index].trim())?;
+                sign = if c == '-' { -1 } else { 1 };
+                start = index + 1;
+            }
+        }
+        Ok(total + sign * self.term(text[start..].trim())?)
+    }
+
+    fn term(&self, text: &str) -> Result<i64, ErrorKind> {
+        if let Some(value) = encode::number(text) {
+            return Ok(value);
+        }
+        if text.is_empty() || text.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(ErrorKind::InvalidNumber(text.to_string()));
+        }
+        match self.symbols.get(text) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Const(value)) => Ok(*value),
+            None => Err(ErrorKind::UnknownSymbol(text.to_string())),
+        }
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, ErrorKind> {
+        let operand = match text.to_ascii_uppercase().as_str() {
+            "I" => Operand::I,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::Hf,
+            "B" => Operand::B,
+            "[I]" => Operand::IndirectI,
+            "R" => Operand::R,
+            upper => match encode::register(upper) {
+                Some(x) => Operand::V(x),
+                None if upper.starts_with("LONG ") => Operand::Long(self.eval(&text[5..])?),
+                None => Operand::Value(self.eval(text)?),
+            },
+        };
+        Ok(operand)
+    }
+
+    // Second pass, encodes every item into the rom image
+    fn finish(self) -> Result<Assembly, AsmError> {
+        let mut rom = Vec::new();
+        for item in &self.items {
+            let bytes = self
+                .encode(&item.kind)
+                .map_err(|kind| self.error(item.file, item.line, kind))?;
+            let start = (item.address - START_ADDRESS as u32) as usize;
+            if rom.len() < start + bytes.len() {
+                rom.resize(start + bytes.len(), 0);
+            }
+            rom[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        let symbols = self
+            .symbols
+            .iter()
+            .filter_map(|(name, symbol)| match symbol {
+                Symbol::Label(addr) => Some((name.clone(), *addr)),
+                Symbol::Const(_) => None,
+            })
+            .collect();
+        Ok(Assembly { rom, symbols })
+    }
+
+    fn encode(&self, kind: &ItemKind) -> Result<Vec<u8>, ErrorKind> {
+        let mut bytes = Vec::new();
+        match kind {
+            ItemKind::Instruction { mnemonic, operands } => {
+                // SAVE and LOAD are written as a range, `SAVE V1 - V4`
+                let operands: Vec<&str> = match (mnemonic.as_str(), &operands[..]) {
+                    ("SAVE" | "LOAD", [range]) => range.split('-').map(str::trim).collect(),
+                    _ => operands.iter().map(String::as_str).collect(),
+                };
+                let operands = operands
+                    .iter()
+                    .map(|text| self.operand(text))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for word in encode::encode(mnemonic, &operands)? {
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            ItemKind::Bytes(values) => {
+                for value in values {
+                    bytes.push(encode::byte(self.eval(value)?)? as u8);
+                }
+            }
+            ItemKind::Words(values) => {
+                for value in values {
+                    let word = encode::long_address(self.eval(value)?)?;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn error(&self, file: usize, line: usize, kind: ErrorKind) -> AsmError {
+        AsmError {
+            file: self.files[file].clone(),
+            line,
+            kind,
+        }
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    match text.find([';', '#']) {
+        Some(index) => &text[..index],
+        None => text,
+    }
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+// Splits comma separated operands
+fn list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
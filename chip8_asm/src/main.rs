@@ -0,0 +1,72 @@
+use std::{env, fs, path::Path, process};
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            println!("Usage: chip8-asm [-o <rom>] [--symbols <file>] <source>");
+            process::exit(2);
+        }
+    };
+
+    let assembly = match chip8_asm::assemble_file(Path::new(&options.source)) {
+        Ok(assembly) => assembly,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = fs::write(&options.output, &assembly.rom) {
+        eprintln!("Unable to write {}: {}", options.output, e);
+        process::exit(1);
+    }
+    println!("Wrote {} bytes to {}", assembly.rom.len(), options.output);
+
+    if let Some(path) = options.symbols {
+        if let Err(e) = fs::write(&path, assembly.symbol_map()) {
+            eprintln!("Unable to write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+struct Options {
+    source: String,
+    output: String,
+    symbols: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("-o needs a file name")?.clone()),
+            "--symbols" => {
+                symbols = Some(args.next().ok_or("--symbols needs a file name")?.clone());
+            }
+            _ if source.is_none() => source = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let source: String = source.ok_or("Run with path to source as argument")?;
+    // Defaults to the source name with a .ch8 extension
+    let output = output.unwrap_or_else(|| {
+        Path::new(&source)
+            .with_extension("ch8")
+            .display()
+            .to_string()
+    });
+    Ok(Options {
+        source,
+        output,
+        symbols,
+    })
+}
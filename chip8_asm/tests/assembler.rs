@@ -0,0 +1,168 @@
+use chip8_asm::{assemble, assemble_file, ErrorKind};
+use chip8_core::{Disassembly, Symbols, START_ADDRESS};
+use std::{env, fs, path::PathBuf, process};
+
+fn rom(source: &str) -> Vec<u8> {
+    match assemble(source, "test.asm") {
+        Ok(assembly) => assembly.rom,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+// A directory of its own for tests that need files
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("chip8_asm_{}_{}", name, process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn labels() {
+    let source = "
+        start:
+            JP end      ; forward reference
+        : middle
+            CALL start
+        end: RET
+    ";
+    let assembly = assemble(source, "test.asm").unwrap();
+    assert_eq!(assembly.rom, [0x12, 0x04, 0x22, 0x00, 0x00, 0xEE]);
+    assert_eq!(assembly.symbols["start"], 0x200);
+    assert_eq!(assembly.symbols["middle"], 0x202);
+    assert_eq!(assembly.symbols["end"], 0x204);
+    assert_eq!(
+        assembly.symbol_map(),
+        "0x200 start\n0x202 middle\n0x204 end\n"
+    );
+
+    // The debugger reads the map back
+    let symbols = Symbols::parse(&assembly.symbol_map()).unwrap();
+    assert_eq!(symbols.address("middle"), Some(0x202));
+    assert_eq!(symbols.locate(0x205), Some(("end", 1)));
+}
+
+#[test]
+fn aliases() {
+    let source = "
+        :alias x V3
+        :alias y VA
+        LD x, 5
+        DRW x, y, 4
+        :alias x V4
+        ADD x, 1
+    ";
+    assert_eq!(rom(source), [0x63, 0x05, 0xD3, 0xA4, 0x74, 0x01]);
+}
+
+#[test]
+fn constants() {
+    let source = "
+        :const speed 4
+        WIDTH EQU 64
+        ADD V0, speed
+        LD V1, WIDTH - speed - 1
+        LD I, sprite + 2
+        sprite: db 0xFF, 0x81
+    ";
+    assert_eq!(
+        rom(source),
+        [0x70, 0x04, 0x61, 0x3B, 0xA2, 0x08, 0xFF, 0x81]
+    );
+}
+
+#[test]
+fn data_directives() {
+    let source = "
+        db 1, 0x02, $03, 0b100
+        :byte 255
+        dw 0x1234, here
+        here:
+    ";
+    assert_eq!(rom(source), [1, 2, 3, 4, 255, 0x12, 0x34, 0x02, 0x09]);
+}
+
+#[test]
+fn org_pads_the_gap() {
+    let source = "
+        CLS
+        org 0x208
+        sprite: db 0x3C
+    ";
+    let assembly = assemble(source, "test.asm").unwrap();
+    assert_eq!(assembly.rom, [0x00, 0xE0, 0, 0, 0, 0, 0, 0, 0x3C]);
+    assert_eq!(assembly.symbols["sprite"], 0x208);
+
+    let error = assemble("org 0x100", "test.asm").unwrap_err();
+    assert!(matches!(error.kind, ErrorKind::InvalidDirective(_)));
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let dir = scratch_dir("include");
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(
+        dir.join("main.asm"),
+        "CALL draw\nEXIT\ninclude \"lib/draw.asm\"\n",
+    )
+    .unwrap();
+    fs::write(dir.join("lib/draw.asm"), "draw: include \"ret.asm\"\n").unwrap();
+    fs::write(dir.join("lib/ret.asm"), "RET\n").unwrap();
+
+    let assembly = assemble_file(&dir.join("main.asm")).unwrap();
+    assert_eq!(assembly.rom, [0x22, 0x04, 0x00, 0xFD, 0x00, 0xEE]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn include_cycles_are_reported() {
+    let dir = scratch_dir("cycle");
+    fs::write(dir.join("a.asm"), "CLS\ninclude \"b.asm\"\n").unwrap();
+    fs::write(dir.join("b.asm"), "include \"a.asm\"\n").unwrap();
+
+    let error = assemble_file(&dir.join("a.asm")).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::IncludeTooDeep);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn errors_carry_their_line() {
+    let error = assemble("loop:\n  CLS\nloop: JP loop\n", "dup.asm").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::DuplicateSymbol("loop".into()));
+    assert_eq!(error.line, 3);
+    assert_eq!(error.to_string(), "dup.asm:3: `loop` is already defined");
+
+    let error = assemble(":const x 1\nx EQU 2\n", "dup.asm").unwrap_err();
+    assert_eq!(
+        (error.kind, error.line),
+        (ErrorKind::DuplicateSymbol("x".into()), 2)
+    );
+
+    // Unknown symbols are found in the second pass, still on the right line
+    let error = assemble("CLS\nCLS\nJP nowhere\n", "test.asm").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::UnknownSymbol("nowhere".into()));
+    assert_eq!(error.line, 3);
+
+    let error = assemble("CLS\nFLY V0\n", "test.asm").unwrap_err();
+    assert_eq!(error.kind, ErrorKind::UnknownMnemonic("FLY".into()));
+    assert_eq!(error.line, 2);
+}
+
+// The disassembler's listing of each rom, without its address and byte
+// columns, assembles back into the same rom
+#[test]
+fn disassembly_round_trip() {
+    let roms = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    for entry in fs::read_dir(roms).unwrap() {
+        let path = entry.unwrap().path();
+        let original = fs::read(&path).unwrap();
+        let listing = Disassembly::new(&original, START_ADDRESS).to_string();
+        let source: String = listing
+            .lines()
+            .map(|line| match line.strip_prefix("    ") {
+                Some(_) => format!("{}\n", &line[22..]),
+                None => format!("{}\n", line),
+            })
+            .collect();
+        assert_eq!(rom(&source), original, "{}", path.display());
+    }
+}
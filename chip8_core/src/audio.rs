@@ -0,0 +1,179 @@
+use crate::{Platform, AUDIO_PATTERN_SIZE, CPU};
+
+// Time taken to fade in or out, short enough to sound instant but long
+// enough to avoid clicks when the tone starts and stops
+const ENVELOPE_SECONDS: f32 = 0.005;
+const PATTERN_BITS: f32 = (AUDIO_PATTERN_SIZE * 8) as f32;
+
+// Generates the samples an audio device should play for a CPU. Plain
+// CHIP-8 sounds a square wave, XO-CHIP plays its 1-bit audio pattern.
+#[derive(Debug, Clone)]
+pub struct Synth {
+    pub frequency: f32, // Square wave frequency in Hz
+    pub volume: f32,    // 0 to 1
+    pub muted: bool,
+    active: bool,
+    pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>, // Pattern and its bit rate
+    phase: f32, // Position in the current wave, or bit in the pattern
+    gain: f32,  // Envelope level, 0 to 1
+}
+
+impl Synth {
+    pub fn new(frequency: f32, volume: f32) -> Synth {
+        Synth {
+            frequency,
+            volume,
+            muted: false,
+            active: false,
+            pattern: None,
+            phase: 0.,
+            gain: 0.,
+        }
+    }
+
+    // Copies the sound state of the CPU, call once per frame
+    pub fn update(&mut self, cpu: &CPU) {
+        self.active = cpu.is_sound_active();
+        self.pattern = match cpu.platform() {
+            Platform::XoChip => Some((*cpu.audio_pattern(), cpu.audio_rate())),
+            _ => None,
+        };
+    }
+
+    // Fades out, for when emulation is paused with the sound timer running
+    pub fn stop(&mut self) {
+        self.active = false;
+    }
+
+    // Fills out with mono samples between -1 and 1
+    pub fn fill(&mut self, out: &mut [f32], sample_rate: u32) {
+        let sample_rate = sample_rate as f32;
+        let target = if self.active && !self.muted { 1. } else { 0. };
+        let step = 1. / (ENVELOPE_SECONDS * sample_rate);
+
+        for sample in out.iter_mut() {
+            if self.gain < target {
+                self.gain = (self.gain + step).min(target);
+            } else if self.gain > target {
+                self.gain = (self.gain - step).max(target);
+            }
+
+            let high = match &self.pattern {
+                Some((pattern, rate)) => {
+                    let bit = self.phase as usize;
+                    self.phase = (self.phase + rate / sample_rate) % PATTERN_BITS;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => {
+                    let high = self.phase < 0.5;
+                    self.phase = (self.phase + self.frequency / sample_rate) % 1.;
+                    high
+                }
+            };
+            let level = if high { 1. } else { -1. };
+            *sample = level * self.gain * self.volume;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+
+    const RATE: u32 = 48_000;
+
+    fn sounding(platform: Platform) -> CPU {
+        let mut cpu = CPU::new(platform, Quirks::default(), Some(0));
+        cpu.sound_timer = 10;
+        cpu
+    }
+
+    #[test]
+    fn starts_silent() {
+        let mut synth = Synth::new(440., 1.);
+        let mut out = [1.; 256];
+        synth.fill(&mut out, RATE);
+        assert!(out.iter().all(|sample| *sample == 0.));
+
+        // The timer running out stays silent too
+        synth.update(&CPU::new(Platform::Chip8, Quirks::default(), Some(0)));
+        synth.fill(&mut out, RATE);
+        assert!(out.iter().all(|sample| *sample == 0.));
+    }
+
+    #[test]
+    fn fades_in_over_five_milliseconds() {
+        let mut synth = Synth::new(440., 0.5);
+        synth.update(&sounding(Platform::Chip8));
+        let mut out = [0.; 480];
+        synth.fill(&mut out, RATE);
+
+        // 240 samples at 48 kHz, rising a step each sample
+        let levels: Vec<f32> = out.iter().map(|sample| sample.abs()).collect();
+        assert!((levels[0] - 0.5 / 240.).abs() < 1e-6);
+        assert!(levels.windows(2).take(238).all(|pair| pair[1] > pair[0]));
+        assert!(levels[238] < 0.5);
+        assert!(levels[241..].iter().all(|level| *level == 0.5));
+
+        // Then out again
+        synth.stop();
+        synth.fill(&mut out, RATE);
+        assert!(out[0].abs() < 0.5);
+        assert!(out[241..].iter().all(|sample| *sample == 0.));
+    }
+
+    #[test]
+    fn muting_fades_out() {
+        let mut synth = Synth::new(440., 1.);
+        synth.update(&sounding(Platform::Chip8));
+        let mut out = [0.; 480];
+        synth.fill(&mut out, RATE);
+        assert_eq!(out[479].abs(), 1.);
+
+        synth.muted = true;
+        synth.fill(&mut out, RATE);
+        assert!(out[241..].iter().all(|sample| *sample == 0.));
+        synth.fill(&mut out, RATE);
+        assert!(out.iter().all(|sample| *sample == 0.));
+    }
+
+    #[test]
+    fn plays_a_square_wave() {
+        let mut synth = Synth::new(1000., 1.);
+        synth.update(&sounding(Platform::Chip8));
+        let mut out = [0.; 1000];
+        synth.fill(&mut out, 8000);
+        // 8 samples a cycle, high then low
+        let signs: Vec<bool> = out[800..816].iter().map(|sample| *sample > 0.).collect();
+        assert_eq!(
+            signs,
+            [[true; 4], [false; 4], [true; 4], [false; 4]].concat()
+        );
+    }
+
+    #[test]
+    fn plays_xo_chip_patterns_high_bit_first() {
+        let mut cpu = sounding(Platform::XoChip);
+        cpu.audio_pattern[0] = 0b1100_1010;
+        cpu.audio_pattern[AUDIO_PATTERN_SIZE - 1] = 0b0000_0001;
+        let mut synth = Synth::new(440., 1.);
+        synth.update(&cpu);
+
+        // At the default pitch the pattern plays at 4000 bits a second,
+        // so one bit per sample. The second time round is past the fade in.
+        let mut out = [0.; 256];
+        synth.fill(&mut out, 4000);
+        let bits: Vec<bool> = out[128..].iter().map(|sample| *sample > 0.).collect();
+        assert_eq!(
+            bits[..8],
+            [true, true, false, false, true, false, true, false]
+        );
+        assert!(bits[8..127].iter().all(|bit| !bit));
+        assert!(bits[127]);
+
+        // Plain CHIP-8 ignores the pattern
+        synth.update(&sounding(Platform::Chip8));
+        assert!(synth.pattern.is_none());
+    }
+}
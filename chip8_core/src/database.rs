@@ -0,0 +1,336 @@
+use crate::json::Json;
+use crate::{Palette, Platform, Quirks, Rgb, Rom, TIMER_HZ};
+use std::collections::HashMap;
+
+// Database files built in, in the format of the community chip-8-database
+// (https://github.com/chip-8/chip-8-database), covering the roms in roms/
+const PROGRAMS: &str = include_str!("../database/programs.json");
+const HASHES: &str = include_str!("../database/sha1-hashes.json");
+
+// What the database knows about one rom. Settings it doesn't give are
+// None, so front ends can fall back to their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub description: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub ips: Option<u32>,
+    pub load_address: Option<u16>,
+    pub palette: Option<Palette>,
+    pub keys: Vec<(String, u8)>, // What keypad keys do, like ("left", 4)
+}
+
+// Roms by lowercase hex SHA-1
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    // The database that comes built in
+    pub fn builtin() -> RomDatabase {
+        RomDatabase::from_json(PROGRAMS, HASHES).expect("built in rom database is invalid")
+    }
+
+    // Reads programs.json and sha1-hashes.json from chip-8-database. Roms
+    // for platforms that can't be emulated are left out.
+    pub fn from_json(programs: &str, hashes: &str) -> Result<RomDatabase, String> {
+        let programs = Json::parse(programs).map_err(|e| format!("programs: {}", e))?;
+        let hashes = Json::parse(hashes).map_err(|e| format!("hashes: {}", e))?;
+        let programs = programs.as_array();
+
+        let mut roms = HashMap::new();
+        for (hash, index) in hashes.members() {
+            let program = index
+                .as_f64()
+                .and_then(|index| programs.get(index as usize))
+                .ok_or(format!("hashes: no program for {}", hash))?;
+            let Some(rom) = program.get("roms").and_then(|roms| roms.get(hash)) else {
+                continue;
+            };
+            if let Some(info) = rom_info(program, rom) {
+                roms.insert(hash.to_lowercase(), info);
+            }
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn lookup(&self, rom: &Rom) -> Option<&RomInfo> {
+        self.roms.get(&rom.sha1_hex())
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+fn rom_info(program: &Json, rom: &Json) -> Option<RomInfo> {
+    // The first platform listed that can be emulated, adjusted by any quirks
+    // the rom needs on it
+    let platforms = rom.get("platforms").map_or(&[][..], Json::as_array);
+    let (platform, quirks) = if platforms.is_empty() {
+        (None, None)
+    } else {
+        let (id, platform, quirks) = platforms.iter().find_map(|id| {
+            let id = id.as_str()?;
+            let (platform, quirks) = platform_preset(id)?;
+            Some((id, platform, quirks))
+        })?;
+        let quirks = match rom.get("quirkyPlatforms").and_then(|q| q.get(id)) {
+            Some(overrides) => apply_quirks(quirks, overrides),
+            None => quirks,
+        };
+        (Some(platform), Some(quirks))
+    };
+
+    // Either level can give the details, the rom's own win
+    let text = |key: &str| {
+        rom.get(key)
+            .or(program.get(key))
+            .and_then(Json::as_str)
+            .map(str::to_string)
+    };
+    let authors = rom
+        .get("authors")
+        .or(program.get("authors"))
+        .map_or(&[][..], Json::as_array)
+        .iter()
+        .filter_map(|author| author.as_str().map(str::to_string))
+        .collect();
+
+    let palette = rom
+        .get("colors")
+        .and_then(|colors| colors.get("pixels"))
+        .and_then(|pixels| {
+            let colors = pixels
+                .as_array()
+                .iter()
+                .map(|pixel| pixel.as_str().and_then(Rgb::from_hex))
+                .collect::<Option<Vec<_>>>()?;
+            Palette::from_colors(&colors)
+        });
+
+    let keys = rom
+        .get("keys")
+        .map_or(&[][..], Json::members)
+        .iter()
+        .filter_map(|(action, key)| Some((action.clone(), key.as_f64()? as u8 & 0xF)))
+        .collect();
+
+    Some(RomInfo {
+        title: text("title").unwrap_or_default(),
+        authors,
+        release: text("release"),
+        description: text("description"),
+        platform,
+        quirks,
+        // Tick rate is instructions per frame
+        ips: rom
+            .get("tickrate")
+            .and_then(Json::as_f64)
+            .map(|rate| rate as u32 * TIMER_HZ),
+        load_address: rom
+            .get("startAddress")
+            .and_then(Json::as_f64)
+            .map(|addr| addr as u16),
+        palette,
+        keys,
+    })
+}
+
+// The platform and quirks to emulate a database platform id with
+fn platform_preset(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::vip())),
+        "modernChip8" => Some((
+            Platform::Chip8,
+            Quirks {
+                logic_resets_vf: false,
+                ..Quirks::vip()
+            },
+        )),
+        "chip48" => Some((Platform::Chip8, Quirks::chip48())),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::schip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xochip())),
+        _ => None,
+    }
+}
+
+// Quirk names from the database's quirks.json. Its vblank quirk is what
+// VIP timing does, so is left to the front end.
+fn apply_quirks(mut quirks: Quirks, overrides: &Json) -> Quirks {
+    for (name, value) in overrides.members() {
+        let Some(value) = value.as_bool() else {
+            continue;
+        };
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !value,
+            "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !value,
+            "memoryIncrementByX" => quirks.load_store_increments_i_by_x = value,
+            "wrap" => quirks.clip_sprites = !value,
+            "jump" => quirks.jump_uses_vx = value,
+            "logic" => quirks.logic_resets_vf = value,
+            _ => (),
+        }
+    }
+    quirks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // One program with a rom for each platform id, under made up hashes
+    const PROGRAMS: &str = r##"[
+      {
+        "title": "Test",
+        "authors": ["Someone"],
+        "release": "2024",
+        "roms": {
+          "aa": {"platforms": ["originalChip8"]},
+          "bb": {"platforms": ["modernChip8"]},
+          "cc": {"platforms": ["chip48"], "tickrate": 30},
+          "dd": {"platforms": ["superchip1"]},
+          "ee": {"platforms": ["xochip"], "startAddress": 768},
+          "ff": {"platforms": ["megachip8"]},
+          "11": {"platforms": ["megachip8", "superchip"]},
+          "22": {
+            "platforms": ["originalChip8"],
+            "quirkyPlatforms": {
+              "originalChip8": {
+                "shift": true, "memoryLeaveIUnchanged": true, "memoryIncrementByX": true, "vblank": false
+              },
+              "chip48": {"jump": false}
+            }
+          },
+          "33": {
+            "title": "Own title",
+            "authors": ["Someone else"],
+            "colors": {"pixels": ["#000000", "#ff8800"]},
+            "keys": {"left": 4, "right": 6, "a": 21}
+          }
+        }
+      }
+    ]"##;
+    const HASHES: &str = r#"{
+      "aa": 0, "bb": 0, "cc": 0, "dd": 0, "ee": 0, "ff": 0, "11": 0, "22": 0, "33": 0
+    }"#;
+
+    fn info(hash: &str) -> Option<RomInfo> {
+        let database = RomDatabase::from_json(PROGRAMS, HASHES).unwrap();
+        database.roms.get(hash).cloned()
+    }
+
+    fn platform(hash: &str) -> (Option<Platform>, Option<Quirks>) {
+        let info = info(hash).unwrap();
+        (info.platform, info.quirks)
+    }
+
+    #[test]
+    fn maps_platform_ids() {
+        let vip = Quirks::vip();
+        assert_eq!(platform("aa"), (Some(Platform::Chip8), Some(vip)));
+        let modern = Quirks {
+            logic_resets_vf: false,
+            ..vip
+        };
+        assert_eq!(platform("bb"), (Some(Platform::Chip8), Some(modern)));
+        assert_eq!(
+            platform("cc"),
+            (Some(Platform::Chip8), Some(Quirks::chip48()))
+        );
+        assert_eq!(
+            platform("dd"),
+            (Some(Platform::SuperChip), Some(Quirks::schip()))
+        );
+        assert_eq!(
+            platform("ee"),
+            (Some(Platform::XoChip), Some(Quirks::xochip()))
+        );
+        assert_eq!(platform("33"), (None, None));
+    }
+
+    #[test]
+    fn skips_platforms_that_cant_be_emulated() {
+        assert_eq!(info("ff"), None);
+        // Falls through to the next platform listed
+        assert_eq!(
+            platform("11"),
+            (Some(Platform::SuperChip), Some(Quirks::schip()))
+        );
+        assert_eq!(RomDatabase::from_json(PROGRAMS, HASHES).unwrap().len(), 8);
+    }
+
+    #[test]
+    fn applies_quirk_overrides_for_the_chosen_platform() {
+        let quirks = Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: true,
+            ..Quirks::vip()
+        };
+        assert_eq!(platform("22"), (Some(Platform::Chip8), Some(quirks)));
+    }
+
+    #[test]
+    fn reads_rom_details() {
+        let info = info("aa").unwrap();
+        assert_eq!(info.title, "Test");
+        assert_eq!(info.authors, ["Someone"]);
+        assert_eq!(info.release.as_deref(), Some("2024"));
+        assert_eq!(
+            (info.ips, info.load_address, info.palette),
+            (None, None, None)
+        );
+
+        assert_eq!(self::info("cc").unwrap().ips, Some(30 * TIMER_HZ));
+        assert_eq!(self::info("ee").unwrap().load_address, Some(0x300));
+
+        // The rom's own details win over the program's
+        let info = self::info("33").unwrap();
+        assert_eq!(info.title, "Own title");
+        assert_eq!(info.authors, ["Someone else"]);
+        let palette = Palette::two_color(Rgb::new(0, 0, 0), Rgb::new(0xff, 0x88, 0));
+        assert_eq!(info.palette, Some(palette));
+        let mut keys = info.keys;
+        keys.sort();
+        assert_eq!(
+            keys,
+            [("a".into(), 5), ("left".into(), 4), ("right".into(), 6)]
+        );
+    }
+
+    #[test]
+    fn rejects_hashes_without_programs() {
+        assert!(RomDatabase::from_json(PROGRAMS, r#"{"aa": 1}"#).is_err());
+        assert!(RomDatabase::from_json("[", HASHES).is_err());
+    }
+
+    #[test]
+    fn builtin_knows_every_rom() {
+        let database = RomDatabase::builtin();
+        let roms = concat!(env!("CARGO_MANIFEST_DIR"), "/../roms");
+        let mut count = 0;
+        for entry in fs::read_dir(roms).unwrap() {
+            let path = entry.unwrap().path();
+            let rom = Rom::new(&fs::read(&path).unwrap());
+            let info = database
+                .lookup(&rom)
+                .unwrap_or_else(|| panic!("{} isn't in the database", path.display()));
+            assert!(!info.title.is_empty());
+            assert!(info.platform.is_some() && info.ips.is_some());
+            // Database platforms are what the rom needs at least
+            assert!(info.platform.unwrap() >= rom.guess_platform());
+            count += 1;
+        }
+        assert_eq!(database.len(), count);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::{Chip8Error, StepOutcome, CPU};
+use std::collections::BTreeSet;
+
+// Kind of memory access seen by a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Registers that conditional breakpoints can test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Breaks when a register comparison becomes true
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+// Why execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // About to execute an instruction with a breakpoint
+    Breakpoint { pc: u16 },
+    // The instruction at pc accessed a watched address
+    Watchpoint { pc: u16, addr: u16, access: Access },
+    // A conditional breakpoint became true
+    Condition { index: usize },
+    // A step, step over or step out finished
+    Step,
+    // The program executed 00FD
+    Exit,
+    // The instruction at pc raised an error
+    Error { pc: u16, error: Chip8Error },
+}
+
+// Where a step over or step out is heading
+#[derive(Debug, Clone, Copy)]
+enum Goal {
+    // Reach pc with the stack back at depth
+    Return { pc: u16, depth: u16 },
+    // Return out of the subroutine at depth
+    Out { depth: u16 },
+}
+
+// Wraps a CPU with breakpoints, watchpoints and stepping
+pub struct Debugger {
+    cpu: CPU,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, Access)>,
+    conditions: Vec<Condition>,
+    goal: Option<Goal>,
+    resume_from: Option<u16>, // Breakpoint last stopped at, skipped once when resuming
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU) -> Debugger {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            goal: None,
+            resume_from: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn into_inner(self) -> CPU {
+        self.cpu
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access) {
+        if !self.watchpoints.contains(&(addr, access)) {
+            self.watchpoints.push((addr, access));
+        }
+    }
+
+    // Removes read and write watchpoints on an address
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|(watched, _)| *watched != addr);
+        self.watchpoints.len() != before
+    }
+
+    pub fn watchpoints(&self) -> &[(u16, Access)] {
+        &self.watchpoints
+    }
+
+    // Adds a conditional breakpoint, returning its index
+    pub fn add_condition(&mut self, condition: Condition) -> usize {
+        self.conditions.push(condition);
+        self.conditions.len() - 1
+    }
+
+    pub fn remove_condition(&mut self, index: usize) -> bool {
+        if index < self.conditions.len() {
+            self.conditions.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    // Current value of a register
+    pub fn register(&self, register: Register) -> u16 {
+        let cpu = &self.cpu;
+        match register {
+            Register::V(x) => cpu.v_registers[x as usize & 0xF] as u16,
+            Register::I => cpu.i_register,
+            Register::Pc => cpu.program_counter,
+            Register::Sp => cpu.stack_pointer,
+            Register::Dt => cpu.delay_timer as u16,
+            Register::St => cpu.sound_timer as u16,
+        }
+    }
+
+    // Executes a single instruction
+    pub fn step(&mut self) -> StopReason {
+        self.goal = None;
+        self.resume_from = None;
+        self.execute().unwrap_or(StopReason::Step)
+    }
+
+    // Executes one instruction, running a whole subroutine if it is a call.
+    // Returns None if max_steps run out first, run continues from there.
+    pub fn step_over(&mut self, max_steps: usize) -> Option<StopReason> {
+        let pc = self.cpu.program_counter;
+        let is_call = matches!(self.cpu.peek(pc), Ok(opcode) if opcode & 0xF000 == 0x2000);
+        if !is_call {
+            return Some(self.step());
+        }
+
+        self.goal = Some(Goal::Return {
+            pc: pc.wrapping_add(2),
+            depth: self.cpu.stack_pointer,
+        });
+        self.run(max_steps)
+    }
+
+    // Runs until the current subroutine returns
+    pub fn step_out(&mut self, max_steps: usize) -> Option<StopReason> {
+        if self.cpu.stack_pointer == 0 {
+            return Some(self.step());
+        }
+
+        self.goal = Some(Goal::Out {
+            depth: self.cpu.stack_pointer,
+        });
+        self.run(max_steps)
+    }
+
+    // Runs until something stops execution, max_steps instructions have run
+    // or VIP timing has used up the frame
+    pub fn run(&mut self, max_steps: usize) -> Option<StopReason> {
+        for _ in 0..max_steps {
+            if self.cpu.is_waiting_for_frame() {
+                break;
+            }
+
+            // Don't stop on the breakpoint execution is resuming from
+            let pc = self.cpu.program_counter;
+            if self.resume_from.take() != Some(pc) && self.breakpoints.contains(&pc) {
+                self.goal = None;
+                self.resume_from = Some(pc);
+                return Some(StopReason::Breakpoint { pc });
+            }
+
+            if let Some(reason) = self.execute() {
+                self.goal = None;
+                return Some(reason);
+            }
+
+            if self.goal_reached() {
+                self.goal = None;
+                return Some(StopReason::Step);
+            }
+        }
+        None
+    }
+
+    // Executes one instruction, checking watchpoints and conditions
+    fn execute(&mut self) -> Option<StopReason> {
+        let pc = self.cpu.program_counter;
+        let before: Vec<bool> = if self.conditions.is_empty() {
+            Vec::new()
+        } else {
+            self.conditions.iter().map(|c| self.test(c)).collect()
+        };
+
+        if !self.watchpoints.is_empty() {
+            self.cpu.access_log = Some(Vec::new());
+        }
+        let result = self.cpu.tick();
+        let accesses = self.cpu.access_log.take().unwrap_or_default();
+
+        match result {
+            Ok(StepOutcome::Exit) => return Some(StopReason::Exit),
+            Err(error) => return Some(StopReason::Error { pc, error }),
+            Ok(_) => (),
+        }
+
+        for (addr, access) in accesses {
+            if self.watchpoints.contains(&(addr, access)) {
+                return Some(StopReason::Watchpoint { pc, addr, access });
+            }
+        }
+
+        // Conditions only fire when they change from false to true
+        for (index, condition) in self.conditions.iter().enumerate() {
+            if !before[index] && self.test(condition) {
+                return Some(StopReason::Condition { index });
+            }
+        }
+        None
+    }
+
+    fn test(&self, condition: &Condition) -> bool {
+        let value = self.register(condition.register);
+        match condition.comparison {
+            Comparison::Eq => value == condition.value,
+            Comparison::Ne => value != condition.value,
+            Comparison::Lt => value < condition.value,
+            Comparison::Le => value <= condition.value,
+            Comparison::Gt => value > condition.value,
+            Comparison::Ge => value >= condition.value,
+        }
+    }
+
+    fn goal_reached(&self) -> bool {
+        match self.goal {
+            Some(Goal::Return { pc, depth }) => {
+                self.cpu.program_counter == pc && self.cpu.stack_pointer <= depth
+            }
+            Some(Goal::Out { depth }) => self.cpu.stack_pointer < depth,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks, Rom};
+
+    // Calls a subroutine that stores V0 at 0x300, then loops forever
+    const PROGRAM: [u8; 14] = [
+        0x60, 0x05, // 200: LD V0, 0x05
+        0x22, 0x08, // 202: CALL 0x208
+        0x61, 0x01, // 204: LD V1, 0x01
+        0x12, 0x06, // 206: JP 0x206
+        0xA3, 0x00, // 208: LD I, 0x300
+        0xF0, 0x55, // 20A: LD [I], V0
+        0x00, 0xEE, // 20C: RET
+    ];
+
+    fn debugger() -> Debugger {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::chip48(), Some(0));
+        cpu.load_rom(&Rom::new(&PROGRAM)).unwrap();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn step_runs_one_instruction() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.register(Register::Pc), 0x202);
+        assert_eq!(debugger.register(Register::V(0)), 5);
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.register(Register::Pc), 0x208);
+        assert_eq!(debugger.register(Register::Sp), 1);
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        let mut debugger = debugger();
+        debugger.step();
+        assert_eq!(debugger.step_over(100), Some(StopReason::Step));
+        assert_eq!(debugger.register(Register::Pc), 0x204);
+        assert_eq!(debugger.register(Register::Sp), 0);
+        assert_eq!(debugger.register(Register::I), 0x300);
+    }
+
+    #[test]
+    fn step_out_returns_from_the_subroutine() {
+        let mut debugger = debugger();
+        debugger.step();
+        debugger.step();
+        assert_eq!(debugger.step_out(100), Some(StopReason::Step));
+        assert_eq!(debugger.register(Register::Pc), 0x204);
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction_and_resume_past_it() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint(0x20A);
+        assert_eq!(
+            debugger.run(100),
+            Some(StopReason::Breakpoint { pc: 0x20A })
+        );
+        assert_eq!(debugger.register(Register::Pc), 0x20A);
+        assert_eq!(debugger.cpu().ram[0x300], 0);
+
+        // Resuming doesn't stop on the same breakpoint again
+        assert_eq!(debugger.run(3), None);
+        assert_eq!(debugger.cpu().ram[0x300], 5);
+
+        assert!(debugger.remove_breakpoint(0x20A));
+        assert!(!debugger.remove_breakpoint(0x20A));
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        let mut debugger = debugger();
+        debugger.add_watchpoint(0x300, Access::Read);
+        debugger.add_watchpoint(0x300, Access::Write);
+        assert_eq!(
+            debugger.run(100),
+            Some(StopReason::Watchpoint {
+                pc: 0x20A,
+                addr: 0x300,
+                access: Access::Write
+            })
+        );
+        assert_eq!(debugger.register(Register::Pc), 0x20C);
+
+        assert!(debugger.remove_watchpoint(0x300));
+        assert!(debugger.watchpoints().is_empty());
+        assert_eq!(debugger.run(100), None);
+    }
+
+    #[test]
+    fn conditions_fire_when_they_become_true() {
+        let mut debugger = debugger();
+        let index = debugger.add_condition(Condition {
+            register: Register::V(1),
+            comparison: Comparison::Eq,
+            value: 1,
+        });
+        assert_eq!(debugger.run(100), Some(StopReason::Condition { index }));
+        assert_eq!(debugger.register(Register::Pc), 0x206);
+
+        // Still true, so it doesn't fire again
+        assert_eq!(debugger.run(100), None);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::Instruction;
+use std::{collections::BTreeSet, fmt};
+
+// Data bytes shown per DB line
+const BYTES_PER_ROW: usize = 8;
+
+// Splits a rom into code and data by following every path execution can
+// take from the entry point. Anything never reached is assumed to be data,
+// usually sprites.
+pub struct Disassembly {
+    origin: u16,
+    rom: Vec<u8>,
+    starts: BTreeSet<u16>, // Addresses of reachable instructions
+    labels: BTreeSet<u16>, // Jump and call targets inside the rom
+}
+
+impl Disassembly {
+    // Traces rom as loaded at origin, starting execution there
+    pub fn new(rom: &[u8], origin: u16) -> Disassembly {
+        let mut disassembly = Disassembly {
+            origin,
+            rom: rom.to_vec(),
+            starts: BTreeSet::new(),
+            labels: BTreeSet::new(),
+        };
+        disassembly.trace();
+        disassembly
+    }
+
+    pub fn origin(&self) -> u16 {
+        self.origin
+    }
+
+    // Whether an instruction was found starting at addr
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.starts.contains(&addr)
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = u16> + '_ {
+        self.labels.iter().copied()
+    }
+
+    // Every instruction found, in address order
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, Instruction)> + '_ {
+        self.starts.iter().map(|addr| {
+            let opcode = self.opcode(*addr).unwrap_or_default();
+            (*addr, Instruction::decode(opcode))
+        })
+    }
+
+    fn end(&self) -> u32 {
+        self.origin as u32 + self.rom.len() as u32
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.origin && (addr as u32) < self.end()
+    }
+
+    fn opcode(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(self.origin)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn trace(&mut self) {
+        let mut pending = vec![self.origin];
+        while let Some(addr) = pending.pop() {
+            if self.starts.contains(&addr) {
+                continue;
+            }
+            let Some(opcode) = self.opcode(addr) else {
+                continue;
+            };
+            let instruction = Instruction::decode(opcode);
+            if let Instruction::Unknown(_) = instruction {
+                continue;
+            }
+            self.starts.insert(addr);
+
+            let next = addr.wrapping_add(instruction.size());
+            match instruction {
+                Instruction::Jump(target) => self.branch(target, &mut pending),
+                Instruction::Call(target) => {
+                    self.branch(target, &mut pending);
+                    pending.push(next);
+                }
+                // With V0 zero this lands on the start of the jump table
+                Instruction::JumpOffset(_, target) => self.branch(target, &mut pending),
+                Instruction::Ret | Instruction::Exit => (),
+                _ if instruction.is_skip() => {
+                    pending.push(next);
+                    let skipped = self.opcode(next).map(Instruction::decode);
+                    let size = skipped.map_or(2, |skipped| skipped.size());
+                    pending.push(next.wrapping_add(size));
+                }
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    fn branch(&mut self, target: u16, pending: &mut Vec<u16>) {
+        if self.contains(target) {
+            self.labels.insert(target);
+            pending.push(target);
+        }
+    }
+
+    // Formats an instruction, naming its target if it has a label and
+    // filling in the address that follows F000
+    fn format_instruction(&self, pc: u16, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::LoadILong => match self.opcode(pc.wrapping_add(2)) {
+                Some(addr) => format!("LD I, LONG {:#06X}", addr),
+                None => instruction.to_string(),
+            },
+            Instruction::Jump(target) if self.labels.contains(&target) => {
+                format!("JP {}", label(target))
+            }
+            Instruction::Call(target) if self.labels.contains(&target) => {
+                format!("CALL {}", label(target))
+            }
+            Instruction::JumpOffset(_, target) if self.labels.contains(&target) => {
+                format!("JP V0, {}", label(target))
+            }
+            _ => instruction.to_string(),
+        }
+    }
+}
+
+fn label(addr: u16) -> String {
+    format!("L{:03X}", addr)
+}
+
+// Writes an assembly listing with the address and raw bytes of every line
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut addr = self.origin as u32;
+        while addr < self.end() {
+            let pc = addr as u16;
+            if self.labels.contains(&pc) {
+                writeln!(f, "{}:", label(pc))?;
+            }
+
+            let offset = (addr - self.origin as u32) as usize;
+            if self.starts.contains(&pc) {
+                let instruction = Instruction::decode(self.opcode(pc).unwrap_or_default());
+                let size = (instruction.size() as usize).min(self.rom.len() - offset);
+                let bytes = &self.rom[offset..offset + size];
+                writeln!(
+                    f,
+                    "    {:#05X}  {:<10} {}",
+                    pc,
+                    hex(bytes),
+                    self.format_instruction(pc, instruction)
+                )?;
+                addr += size as u32;
+                continue;
+            }
+
+            // Data runs until the next instruction, label or full row
+            let mut len = 1;
+            while len < BYTES_PER_ROW && offset + len < self.rom.len() {
+                let next = pc.wrapping_add(len as u16);
+                if self.starts.contains(&next) || self.labels.contains(&next) {
+                    break;
+                }
+                len += 1;
+            }
+            let bytes = &self.rom[offset..offset + len];
+            let values: Vec<String> = bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+            writeln!(f, "    {:#05X}  {:<10} DB {}", pc, "", values.join(", "))?;
+            addr += len as u32;
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+
+// Errors that can be raised while loading or running a rom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    // Opcode not in the instruction set
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // Call with a full stack
+    StackOverflow,
+    // Return with an empty stack
+    StackUnderflow,
+    // Program counter left ram
+    PcOutOfBounds { pc: u16 },
+    // Read or write outside of ram
+    MemoryOutOfBounds { addr: usize },
+    // Key instruction with VX > 0xF
+    InvalidKey { key: u8 },
+    // Rom does not fit in ram
+    RomTooLarge { size: usize, max: usize },
+    // Rom would be loaded over the interpreter or outside of ram
+    InvalidLoadAddress { addr: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "stack underflow"),
+            Chip8Error::PcOutOfBounds { pc } => {
+                write!(f, "program counter out of bounds: {:#05X}", pc)
+            }
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds: {:#05X}", addr)
+            }
+            Chip8Error::InvalidKey { key } => write!(f, "invalid key: {:#04X}", key),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "rom is too large: {} bytes (max {})", size, max)
+            }
+            Chip8Error::InvalidLoadAddress { addr } => {
+                write!(f, "invalid load address: {:#05X}", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
@@ -0,0 +1,682 @@
+use crate::{Access, Debugger, StopReason, CPU, STACK_SIZE};
+use std::collections::BTreeSet;
+
+// Register names in the order gdb numbers them. PC and I are two bytes,
+// the rest one, and values are sent big endian like CHIP-8 stores them.
+const REGISTERS: [&str; 21] = [
+    "pc", "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd", "ve",
+    "vf", "i", "sp", "dt", "st",
+];
+
+// Largest packet gdb may send, replies are kept under it too
+const PACKET_SIZE: usize = 0x1000;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Speaks the gdb remote serial protocol for a Debugger. Bytes from gdb go
+// in through receive and whatever comes back is sent to it, so the
+// connection itself is up to the front end.
+pub struct GdbStub {
+    input: Vec<u8>,      // Received bytes not yet handled
+    last_reply: Vec<u8>, // Sent again if gdb asks for it with -
+    last_stop: String,   // Answer to ?
+    no_ack: bool,        // Packets aren't acknowledged after QStartNoAckMode
+    running: bool,       // Continued and not stopped since
+    killed: bool,        // gdb asked for the program to be killed
+    // What gdb added, so detaching leaves the debugger's own alone
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, Access)>,
+}
+
+impl GdbStub {
+    // The program is stopped until gdb continues it
+    pub fn new() -> GdbStub {
+        GdbStub {
+            input: Vec::new(),
+            last_reply: Vec::new(),
+            last_stop: format!("S{:02x}", SIGTRAP),
+            no_ack: false,
+            running: false,
+            killed: false,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed
+    }
+
+    // Handles bytes received from gdb, returning the bytes to send back
+    pub fn receive(&mut self, debugger: &mut Debugger, bytes: &[u8]) -> Vec<u8> {
+        self.input.extend_from_slice(bytes);
+        let mut output = Vec::new();
+
+        while let Some(&byte) = self.input.first() {
+            match byte {
+                // Ctrl-C interrupts a running program
+                0x03 => {
+                    self.input.remove(0);
+                    if self.running {
+                        output.extend(self.stop(format!("S{:02x}", SIGINT)));
+                    }
+                }
+                b'-' => {
+                    self.input.remove(0);
+                    output.extend_from_slice(&self.last_reply);
+                }
+                b'$' => {
+                    // Wait for the rest of the packet and its checksum
+                    let Some(end) = self.input.iter().position(|&b| b == b'#') else {
+                        break;
+                    };
+                    if self.input.len() < end + 3 {
+                        break;
+                    }
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    if !self.no_ack {
+                        if checksum != Some(sum(data)) {
+                            output.push(b'-');
+                            continue;
+                        }
+                        output.push(b'+');
+                    }
+                    let data = String::from_utf8_lossy(data).into_owned();
+                    if let Some(reply) = self.handle(debugger, &data) {
+                        output.extend(self.packet(&reply));
+                    }
+                }
+                // Acks and anything between packets
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+        output
+    }
+
+    // Reports why a continued program stopped, returning the bytes to send
+    pub fn stopped(&mut self, reason: StopReason) -> Vec<u8> {
+        self.stop(stop_reply(reason))
+    }
+
+    // Removes gdb's breakpoints and lets the program run on, for when gdb
+    // detaches or the connection is lost
+    pub fn detach(&mut self, debugger: &mut Debugger) {
+        for addr in std::mem::take(&mut self.breakpoints) {
+            debugger.remove_breakpoint(addr);
+        }
+
+        // Watchpoints are removed by address, so put back any others on
+        // the same addresses
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let others: Vec<(u16, Access)> = debugger
+            .watchpoints()
+            .iter()
+            .filter(|watchpoint| !watchpoints.contains(watchpoint))
+            .copied()
+            .collect();
+        for (addr, _) in watchpoints {
+            debugger.remove_watchpoint(addr);
+        }
+        for (addr, access) in others {
+            debugger.add_watchpoint(addr, access);
+        }
+        self.running = true;
+    }
+
+    fn stop(&mut self, reply: String) -> Vec<u8> {
+        self.running = false;
+        self.last_stop = reply.clone();
+        self.packet(&reply)
+    }
+
+    // Frames a reply as $data#checksum
+    fn packet(&mut self, data: &str) -> Vec<u8> {
+        let packet = format!("${}#{:02x}", data, sum(data.as_bytes())).into_bytes();
+        self.last_reply = packet.clone();
+        packet
+    }
+
+    // The reply to a packet, None when there is nothing to send until the
+    // program stops
+    fn handle(&mut self, debugger: &mut Debugger, packet: &str) -> Option<String> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => self.last_stop.clone(),
+            Some(b'g') => read_registers(debugger.cpu()),
+            Some(b'G') => ok_or_error(write_registers(debugger.cpu_mut(), &packet[1..])),
+            Some(b'p') => parse_hex(&packet[1..])
+                .and_then(|n| read_register(debugger.cpu(), n))
+                .unwrap_or_else(|| error(1)),
+            Some(b'P') => {
+                let written = packet[1..].split_once('=').and_then(|(n, value)| {
+                    write_register(debugger.cpu_mut(), parse_hex(n)?, value)
+                });
+                ok_or_error(written)
+            }
+            Some(b'm') => parse_pair(&packet[1..])
+                .and_then(|(addr, len)| read_memory(debugger.cpu(), addr, len))
+                .unwrap_or_else(|| error(1)),
+            Some(b'M') => {
+                let written = packet[1..].split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_pair(range)?;
+                    let bytes = from_hex(data).filter(|bytes| bytes.len() == len)?;
+                    write_memory(debugger.cpu_mut(), addr, &bytes)
+                });
+                ok_or_error(written)
+            }
+            Some(b'c') => return self.resume(debugger, &packet[1..], false),
+            Some(b's') => return self.resume(debugger, &packet[1..], true),
+            Some(b'Z' | b'z') => ok_or_error(self.set_breakpoint(debugger, packet)),
+            Some(b'H' | b'T') => "OK".to_string(),
+            Some(b'D') => {
+                self.detach(debugger);
+                "OK".to_string()
+            }
+            Some(b'k') => {
+                self.killed = true;
+                return None;
+            }
+            _ => self.query(debugger, packet)?,
+        };
+        Some(reply)
+    }
+
+    // Longer named packets
+    fn query(&mut self, debugger: &mut Debugger, packet: &str) -> Option<String> {
+        let reply = if packet.starts_with("qSupported") {
+            format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+",
+                PACKET_SIZE
+            )
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if let Some(annex) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            match parse_pair(annex) {
+                Some((offset, len)) => read_part(&target_xml(), offset, len),
+                None => error(1),
+            }
+        } else if packet == "vCont?" {
+            "vCont;c;C;s;S".to_string()
+        } else if let Some(actions) = packet.strip_prefix("vCont;") {
+            // Only one thread, so the first action is the one that applies
+            return match actions.as_bytes().first() {
+                Some(b'c' | b'C') => self.resume(debugger, "", false),
+                Some(b's' | b'S') => self.resume(debugger, "", true),
+                _ => Some(error(1)),
+            };
+        } else if packet.starts_with("vKill") {
+            self.killed = true;
+            "OK".to_string()
+        } else {
+            // Empty means not supported
+            String::new()
+        };
+        Some(reply)
+    }
+
+    // Continues or steps, optionally from another address
+    fn resume(&mut self, debugger: &mut Debugger, addr: &str, step: bool) -> Option<String> {
+        if !addr.is_empty() {
+            match parse_hex(addr) {
+                Some(addr) => debugger.cpu_mut().program_counter = addr as u16,
+                None => return Some(error(1)),
+            }
+        }
+        if step {
+            let reply = stop_reply(debugger.step());
+            self.last_stop = reply.clone();
+            Some(reply)
+        } else {
+            self.running = true;
+            None
+        }
+    }
+    // Z0/Z1 add software and hardware breakpoints, which are the same thing
+    // here, and Z2/Z3/Z4 add write, read and access watchpoints on each byte
+    // of a range. z removes them.
+    fn set_breakpoint(&mut self, debugger: &mut Debugger, packet: &str) -> Option<()> {
+        let insert = packet.starts_with('Z');
+        let mut fields = packet[1..].split(',');
+        let kind = fields.next()?;
+        let addr = parse_hex(fields.next()?)? as u16;
+        let len = parse_hex(fields.next()?)? as u16;
+
+        let accesses: &[Access] = match kind {
+            "0" | "1" => {
+                if !insert {
+                    self.breakpoints.remove(&addr);
+                    debugger.remove_breakpoint(addr);
+                } else if !debugger.breakpoints().any(|pc| pc == addr) {
+                    self.breakpoints.insert(addr);
+                    debugger.add_breakpoint(addr);
+                }
+                return Some(());
+            }
+            "2" => &[Access::Write],
+            "3" => &[Access::Read],
+            "4" => &[Access::Read, Access::Write],
+            _ => return None,
+        };
+        for offset in 0..len.max(1) {
+            let addr = addr.wrapping_add(offset);
+            if insert {
+                for &access in accesses {
+                    if !debugger.watchpoints().contains(&(addr, access)) {
+                        self.watchpoints.push((addr, access));
+                        debugger.add_watchpoint(addr, access);
+                    }
+                }
+            } else {
+                self.watchpoints.retain(|(watched, _)| *watched != addr);
+                debugger.remove_watchpoint(addr);
+            }
+        }
+        Some(())
+    }
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        GdbStub::new()
+    }
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint { .. } => format!("T{:02x}swbreak:;", SIGTRAP),
+        StopReason::Watchpoint { addr, access, .. } => {
+            let kind = match access {
+                Access::Read => "rwatch",
+                Access::Write => "watch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+        }
+        StopReason::Condition { .. } | StopReason::Step => format!("S{:02x}", SIGTRAP),
+        StopReason::Exit => "W00".to_string(),
+        StopReason::Error { .. } => format!("S{:02x}", SIGILL),
+    }
+}
+
+// Describes the registers, since gdb has no CHIP-8 architecture of its own
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.cpu\">",
+    );
+    for (n, name) in REGISTERS.iter().enumerate() {
+        let kind = match *name {
+            "pc" => "code_ptr",
+            "i" => "data_ptr",
+            _ => "uint8",
+        };
+        let bits = register_size(n) * 8;
+        xml += &format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+            name, bits, kind, n
+        );
+    }
+    xml + "</feature></target>"
+}
+
+fn register_size(n: usize) -> usize {
+    match REGISTERS[n] {
+        "pc" | "i" => 2,
+        _ => 1,
+    }
+}
+
+fn register(cpu: &CPU, n: usize) -> u16 {
+    match n {
+        0 => cpu.program_counter,
+        1..=16 => cpu.v_registers[n - 1] as u16,
+        17 => cpu.i_register,
+        18 => cpu.stack_pointer,
+        19 => cpu.delay_timer as u16,
+        _ => cpu.sound_timer as u16,
+    }
+}
+
+fn read_register(cpu: &CPU, n: usize) -> Option<String> {
+    if n >= REGISTERS.len() {
+        return None;
+    }
+    let value = register(cpu, n);
+    Some(match register_size(n) {
+        2 => format!("{:04x}", value),
+        _ => format!("{:02x}", value),
+    })
+}
+
+fn read_registers(cpu: &CPU) -> String {
+    (0..REGISTERS.len())
+        .filter_map(|n| read_register(cpu, n))
+        .collect()
+}
+
+// Sets a register from its hex value, the stack pointer can't go past the
+// end of the stack
+fn write_register(cpu: &mut CPU, n: usize, hex: &str) -> Option<()> {
+    if n >= REGISTERS.len() || hex.len() != register_size(n) * 2 {
+        return None;
+    }
+    let value = u16::from_str_radix(hex, 16).ok()?;
+    match n {
+        0 => cpu.program_counter = value,
+        1..=16 => cpu.v_registers[n - 1] = value as u8,
+        17 => cpu.i_register = value,
+        18 if value as usize <= STACK_SIZE => cpu.stack_pointer = value,
+        18 => return None,
+        19 => cpu.delay_timer = value as u8,
+        _ => cpu.sound_timer = value as u8,
+    }
+    Some(())
+}
+
+fn write_registers(cpu: &mut CPU, hex: &str) -> Option<()> {
+    let mut rest = hex;
+    for n in 0..REGISTERS.len() {
+        let (value, tail) = rest.split_at_checked(register_size(n) * 2)?;
+        write_register(cpu, n, value)?;
+        rest = tail;
+    }
+    Some(())
+}
+
+// Reads as much of a range as is in ram
+fn read_memory(cpu: &CPU, addr: usize, len: usize) -> Option<String> {
+    let memory = cpu.memory();
+    if addr >= memory.len() {
+        return None;
+    }
+    let end = memory.len().min(addr + len.min(PACKET_SIZE / 2));
+    Some(to_hex(&memory[addr..end]))
+}
+
+fn write_memory(cpu: &mut CPU, addr: usize, bytes: &[u8]) -> Option<()> {
+    let end = addr.checked_add(bytes.len())?;
+    if end > cpu.memory().len() {
+        return None;
+    }
+    cpu.ram[addr..end].copy_from_slice(bytes);
+    Some(())
+}
+
+// Part of a qXfer document, m when there is more to come and l for the last
+fn read_part(document: &str, offset: usize, len: usize) -> String {
+    let bytes = document.as_bytes();
+    let start = offset.min(bytes.len());
+    let end = bytes.len().min(start + len.min(PACKET_SIZE - 1));
+    let marker = if end < bytes.len() { 'm' } else { 'l' };
+    format!("{}{}", marker, String::from_utf8_lossy(&bytes[start..end]))
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    match result {
+        Some(()) => "OK".to_string(),
+        None => error(1),
+    }
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// ADDR,LENGTH as used by m, M and qXfer
+fn parse_pair(text: &str) -> Option<(usize, usize)> {
+    let (first, second) = text.split_once(',')?;
+    Some((parse_hex(first)?, parse_hex(second)?))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks, Register, Rom};
+
+    // Calls a subroutine that stores V0 at 0x300, then loops forever
+    const PROGRAM: [u8; 14] = [
+        0x60, 0x05, // 200: LD V0, 0x05
+        0x22, 0x08, // 202: CALL 0x208
+        0x61, 0x01, // 204: LD V1, 0x01
+        0x12, 0x06, // 206: JP 0x206
+        0xA3, 0x00, // 208: LD I, 0x300
+        0xF0, 0x55, // 20A: LD [I], V0
+        0x00, 0xEE, // 20C: RET
+    ];
+
+    fn debugger() -> Debugger {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::chip48(), Some(0));
+        cpu.load_rom(&Rom::new(&PROGRAM)).unwrap();
+        Debugger::new(cpu)
+    }
+
+    fn frame(data: &str) -> Vec<u8> {
+        format!("${}#{:02x}", data, sum(data.as_bytes())).into_bytes()
+    }
+
+    // Sends a packet and returns the reply, checking it is acknowledged
+    // and framed properly
+    fn send(stub: &mut GdbStub, debugger: &mut Debugger, data: &str) -> String {
+        let output = stub.receive(debugger, &frame(data));
+        let output = String::from_utf8(output).unwrap();
+        let reply = output
+            .strip_prefix('+')
+            .expect("packet wasn't acknowledged");
+        if reply.is_empty() {
+            return reply.to_string();
+        }
+        let (body, checksum) = reply[1..].split_once('#').unwrap();
+        assert_eq!(checksum, format!("{:02x}", sum(body.as_bytes())));
+        body.to_string()
+    }
+
+    #[test]
+    fn answers_queries() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        let supported = send(&mut stub, &mut debugger, "qSupported:swbreak+");
+        assert!(supported.starts_with("PacketSize=1000;"));
+        assert!(supported.contains("qXfer:features:read+"));
+        assert_eq!(send(&mut stub, &mut debugger, "?"), "S05");
+        assert_eq!(send(&mut stub, &mut debugger, "qAttached"), "1");
+        assert_eq!(send(&mut stub, &mut debugger, "vMustReplyEmpty"), "");
+
+        let xml = send(
+            &mut stub,
+            &mut debugger,
+            "qXfer:features:read:target.xml:0,ffb",
+        );
+        assert!(xml.starts_with("l<?xml"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"0\"/>"));
+        let part = send(
+            &mut stub,
+            &mut debugger,
+            "qXfer:features:read:target.xml:0,10",
+        );
+        assert_eq!(part, format!("m{}", &xml[1..17]));
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        debugger.step();
+        let registers = format!("0202{}{}0000000000", "05", "00".repeat(15));
+        assert_eq!(send(&mut stub, &mut debugger, "g"), registers);
+
+        let registers = format!("0204{}1234020304", "a1".repeat(16));
+        assert_eq!(
+            send(&mut stub, &mut debugger, &format!("G{}", registers)),
+            "OK"
+        );
+        assert_eq!(send(&mut stub, &mut debugger, "g"), registers);
+        assert_eq!(debugger.register(Register::Sp), 2);
+        assert_eq!(send(&mut stub, &mut debugger, "G0204"), "E01");
+
+        assert_eq!(send(&mut stub, &mut debugger, "p0"), "0204");
+        assert_eq!(send(&mut stub, &mut debugger, "p11"), "1234");
+        assert_eq!(send(&mut stub, &mut debugger, "p15"), "E01");
+        assert_eq!(send(&mut stub, &mut debugger, "P10=7f"), "OK");
+        assert_eq!(debugger.register(Register::V(0xF)), 0x7F);
+        assert_eq!(send(&mut stub, &mut debugger, "P10=7f7f"), "E01");
+        // Past the end of the stack
+        assert_eq!(send(&mut stub, &mut debugger, "P12=11"), "E01");
+        assert_eq!(send(&mut stub, &mut debugger, "P15=00"), "E01");
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        assert_eq!(send(&mut stub, &mut debugger, "m200,4"), "60052208");
+        // Reads stop at the end of ram
+        assert_eq!(send(&mut stub, &mut debugger, "mffe,4"), "0000");
+        assert_eq!(send(&mut stub, &mut debugger, "m1000,1"), "E01");
+        assert_eq!(send(&mut stub, &mut debugger, "mffffffffffffffff,1"), "E01");
+
+        assert_eq!(send(&mut stub, &mut debugger, "M300,2:abcd"), "OK");
+        assert_eq!(debugger.cpu().ram[0x300..0x302], [0xAB, 0xCD]);
+        assert_eq!(send(&mut stub, &mut debugger, "M300,2:ab"), "E01");
+        assert_eq!(send(&mut stub, &mut debugger, "Mfff,2:0000"), "E01");
+        assert_eq!(
+            send(&mut stub, &mut debugger, "Mffffffffffffffff,1:00"),
+            "E01"
+        );
+    }
+
+    #[test]
+    fn continues_to_a_breakpoint() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        assert_eq!(send(&mut stub, &mut debugger, "Z0,20a,2"), "OK");
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x20A]);
+
+        // Nothing comes back until the front end reports the stop
+        assert_eq!(send(&mut stub, &mut debugger, "c"), "");
+        assert!(stub.is_running());
+        let reason = debugger.run(100).unwrap();
+        assert_eq!(reason, StopReason::Breakpoint { pc: 0x20A });
+        assert_eq!(stub.stopped(reason), frame("T05swbreak:;"));
+        assert!(!stub.is_running());
+        assert_eq!(send(&mut stub, &mut debugger, "?"), "T05swbreak:;");
+
+        assert_eq!(send(&mut stub, &mut debugger, "z0,20a,2"), "OK");
+        assert_eq!(debugger.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn stops_on_watchpoints() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        assert_eq!(send(&mut stub, &mut debugger, "Z2,300,1"), "OK");
+        assert_eq!(send(&mut stub, &mut debugger, "c"), "");
+        let reason = debugger.run(100).unwrap();
+        assert_eq!(stub.stopped(reason), frame("T05watch:300;"));
+    }
+
+    #[test]
+    fn steps_one_instruction() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        assert_eq!(send(&mut stub, &mut debugger, "s"), "S05");
+        assert_eq!(debugger.register(Register::Pc), 0x202);
+        assert!(!stub.is_running());
+        // From another address
+        assert_eq!(send(&mut stub, &mut debugger, "s204"), "S05");
+        assert_eq!(debugger.register(Register::Pc), 0x206);
+        assert_eq!(debugger.register(Register::V(1)), 1);
+    }
+
+    #[test]
+    fn ctrl_c_interrupts_a_running_program() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        // Ignored while stopped
+        assert_eq!(stub.receive(&mut debugger, &[0x03]), b"");
+
+        send(&mut stub, &mut debugger, "c");
+        assert_eq!(stub.receive(&mut debugger, &[0x03]), frame("S02"));
+        assert!(!stub.is_running());
+        assert_eq!(send(&mut stub, &mut debugger, "?"), "S02");
+    }
+
+    #[test]
+    fn bad_checksums_are_refused_and_replies_resent() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        assert_eq!(stub.receive(&mut debugger, b"$p0#00"), b"-");
+
+        // Packets can arrive in pieces
+        assert_eq!(stub.receive(&mut debugger, b"$p"), b"");
+        let mut reply = b"+".to_vec();
+        reply.extend(frame("0200"));
+        assert_eq!(stub.receive(&mut debugger, b"0#a0"), reply);
+        assert_eq!(stub.receive(&mut debugger, b"-"), frame("0200"));
+
+        // No acks at all after QStartNoAckMode
+        send(&mut stub, &mut debugger, "QStartNoAckMode");
+        assert_eq!(stub.receive(&mut debugger, &frame("p0")), frame("0200"));
+    }
+
+    #[test]
+    fn detach_only_removes_what_gdb_added() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        debugger.add_breakpoint(0x204);
+        debugger.add_watchpoint(0x300, Access::Read);
+
+        send(&mut stub, &mut debugger, "Z0,204,2");
+        send(&mut stub, &mut debugger, "Z0,20a,2");
+        send(&mut stub, &mut debugger, "Z4,300,2");
+        assert_eq!(debugger.watchpoints().len(), 4);
+
+        assert_eq!(send(&mut stub, &mut debugger, "D"), "OK");
+        assert!(stub.is_running());
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x204]);
+        assert_eq!(debugger.watchpoints(), [(0x300, Access::Read)]);
+    }
+
+    #[test]
+    fn kill_is_reported_to_the_front_end() {
+        let (mut stub, mut debugger) = (GdbStub::new(), debugger());
+        assert_eq!(send(&mut stub, &mut debugger, "k"), "");
+        assert!(stub.is_killed());
+
+        let mut stub = GdbStub::new();
+        assert_eq!(send(&mut stub, &mut debugger, "vKill;1"), "OK");
+        assert!(stub.is_killed());
+    }
+}
@@ -0,0 +1,320 @@
+use crate::Platform;
+use std::fmt;
+
+// A decoded opcode. X and Y are register indexes, NN a byte, NNN an address
+// and N a nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,                 // 0000
+    Cls,                 // 00E0
+    Ret,                 // 00EE
+    ScrollDown(u8),      // 00CN
+    ScrollUp(u8),        // 00DN
+    ScrollRight,         // 00FB
+    ScrollLeft,          // 00FC
+    Exit,                // 00FD
+    Lores,               // 00FE
+    Hires,               // 00FF
+    Jump(u16),           // 1NNN
+    Call(u16),           // 2NNN
+    SkipEqByte(u8, u8),  // 3XNN
+    SkipNeByte(u8, u8),  // 4XNN
+    SkipEqReg(u8, u8),   // 5XY0
+    SaveRange(u8, u8),   // 5XY2
+    LoadRange(u8, u8),   // 5XY3
+    LoadByte(u8, u8),    // 6XNN
+    AddByte(u8, u8),     // 7XNN
+    LoadReg(u8, u8),     // 8XY0
+    Or(u8, u8),          // 8XY1
+    And(u8, u8),         // 8XY2
+    Xor(u8, u8),         // 8XY3
+    AddReg(u8, u8),      // 8XY4
+    Sub(u8, u8),         // 8XY5
+    ShiftRight(u8, u8),  // 8XY6
+    SubReverse(u8, u8),  // 8XY7
+    ShiftLeft(u8, u8),   // 8XYE
+    SkipNeReg(u8, u8),   // 9XY0
+    LoadI(u16),          // ANNN
+    JumpOffset(u8, u16), // BNNN, X is only used with the jump quirk
+    Random(u8, u8),      // CXNN
+    Draw(u8, u8, u8),    // DXYN
+    SkipKey(u8),         // EX9E
+    SkipNotKey(u8),      // EXA1
+    LoadILong,           // F000 NNNN, the address is the following word
+    Plane(u8),           // FN01
+    Audio,               // F002
+    LoadDelay(u8),       // FX07
+    WaitKey(u8),         // FX0A
+    SetDelay(u8),        // FX15
+    SetSound(u8),        // FX18
+    AddI(u8),            // FX1E
+    Font(u8),            // FX29
+    BigFont(u8),         // FX30
+    Bcd(u8),             // FX33
+    Pitch(u8),           // FX3A
+    Store(u8),           // FX55
+    Load(u8),            // FX65
+    StoreFlags(u8),      // FX75
+    LoadFlags(u8),       // FX85
+    Unknown(u16),
+}
+
+impl Instruction {
+    // Decodes opcode by separating it out into induvidual digits
+    pub fn decode(opcode: u16) -> Instruction {
+        let digits = (
+            (opcode & 0xF000) >> 12,
+            (opcode & 0x0F00) >> 8,
+            (opcode & 0x00F0) >> 4,
+            (opcode & 0x000F),
+        );
+        let x = digits.1 as u8;
+        let y = digits.2 as u8;
+        let n = digits.3 as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        match digits {
+            (0, 0, 0, 0) => Instruction::Nop,
+            (0, 0, 0xE, 0) => Instruction::Cls,
+            (0, 0, 0xE, 0xE) => Instruction::Ret,
+            (0, 0, 0xC, _) => Instruction::ScrollDown(n),
+            (0, 0, 0xD, _) => Instruction::ScrollUp(n),
+            (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0, 0, 0xF, 0xD) => Instruction::Exit,
+            (0, 0, 0xF, 0xE) => Instruction::Lores,
+            (0, 0, 0xF, 0xF) => Instruction::Hires,
+            (1, _, _, _) => Instruction::Jump(nnn),
+            (2, _, _, _) => Instruction::Call(nnn),
+            (3, _, _, _) => Instruction::SkipEqByte(x, nn),
+            (4, _, _, _) => Instruction::SkipNeByte(x, nn),
+            (5, _, _, 0) => Instruction::SkipEqReg(x, y),
+            (5, _, _, 2) => Instruction::SaveRange(x, y),
+            (5, _, _, 3) => Instruction::LoadRange(x, y),
+            (6, _, _, _) => Instruction::LoadByte(x, nn),
+            (7, _, _, _) => Instruction::AddByte(x, nn),
+            (8, _, _, 0) => Instruction::LoadReg(x, y),
+            (8, _, _, 1) => Instruction::Or(x, y),
+            (8, _, _, 2) => Instruction::And(x, y),
+            (8, _, _, 3) => Instruction::Xor(x, y),
+            (8, _, _, 4) => Instruction::AddReg(x, y),
+            (8, _, _, 5) => Instruction::Sub(x, y),
+            (8, _, _, 6) => Instruction::ShiftRight(x, y),
+            (8, _, _, 7) => Instruction::SubReverse(x, y),
+            (8, _, _, 0xE) => Instruction::ShiftLeft(x, y),
+            (9, _, _, 0) => Instruction::SkipNeReg(x, y),
+            (0xA, _, _, _) => Instruction::LoadI(nnn),
+            (0xB, _, _, _) => Instruction::JumpOffset(x, nnn),
+            (0xC, _, _, _) => Instruction::Random(x, nn),
+            (0xD, _, _, _) => Instruction::Draw(x, y, n),
+            (0xE, _, 9, 0xE) => Instruction::SkipKey(x),
+            (0xE, _, 0xA, 1) => Instruction::SkipNotKey(x),
+            (0xF, 0, 0, 0) => Instruction::LoadILong,
+            (0xF, _, 0, 1) => Instruction::Plane(x),
+            (0xF, 0, 0, 2) => Instruction::Audio,
+            (0xF, _, 0, 7) => Instruction::LoadDelay(x),
+            (0xF, _, 0, 0xA) => Instruction::WaitKey(x),
+            (0xF, _, 1, 5) => Instruction::SetDelay(x),
+            (0xF, _, 1, 8) => Instruction::SetSound(x),
+            (0xF, _, 1, 0xE) => Instruction::AddI(x),
+            (0xF, _, 2, 9) => Instruction::Font(x),
+            (0xF, _, 3, 0) => Instruction::BigFont(x),
+            (0xF, _, 3, 3) => Instruction::Bcd(x),
+            (0xF, _, 3, 0xA) => Instruction::Pitch(x),
+            (0xF, _, 5, 5) => Instruction::Store(x),
+            (0xF, _, 6, 5) => Instruction::Load(x),
+            (0xF, _, 7, 5) => Instruction::StoreFlags(x),
+            (0xF, _, 8, 5) => Instruction::LoadFlags(x),
+            (_, _, _, _) => Instruction::Unknown(opcode),
+        }
+    }
+
+    // Size in bytes, including the address word of F000 NNNN
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong => 4,
+            _ => 2,
+        }
+    }
+
+    // Conditionally skips the next instruction
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqByte(..)
+                | Instruction::SkipNeByte(..)
+                | Instruction::SkipEqReg(..)
+                | Instruction::SkipNeReg(..)
+                | Instruction::SkipKey(_)
+                | Instruction::SkipNotKey(_)
+        )
+    }
+
+    // The first platform with this instruction
+    pub fn platform(&self) -> Platform {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::Draw(_, _, 0)
+            | Instruction::BigFont(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_) => Platform::SuperChip,
+            Instruction::ScrollUp(_)
+            | Instruction::SaveRange(..)
+            | Instruction::LoadRange(..)
+            | Instruction::LoadILong
+            | Instruction::Plane(_)
+            | Instruction::Audio
+            | Instruction::Pitch(_) => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+}
+
+// Formats using the common Cowgod style mnemonics
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Jump(nnn) => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SkipEqByte(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipNeByte(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SkipEqReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::LoadByte(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::LoadReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubReverse(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JumpOffset(_, nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_and_formats_every_instruction() {
+        let table = [
+            (0x0000, Instruction::Nop, "NOP"),
+            (0x00E0, Instruction::Cls, "CLS"),
+            (0x00EE, Instruction::Ret, "RET"),
+            (0x00C4, Instruction::ScrollDown(4), "SCD 4"),
+            (0x00D2, Instruction::ScrollUp(2), "SCU 2"),
+            (0x00FB, Instruction::ScrollRight, "SCR"),
+            (0x00FC, Instruction::ScrollLeft, "SCL"),
+            (0x00FD, Instruction::Exit, "EXIT"),
+            (0x00FE, Instruction::Lores, "LOW"),
+            (0x00FF, Instruction::Hires, "HIGH"),
+            (0x1234, Instruction::Jump(0x234), "JP 0x234"),
+            (0x2ABC, Instruction::Call(0xABC), "CALL 0xABC"),
+            (0x3A05, Instruction::SkipEqByte(0xA, 0x05), "SE VA, 0x05"),
+            (0x4BFF, Instruction::SkipNeByte(0xB, 0xFF), "SNE VB, 0xFF"),
+            (0x5120, Instruction::SkipEqReg(1, 2), "SE V1, V2"),
+            (0x5132, Instruction::SaveRange(1, 3), "SAVE V1 - V3"),
+            (0x5313, Instruction::LoadRange(3, 1), "LOAD V3 - V1"),
+            (0x6E05, Instruction::LoadByte(0xE, 0x05), "LD VE, 0x05"),
+            (0x7001, Instruction::AddByte(0, 0x01), "ADD V0, 0x01"),
+            (0x8120, Instruction::LoadReg(1, 2), "LD V1, V2"),
+            (0x8121, Instruction::Or(1, 2), "OR V1, V2"),
+            (0x8122, Instruction::And(1, 2), "AND V1, V2"),
+            (0x8123, Instruction::Xor(1, 2), "XOR V1, V2"),
+            (0x8124, Instruction::AddReg(1, 2), "ADD V1, V2"),
+            (0x8125, Instruction::Sub(1, 2), "SUB V1, V2"),
+            (0x8126, Instruction::ShiftRight(1, 2), "SHR V1, V2"),
+            (0x8127, Instruction::SubReverse(1, 2), "SUBN V1, V2"),
+            (0x812E, Instruction::ShiftLeft(1, 2), "SHL V1, V2"),
+            (0x9120, Instruction::SkipNeReg(1, 2), "SNE V1, V2"),
+            (0xA2F0, Instruction::LoadI(0x2F0), "LD I, 0x2F0"),
+            (0xB300, Instruction::JumpOffset(3, 0x300), "JP V0, 0x300"),
+            (0xC70F, Instruction::Random(7, 0x0F), "RND V7, 0x0F"),
+            (0xD125, Instruction::Draw(1, 2, 5), "DRW V1, V2, 5"),
+            (0xE29E, Instruction::SkipKey(2), "SKP V2"),
+            (0xE2A1, Instruction::SkipNotKey(2), "SKNP V2"),
+            (0xF000, Instruction::LoadILong, "LD I, LONG"),
+            (0xF201, Instruction::Plane(2), "PLANE 2"),
+            (0xF002, Instruction::Audio, "AUDIO"),
+            (0xF307, Instruction::LoadDelay(3), "LD V3, DT"),
+            (0xF30A, Instruction::WaitKey(3), "LD V3, K"),
+            (0xF315, Instruction::SetDelay(3), "LD DT, V3"),
+            (0xF318, Instruction::SetSound(3), "LD ST, V3"),
+            (0xF31E, Instruction::AddI(3), "ADD I, V3"),
+            (0xF329, Instruction::Font(3), "LD F, V3"),
+            (0xF330, Instruction::BigFont(3), "LD HF, V3"),
+            (0xF333, Instruction::Bcd(3), "LD B, V3"),
+            (0xF33A, Instruction::Pitch(3), "PITCH V3"),
+            (0xF355, Instruction::Store(3), "LD [I], V3"),
+            (0xF365, Instruction::Load(3), "LD V3, [I]"),
+            (0xF375, Instruction::StoreFlags(3), "LD R, V3"),
+            (0xF385, Instruction::LoadFlags(3), "LD V3, R"),
+        ];
+        for (opcode, instruction, mnemonic) in table {
+            assert_eq!(Instruction::decode(opcode), instruction, "{:04X}", opcode);
+            assert_eq!(instruction.to_string(), mnemonic, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_data_words() {
+        for opcode in [0x00E1, 0x5121, 0x8128, 0x9121, 0xE200, 0xF099, 0xF100] {
+            assert_eq!(Instruction::decode(opcode), Instruction::Unknown(opcode));
+        }
+        assert_eq!(Instruction::Unknown(0xE200).to_string(), "DW 0xE200");
+    }
+
+    #[test]
+    fn sizes_and_platforms() {
+        assert_eq!(Instruction::LoadILong.size(), 4);
+        assert_eq!(Instruction::Cls.size(), 2);
+        assert_eq!(Instruction::decode(0xD120).platform(), Platform::SuperChip);
+        assert_eq!(Instruction::decode(0xD125).platform(), Platform::Chip8);
+        assert_eq!(Instruction::decode(0xF201).platform(), Platform::XoChip);
+    }
+}
@@ -0,0 +1,181 @@
+// Maps host keys onto the 16-key keypad. Host keys are matched by name
+// ignoring case, using whatever names the front end gets from its platform
+// (SDL key names on the desktop, KeyboardEvent.key in the browser). Letters
+// and digits are named the same everywhere so the layouts work in both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: Vec<(String, u8)>, // Lowercase host key name and keypad key
+}
+
+// Keypad keys in the order they sit on the COSMAC VIP, row by row
+#[rustfmt::skip]
+const KEYPAD: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+impl Keymap {
+    // Names accepted by Keymap::from_name
+    pub const NAMES: [&'static str; 2] = ["qwerty", "azerty"];
+
+    // No keys bound
+    pub fn new() -> Keymap {
+        Keymap {
+            bindings: Vec::new(),
+        }
+    }
+
+    // The left hand block 1234/QWER/ASDF/ZXCV
+    pub fn qwerty() -> Keymap {
+        Keymap::from_rows(["1234", "qwer", "asdf", "zxcv"])
+    }
+
+    // The same block on a French keyboard. Its top row types symbols
+    // without shift, so those are bound as well as the digits.
+    pub fn azerty() -> Keymap {
+        let mut keymap = Keymap::from_rows(["1234", "azer", "qsdf", "wxcv"]);
+        for (host, key) in [("&", 0x1), ("é", 0x2), ("\"", 0x3), ("'", 0xC)] {
+            keymap.bind(host, key);
+        }
+        keymap
+    }
+
+    // Looks up a layout by name
+    pub fn from_name(name: &str) -> Option<Keymap> {
+        match name.to_ascii_lowercase().as_str() {
+            "qwerty" => Some(Keymap::qwerty()),
+            "azerty" => Some(Keymap::azerty()),
+            _ => None,
+        }
+    }
+
+    fn from_rows(rows: [&str; 4]) -> Keymap {
+        let mut keymap = Keymap::new();
+        let hosts = rows.iter().flat_map(|row| row.chars());
+        for (host, key) in hosts.zip(KEYPAD) {
+            keymap.bind(&host.to_string(), key);
+        }
+        keymap
+    }
+
+    // Parses a keypad key name, a single hex digit
+    pub fn parse_key(name: &str) -> Option<u8> {
+        match name.len() {
+            1 => u8::from_str_radix(name, 16).ok(),
+            _ => None,
+        }
+    }
+
+    // Adds a host key for a keypad key, any number can share one
+    pub fn bind(&mut self, host: &str, key: u8) {
+        let host = host.to_lowercase();
+        self.bindings.retain(|(bound, _)| *bound != host);
+        self.bindings.push((host, key & 0xF));
+    }
+
+    // Replaces every host key bound to a keypad key
+    pub fn set(&mut self, key: u8, hosts: &[String]) {
+        self.bindings.retain(|(_, bound)| *bound != key);
+        for host in hosts {
+            self.bind(host, key);
+        }
+    }
+
+    // Keypad key pressed by a host key
+    pub fn key(&self, host: &str) -> Option<usize> {
+        let host = host.to_lowercase();
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == host)
+            .map(|(_, key)| *key as usize)
+    }
+
+    // Host keys bound to a keypad key
+    pub fn hosts(&self, key: u8) -> impl Iterator<Item = &str> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bound)| *bound == key)
+            .map(|(host, _)| host.as_str())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::qwerty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(keymap: &Keymap, key: u8) -> Vec<&str> {
+        keymap.hosts(key).collect()
+    }
+
+    #[test]
+    fn looks_up_layouts() {
+        let qwerty = Keymap::from_name("QWERTY").unwrap();
+        assert_eq!(qwerty, Keymap::default());
+        for (host, key) in [
+            ("1", 0x1),
+            ("4", 0xC),
+            ("q", 0x4),
+            ("Q", 0x4),
+            ("x", 0x0),
+            ("v", 0xF),
+        ] {
+            assert_eq!(qwerty.key(host), Some(key), "{}", host);
+        }
+        assert_eq!(qwerty.key("p"), None);
+        assert_eq!(qwerty.key("Space"), None);
+
+        let azerty = Keymap::from_name("azerty").unwrap();
+        assert_eq!(azerty.key("a"), Some(0x4));
+        assert_eq!(azerty.key("q"), Some(0x7));
+        assert_eq!(azerty.key("W"), Some(0xA));
+        // The unshifted top row, matched ignoring case like everything else
+        assert_eq!(azerty.key("É"), Some(0x2));
+        assert_eq!(azerty.key("&"), Some(0x1));
+        assert_eq!(hosts(&azerty, 0x1), ["1", "&"]);
+
+        assert_eq!(Keymap::from_name("dvorak"), None);
+        assert_eq!(Keymap::new().key("1"), None);
+    }
+
+    #[test]
+    fn parses_key_names() {
+        assert_eq!(Keymap::parse_key("0"), Some(0x0));
+        assert_eq!(Keymap::parse_key("a"), Some(0xA));
+        assert_eq!(Keymap::parse_key("F"), Some(0xF));
+        for bad in ["", "10", "0x1", "g", "+", "é"] {
+            assert_eq!(Keymap::parse_key(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn a_host_key_only_presses_one_keypad_key() {
+        let mut keymap = Keymap::qwerty();
+        keymap.bind("Q", 0x5);
+        assert_eq!(keymap.key("q"), Some(0x5));
+        assert_eq!(hosts(&keymap, 0x4), Vec::<&str>::new());
+        assert_eq!(hosts(&keymap, 0x5), ["w", "q"]);
+    }
+
+    #[test]
+    fn set_replaces_a_keys_hosts() {
+        let mut keymap = Keymap::qwerty();
+        let up = ["Up".to_string(), "w".to_string(), "UP".to_string()];
+        keymap.set(0x5, &up);
+        // Duplicate names are bound once, and taken from the key they were on
+        assert_eq!(hosts(&keymap, 0x5), ["w", "up"]);
+        assert_eq!(keymap.key("Up"), Some(0x5));
+        assert_eq!(keymap.key("q"), Some(0x4));
+
+        keymap.set(0x5, &[]);
+        assert_eq!(keymap.key("w"), None);
+        assert_eq!(hosts(&keymap, 0x5), Vec::<&str>::new());
+    }
+}
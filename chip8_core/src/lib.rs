@@ -1,14 +1,69 @@
 use rand::random;
+use rng::Rng;
+use timing::VIP_FRAME_BUDGET;
+
+mod audio;
+mod checksum;
+mod database;
+mod debugger;
+mod disasm;
+mod error;
+mod gdb;
+mod instruction;
+mod json;
+mod keymap;
+mod movie;
+mod palette;
+mod platform;
+mod quirks;
+mod rewind;
+mod rng;
+mod rom;
+mod scheduler;
+mod script;
+mod state;
+mod symbols;
+mod timing;
+mod trace;
+
+pub use audio::Synth;
+pub use checksum::sha1;
+pub use database::{RomDatabase, RomInfo};
+pub use debugger::{Access, Comparison, Condition, Debugger, Register, StopReason};
+pub use disasm::Disassembly;
+pub use error::Chip8Error;
+pub use gdb::GdbStub;
+pub use instruction::Instruction;
+pub use keymap::Keymap;
+pub use movie::{Movie, MovieError};
+pub use palette::{Palette, Rgb};
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use rewind::Rewinder;
+pub use rom::Rom;
+pub use scheduler::{Scheduler, Slice, DEFAULT_IPS, TIMER_HZ};
+pub use script::{InputScript, KeyEvent};
+pub use state::StateError;
+pub use symbols::Symbols;
+pub use timing::{Timing, VIP_CYCLES_PER_FRAME};
+pub use trace::{TraceEntry, TraceFilter, TraceFormat, TraceRegisters, Tracer};
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const START_ADDRESS: u16 = 0x200;
 
-const RAM_SIZE: usize = 4096;
+const MAX_RAM_SIZE: usize = 0x10000;
 const NUM_REGS: usize = 16;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
-const START_ADDRESS: u16 = 0x200;
 const FONTSET_SIZE: usize = 80;
+const BIG_FONTSET_SIZE: usize = 160;
+const BIG_FONT_ADDRESS: usize = FONTSET_SIZE;
+const NUM_RPL_FLAGS: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
 
 const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -29,25 +84,92 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// 8x10 SUPER-CHIP font used by FX30
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// What happened during a single tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // Instruction executed normally
+    Continue,
+    // Blocked on FX0A until a key is pressed
+    WaitingForKey,
+    // The program executed 00FD and has stopped
+    Exit,
+    // VIP timing has used up this frame's cycles, nothing runs until the
+    // next tick_timers
+    WaitingForFrame,
+}
+
+// The visible part of the display at its current resolution, each pixel
+// holds one bit per XO-CHIP bitplane
+pub struct Frame<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [u8],
+}
+
+#[derive(Clone)]
 pub struct CPU {
-    program_counter: u16,                         // Program counter
-    ram: [u8; RAM_SIZE],                          // Ram
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT], // Display
-    v_registers: [u8; NUM_REGS],                  // V registers
-    i_register: u16,                              // I register
-    stack_pointer: u16,                           // Pointer to the top of the stack
-    stack: [u16; STACK_SIZE],                     // Stack
-    keypad: [bool; NUM_KEYS],                     // Keys pressed
-    delay_timer: u8,                              // Delay timer
-    sound_timer: u8,                              // Sound timer
+    program_counter: u16,                     // Program counter
+    ram: [u8; MAX_RAM_SIZE],                  // Ram
+    screen: [u8; HIRES_WIDTH * HIRES_HEIGHT], // Display
+    hires: bool,                              // 128x64 display mode
+    planes: u8,                               // Bitplanes selected for drawing
+    v_registers: [u8; NUM_REGS],              // V registers
+    i_register: u16,                          // I register
+    stack_pointer: u16,                       // Pointer to the top of the stack
+    stack: [u16; STACK_SIZE],                 // Stack
+    keypad: [bool; NUM_KEYS],                 // Keys pressed
+    delay_timer: u8,                          // Delay timer
+    sound_timer: u8,                          // Sound timer
+    rpl_flags: [u8; NUM_RPL_FLAGS],           // SUPER-CHIP user flags
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],  // XO-CHIP audio pattern buffer
+    pitch: u8,                                // XO-CHIP audio pitch
+    platform: Platform,                       // Machine being emulated
+    quirks: Quirks,                           // Interpreter behaviour
+    timing: Timing,                           // How long instructions take
+    cycles_left: u32,                         // VIP machine cycles left this frame
+    seed: u64,                                // Seed the random number generator started from
+    rng: Rng,                                 // Random number generator for CXNN
+    access_log: Option<Vec<(u16, Access)>>,   // Memory accesses for debugger watchpoints
+    trace_log: Option<Vec<TraceEntry>>,       // Instructions executed while tracing
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new(Platform::default(), Quirks::default(), None)
+    }
 }
 
 impl CPU {
-    pub fn new() -> CPU {
+    // A seed makes CXNN produce the same numbers every run, without one a
+    // random seed is picked
+    pub fn new(platform: Platform, quirks: Quirks, seed: Option<u64>) -> CPU {
+        let seed = seed.unwrap_or_else(random);
         let mut new_cpu = CPU {
             program_counter: START_ADDRESS,
-            ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            ram: [0; MAX_RAM_SIZE],
+            screen: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            hires: false,
+            planes: 1,
             v_registers: [0; NUM_REGS],
             i_register: 0,
             stack_pointer: 0,
@@ -55,19 +177,33 @@ impl CPU {
             keypad: [false; NUM_KEYS],
             delay_timer: 0,
             sound_timer: 0,
+            rpl_flags: [0; NUM_RPL_FLAGS],
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            platform,
+            quirks,
+            timing: Timing::default(),
+            cycles_left: VIP_FRAME_BUDGET,
+            seed,
+            rng: Rng::new(seed),
+            access_log: None,
+            trace_log: None,
         };
 
-        // Loads the fontset into ram
-        new_cpu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        // Loads the fontsets into ram
+        new_cpu.load_fonts();
 
         new_cpu
     }
 
-    // Resets cpu back to original state
+    // Resets cpu back to original state, RPL flags are kept like on the HP-48.
+    // The random number generator restarts from its seed.
     pub fn reset(&mut self) {
         self.program_counter = START_ADDRESS;
-        self.ram = [0; RAM_SIZE];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.ram = [0; MAX_RAM_SIZE];
+        self.screen = [0; HIRES_WIDTH * HIRES_HEIGHT];
+        self.hires = false;
+        self.planes = 1;
         self.v_registers = [0; NUM_REGS];
         self.i_register = 0;
         self.stack_pointer = 0;
@@ -75,275 +211,604 @@ impl CPU {
         self.keypad = [false; NUM_KEYS];
         self.delay_timer = 0;
         self.sound_timer = 0;
+        self.audio_pattern = [0; AUDIO_PATTERN_SIZE];
+        self.pitch = DEFAULT_PITCH;
+        self.cycles_left = VIP_FRAME_BUDGET;
+        self.rng = Rng::new(self.seed);
+        self.load_fonts();
+    }
+
+    fn load_fonts(&mut self) {
         self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        self.ram[BIG_FONT_ADDRESS..BIG_FONT_ADDRESS + BIG_FONTSET_SIZE]
+            .copy_from_slice(&BIG_FONTSET);
+    }
+
+    // Restarts the random number generator from a new seed
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Changes the quirks used by future instructions
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Switches timing model, starting with a full frame of cycles
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.cycles_left = VIP_FRAME_BUDGET;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    // Whether VIP timing has run out of cycles for this frame
+    pub fn is_waiting_for_frame(&self) -> bool {
+        self.timing == Timing::Vip && self.cycles_left == 0
+    }
+
+    // Changes the emulated machine, takes effect for the next rom loaded
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    // XO-CHIP audio pattern, one bit per sample
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
+    }
+
+    // The buzzer sounds while the sound timer is running
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // Playback rate of the audio pattern in samples per second
+    pub fn audio_rate(&self) -> f32 {
+        4000. * 2f32.powf((self.pitch as f32 - 64.) / 48.)
     }
 
     // Pushes a value to the stack
-    fn push(&mut self, val: u16) {
+    fn push(&mut self, val: u16) -> Result<(), Chip8Error> {
+        if self.stack_pointer as usize >= STACK_SIZE {
+            return Err(Chip8Error::StackOverflow);
+        }
         self.stack[self.stack_pointer as usize] = val;
         self.stack_pointer += 1;
+        Ok(())
     }
 
     // pops a value off the stack
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, Chip8Error> {
+        if self.stack_pointer == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
         self.stack_pointer -= 1;
-        self.stack[self.stack_pointer as usize]
+        Ok(self.stack[self.stack_pointer as usize])
+    }
+
+    // Reads a byte from ram
+    fn read(&mut self, addr: usize) -> Result<u8, Chip8Error> {
+        if addr >= self.platform.ram_size() {
+            return Err(Chip8Error::MemoryOutOfBounds { addr });
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push((addr as u16, Access::Read));
+        }
+        Ok(self.ram[addr])
+    }
+
+    // Writes a byte to ram
+    fn write(&mut self, addr: usize, val: u8) -> Result<(), Chip8Error> {
+        if addr >= self.platform.ram_size() {
+            return Err(Chip8Error::MemoryOutOfBounds { addr });
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push((addr as u16, Access::Write));
+        }
+        self.ram[addr] = val;
+        Ok(())
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn i_register(&self) -> u16 {
+        self.i_register
     }
 
-    // Get display
-    pub fn get_display(&self) -> &[bool] {
-        &self.screen
+    pub fn v_registers(&self) -> &[u8; NUM_REGS] {
+        &self.v_registers
+    }
+
+    // Return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_pointer as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // Addressable ram for the current platform
+    pub fn memory(&self) -> &[u8] {
+        &self.ram[..self.platform.ram_size()]
+    }
+
+    // Get display at the current resolution
+    pub fn get_display(&self) -> Frame<'_> {
+        let (width, height) = (self.width(), self.height());
+        Frame {
+            width,
+            height,
+            pixels: &self.screen[..width * height],
+        }
+    }
+
+    // Current display width
+    fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    // Current display height
+    fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
     }
 
     pub fn keypress(&mut self, index: usize, pressed: bool) {
         self.keypad[index] = pressed;
     }
 
-    // Loads game rom into ram
-    pub fn load_rom(&mut self, data: &[u8]) {
-        let start = START_ADDRESS as usize;
-        let end = (START_ADDRESS as usize) + data.len();
-        self.ram[start..end].copy_from_slice(data);
+    // Keys held as a bitmask, bit N is key N
+    pub fn keys(&self) -> u16 {
+        (0..NUM_KEYS).fold(0, |keys, i| keys | (self.keypad[i] as u16) << i)
     }
 
+    // Presses exactly the keys in a bitmask from keys
+    pub fn set_keys(&mut self, keys: u16) {
+        for i in 0..NUM_KEYS {
+            self.keypress(i, keys & (1 << i) != 0);
+        }
+    }
+
+    // Loads a rom into ram and starts execution at its load address
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), Chip8Error> {
+        rom.check(self.platform)?;
+        let start = rom.load_address() as usize;
+        self.ram[start..start + rom.len()].copy_from_slice(rom.data());
+        self.program_counter = rom.load_address();
+        Ok(())
+    }
+
+    // Starts or stops recording each instruction tick executes
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.trace_log = enabled.then(Vec::new);
+    }
+
+    // Instructions executed since tracing started or the last call, oldest
+    // first
+    pub fn take_trace(&mut self) -> Vec<TraceEntry> {
+        self.trace_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    // Called at 60 Hz, which also starts a new frame of VIP cycles
     pub fn tick_timers(&mut self) {
+        self.cycles_left = VIP_FRAME_BUDGET;
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                // BEEP
-            }
             self.sound_timer -= 1;
         }
     }
 
-    pub fn tick(&mut self) {
+    pub fn tick(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.is_waiting_for_frame() {
+            return Ok(StepOutcome::WaitingForFrame);
+        }
+
         // Fetch
-        let opcode = self.fetch();
+        let pc = self.program_counter;
+        let opcode = self.fetch()?;
         // Decode
-        let digits = self.decode(opcode);
+        let instruction = Instruction::decode(opcode);
         // Execute
-        self.execute(digits, opcode);
+        let before = self.trace_log.is_some().then(|| TraceRegisters::of(self));
+        let next = self.program_counter;
+        let result = self.execute(instruction);
+
+        // Instructions that fail are traced too, they're the interesting ones
+        if let Some(before) = before {
+            let after = TraceRegisters::of(self);
+            if let Some(log) = &mut self.trace_log {
+                log.push(TraceEntry {
+                    pc,
+                    opcode,
+                    before,
+                    after,
+                });
+            }
+        }
+        let outcome = result?;
+
+        if self.timing == Timing::Vip {
+            let skipped = instruction.is_skip() && self.program_counter != next;
+            let cycles = timing::vip_cycles(self, instruction, skipped);
+            self.cycles_left = self.cycles_left.saturating_sub(cycles);
+            // Sprites are drawn during the vertical blank, ending the frame
+            if let Instruction::Draw(..) = instruction {
+                self.cycles_left = 0;
+            }
+        }
+        Ok(outcome)
     }
 
-    fn fetch(&mut self) -> u16 {
-        // Fetches the first byte of the opcode
-        let first_half = self.ram[self.program_counter as usize] as u16;
-
-        // Fetches the second byte of the opcode
-        let second_half = self.ram[(self.program_counter + 1) as usize] as u16;
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let pc = self.program_counter;
+        let opcode = self.peek(pc)?;
 
         // Increments Program counter by 2
-        self.program_counter += 2;
+        self.program_counter = pc.wrapping_add(2);
 
-        // combines the two bytes into the full opcode
-        (first_half << 8) | second_half
+        Ok(opcode)
+    }
+
+    // Skips the next instruction, which is 4 bytes long for XO-CHIP's F000 NNNN.
+    // That runs on every platform, like the other extensions, so is skipped
+    // whole on every platform too.
+    fn skip(&mut self) {
+        let pc = self.program_counter;
+        if self.peek(pc) == Ok(0xF000) {
+            self.program_counter = pc.wrapping_add(4);
+        } else {
+            self.program_counter = pc.wrapping_add(2);
+        }
     }
 
-    // Decodes opcode by separating it out into induvidual digits
-    fn decode(&mut self, opcode: u16) -> (u16, u16, u16, u16) {
-        (
-            (opcode & 0xF000) >> 12,
-            (opcode & 0x0F00) >> 8,
-            (opcode & 0x00F0) >> 4,
-            (opcode & 0x000F),
-        )
+    // Reads the opcode at an address without executing it
+    fn peek(&self, addr: u16) -> Result<u16, Chip8Error> {
+        if addr as usize + 1 >= self.platform.ram_size() {
+            return Err(Chip8Error::PcOutOfBounds { pc: addr });
+        }
+
+        // Fetches the first byte of the opcode
+        let first_half = self.ram[addr as usize] as u16;
+
+        // Fetches the second byte of the opcode
+        let second_half = self.ram[addr as usize + 1] as u16;
+
+        // combines the two bytes into the full opcode
+        Ok((first_half << 8) | second_half)
     }
 
-    fn execute(&mut self, digits: (u16, u16, u16, u16), opcode: u16) {
-        match digits {
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, Chip8Error> {
+        match instruction {
             // Do nothing
-            (0, 0, 0, 0) => return,
+            Instruction::Nop => (),
 
             // Clear screen
-            (0, 0, 0xE, 0) => self.cls(),
+            Instruction::Cls => self.cls(),
 
             // Return from subroutine
-            (0, 0, 0xE, 0xE) => self.ret(),
+            Instruction::Ret => self.ret()?,
+
+            // Scroll display down N lines
+            Instruction::ScrollDown(n) => self.scroll(0, n as isize),
+
+            // Scroll display up N lines
+            Instruction::ScrollUp(n) => self.scroll(0, -(n as isize)),
+
+            // Scroll display right 4 pixels
+            Instruction::ScrollRight => self.scroll(4, 0),
+
+            // Scroll display left 4 pixels
+            Instruction::ScrollLeft => self.scroll(-4, 0),
+
+            // Exit the interpreter
+            Instruction::Exit => return Ok(self.exit()),
+
+            // Switch to low resolution
+            Instruction::Lores => self.set_hires(false),
+
+            // Switch to high resolution
+            Instruction::Hires => self.set_hires(true),
 
             // Jump to new address
-            (1, _, _, _) => self.jmp(opcode),
+            Instruction::Jump(nnn) => self.jmp(nnn),
 
             // Call subroutine
-            (2, _, _, _) => self.call(opcode),
+            Instruction::Call(nnn) => self.call(nnn)?,
 
             // Skip a line if VX == NN
-            (3, _, _, _) => self.skip_eq_nn(digits.1, opcode),
+            Instruction::SkipEqByte(x, nn) => self.skip_eq_nn(x, nn),
 
             // Skip a line if VX != NN
-            (4, _, _, _) => self.skip_neq_nn(digits.1, opcode),
+            Instruction::SkipNeByte(x, nn) => self.skip_neq_nn(x, nn),
 
             // Skip a line if VX == VY
-            (5, _, _, 0) => self.skip_eq_vy(digits.1, digits.2),
+            Instruction::SkipEqReg(x, y) => self.skip_eq_vy(x, y),
+
+            // Store VX -> VY in ram
+            Instruction::SaveRange(x, y) => self.store_range(x, y)?,
+
+            // Load VX -> VY from ram
+            Instruction::LoadRange(x, y) => self.load_range(x, y)?,
 
             // Set VX to NN
-            (6, _, _, _) => self.set_nn(digits.1, opcode),
+            Instruction::LoadByte(x, nn) => self.set_nn(x, nn),
 
             // Increment VX by NN
-            (7, _, _, _) => self.incr_nn(digits.1, opcode),
+            Instruction::AddByte(x, nn) => self.incr_nn(x, nn),
 
             // Set VX to VY
-            (8, _, _, 0) => self.set_vy(digits.1, digits.2),
+            Instruction::LoadReg(x, y) => self.set_vy(x, y),
 
             // Apply bitwise OR to VX using VY
-            (8, _, _, 1) => self.or(digits.1, digits.2),
+            Instruction::Or(x, y) => self.or(x, y),
 
             // Apply bitwise AND to VX using VY
-            (8, _, _, 2) => self.and(digits.1, digits.2),
+            Instruction::And(x, y) => self.and(x, y),
 
             // Apply bitwise XOR to VX using VY
-            (8, _, _, 3) => self.xor(digits.1, digits.2),
+            Instruction::Xor(x, y) => self.xor(x, y),
 
             // Increment VX by VY
-            (8, _, _, 4) => self.incr_vy(digits.1, digits.2),
+            Instruction::AddReg(x, y) => self.incr_vy(x, y),
 
             // Decrease VX by VY
-            (8, _, _, 5) => self.decr_vy(digits.1, digits.2),
+            Instruction::Sub(x, y) => self.decr_vy(x, y),
 
             // Binary right shift VX
-            (8, _, _, 6) => self.brs(digits.1),
+            Instruction::ShiftRight(x, y) => self.brs(x, y),
 
             // Set VX to VY - VX
-            (8, _, _, 7) => self.sub_vx(digits.1, digits.2),
+            Instruction::SubReverse(x, y) => self.sub_vx(x, y),
 
             // Binary left shift VX
-            (8, _, _, 0xE) => self.bls(digits.1),
+            Instruction::ShiftLeft(x, y) => self.bls(x, y),
 
             // Skip a line if VX != VY
-            (9, _, _, 0) => self.skip_neq_vy(digits.1, digits.2),
+            Instruction::SkipNeReg(x, y) => self.skip_neq_vy(x, y),
 
             // Set value of I register to value in opcode
-            (0xA, _, _, _) => self.seti(opcode),
+            Instruction::LoadI(nnn) => self.seti(nnn),
 
-            // Set program counter to V0 + value in opcode
-            (0xB, _, _, _) => self.setpc(opcode),
+            // Set program counter to V0 (or VX) + value in opcode
+            Instruction::JumpOffset(x, nnn) => self.setpc(x, nnn),
 
             // Set VX to random number & value in opcode
-            (0xC, _, _, _) => self.rand(digits.1, opcode),
+            Instruction::Random(x, nn) => self.rand(x, nn),
 
             // Draw sprite
-            (0xD, _, _, _) => self.draw(digits.1, digits.2, digits.3),
+            Instruction::Draw(x, y, n) => self.draw(x, y, n)?,
 
             // Skip if key pressed
-            (0xE, _, 9, 0xE) => self.skip_kp(digits.1),
+            Instruction::SkipKey(x) => self.skip_kp(x)?,
 
             // Skip if key not pressed
-            (0xE, _, 0xA, 1) => self.skip_knp(digits.1),
+            Instruction::SkipNotKey(x) => self.skip_knp(x)?,
+
+            // Set I register to the following 16 bit word
+            Instruction::LoadILong => self.seti_long()?,
+
+            // Select bitplanes to draw to
+            Instruction::Plane(n) => self.select_planes(n),
+
+            // Load the audio pattern buffer from ram
+            Instruction::Audio => self.load_audio_pattern()?,
 
             // Set VX to delay timer
-            (0xF, _, 0, 7) => self.vx_to_dt(digits.1),
+            Instruction::LoadDelay(x) => self.vx_to_dt(x),
 
             // Wait for key press
-            (0xF, _, 0, 0xA) => self.wait(digits.1),
+            Instruction::WaitKey(x) => return Ok(self.wait(x)),
 
             // Set the delay timer to VX
-            (0xF, _, 1, 5) => self.dt_to_vx(digits.1),
+            Instruction::SetDelay(x) => self.dt_to_vx(x),
 
             // Set the sound timer to VX
-            (0xF, _, 1, 8) => self.st_to_vx(digits.1),
+            Instruction::SetSound(x) => self.st_to_vx(x),
 
             // Increment the I register by VX
-            (0xF, _, 1, 0xE) => self.incr_i(digits.1),
+            Instruction::AddI(x) => self.incr_i(x),
 
             // Set I register to font address
-            (0xF, _, 2, 9) => self.seti_font(digits.1),
+            Instruction::Font(x) => self.seti_font(x),
+
+            // Set I register to big font address
+            Instruction::BigFont(x) => self.seti_big_font(x),
+
+            // Set the audio pitch to VX
+            Instruction::Pitch(x) => self.set_pitch(x),
 
             // Store the Binary coded decimal of VX in ram
-            (0xF, _, 3, 3) => self.bcd(digits.1),
+            Instruction::Bcd(x) => self.bcd(x)?,
 
             // Store V0 -> VX in ram
-            (0xF, _, 5, 5) => self.store_v(digits.1),
+            Instruction::Store(x) => self.store_v(x)?,
 
             // Load V0 -> VX from ram
-            (0xF, _, 6, 5) => self.load_v(digits.1),
+            Instruction::Load(x) => self.load_v(x)?,
 
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {:?}", digits),
+            // Store V0 -> VX in RPL flags
+            Instruction::StoreFlags(x) => self.store_rpl(x),
+
+            // Load V0 -> VX from RPL flags
+            Instruction::LoadFlags(x) => self.load_rpl(x),
+
+            Instruction::Unknown(opcode) => {
+                return Err(Chip8Error::UnknownOpcode {
+                    pc: self.program_counter.wrapping_sub(2),
+                    opcode,
+                })
+            }
         }
+
+        Ok(StepOutcome::Continue)
     }
 
-    // Clears screen
+    // Clears the selected bitplanes
     fn cls(&mut self) {
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT]
+        let mask = !self.planes;
+        for pixel in self.screen.iter_mut() {
+            *pixel &= mask;
+        }
+    }
+
+    // Scrolls the selected bitplanes by dx, dy pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width(), self.height());
+        let mask = self.planes;
+        let old = self.screen;
+
+        for y in 0..height {
+            for x in 0..width {
+                let sx = x as isize - dx;
+                let sy = y as isize - dy;
+                let moved = if sx >= 0 && sy >= 0 && (sx as usize) < width && (sy as usize) < height
+                {
+                    old[sx as usize + width * sy as usize] & mask
+                } else {
+                    0
+                };
+
+                let index = x + width * y;
+                self.screen[index] = (self.screen[index] & !mask) | moved;
+            }
+        }
+    }
+
+    // Stops execution by staying on the exit instruction
+    fn exit(&mut self) -> StepOutcome {
+        self.program_counter = self.program_counter.wrapping_sub(2);
+        StepOutcome::Exit
+    }
+
+    // Switches display resolution, clearing the screen
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.screen = [0; HIRES_WIDTH * HIRES_HEIGHT];
+    }
+
+    // Selects which bitplanes drawing instructions affect
+    fn select_planes(&mut self, x: u8) {
+        self.planes = x & 0b11;
     }
 
     // Returns from a subroutine
-    fn ret(&mut self) {
-        let return_address = self.pop();
+    fn ret(&mut self) -> Result<(), Chip8Error> {
+        let return_address = self.pop()?;
         self.program_counter = return_address;
+        Ok(())
     }
 
     // Jumps to new address
-    fn jmp(&mut self, opcode: u16) {
-        let new_address = opcode & 0xFFF;
+    fn jmp(&mut self, new_address: u16) {
         self.program_counter = new_address;
     }
 
     // Calls a subroutine
-    fn call(&mut self, opcode: u16) {
-        let subroutine_address = opcode & 0xFFF;
-        self.push(self.program_counter);
+    fn call(&mut self, subroutine_address: u16) -> Result<(), Chip8Error> {
+        self.push(self.program_counter)?;
         self.program_counter = subroutine_address;
+        Ok(())
     }
 
     // Skips a line if VX == NN
-    fn skip_eq_nn(&mut self, x: u16, opcode: u16) {
-        let nn = (opcode & 0xFF) as u8;
+    fn skip_eq_nn(&mut self, x: u8, nn: u8) {
         if self.v_registers[x as usize] == nn {
-            self.program_counter += 2
+            self.skip()
         }
     }
 
     // Skips a line if VX != NN
-    fn skip_neq_nn(&mut self, x: u16, opcode: u16) {
-        let nn = (opcode & 0xFF) as u8;
+    fn skip_neq_nn(&mut self, x: u8, nn: u8) {
         if self.v_registers[x as usize] != nn {
-            self.program_counter += 2
+            self.skip()
         }
     }
 
     // Skips a line if VX == VY
-    fn skip_eq_vy(&mut self, x: u16, y: u16) {
+    fn skip_eq_vy(&mut self, x: u8, y: u8) {
         if self.v_registers[x as usize] == self.v_registers[y as usize] {
-            self.program_counter += 2
+            self.skip()
         }
     }
 
     // Sets VX to NN
-    fn set_nn(&mut self, x: u16, opcode: u16) {
-        let new_value = (opcode & 0xFF) as u8;
+    fn set_nn(&mut self, x: u8, new_value: u8) {
         self.v_registers[x as usize] = new_value;
     }
 
     // Increments VX by NN
-    fn incr_nn(&mut self, x: u16, opcode: u16) {
-        let increment_by = (opcode & 0xFF) as u8;
+    fn incr_nn(&mut self, x: u8, increment_by: u8) {
         self.v_registers[x as usize] = self.v_registers[x as usize].wrapping_add(increment_by);
     }
 
     // Sets VX to VY
-    fn set_vy(&mut self, x: u16, y: u16) {
+    fn set_vy(&mut self, x: u8, y: u8) {
         self.v_registers[x as usize] = self.v_registers[y as usize]
     }
 
     // Applies bitwise OR to VX using VY
-    fn or(&mut self, x: u16, y: u16) {
+    fn or(&mut self, x: u8, y: u8) {
         self.v_registers[x as usize] |= self.v_registers[y as usize];
+        self.reset_vf();
     }
 
     // Applies bitwise AND to VX using VY
-    fn and(&mut self, x: u16, y: u16) {
+    fn and(&mut self, x: u8, y: u8) {
         self.v_registers[x as usize] &= self.v_registers[y as usize];
+        self.reset_vf();
     }
 
     // Applies bitwise XOR to VX using VY
-    fn xor(&mut self, x: u16, y: u16) {
+    fn xor(&mut self, x: u8, y: u8) {
         self.v_registers[x as usize] ^= self.v_registers[y as usize];
+        self.reset_vf();
+    }
+
+    // Resets VF after a logic instruction if the quirk is enabled
+    fn reset_vf(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v_registers[0xF] = 0;
+        }
     }
 
     // Increments VX by VY
-    fn incr_vy(&mut self, x: u16, y: u16) {
+    fn incr_vy(&mut self, x: u8, y: u8) {
         let (new_vx, carry) =
             self.v_registers[x as usize].overflowing_add(self.v_registers[y as usize]);
         let new_vf = if carry { 1 } else { 0 };
@@ -353,7 +818,7 @@ impl CPU {
     }
 
     // Decreases VX by VY
-    fn decr_vy(&mut self, x: u16, y: u16) {
+    fn decr_vy(&mut self, x: u8, y: u8) {
         let (new_vx, borrow) =
             self.v_registers[x as usize].overflowing_sub(self.v_registers[y as usize]);
         let new_vf = if borrow { 0 } else { 1 };
@@ -363,14 +828,14 @@ impl CPU {
     }
 
     // Binary right shifts VX
-    fn brs(&mut self, x: u16) {
-        let lsb = self.v_registers[x as usize] & 1;
-        self.v_registers[x as usize] >>= 1;
-        self.v_registers[0xF] = lsb;
+    fn brs(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.v_registers[x as usize] = value >> 1;
+        self.v_registers[0xF] = value & 1;
     }
 
     // Sets VX to be VY - VX
-    fn sub_vx(&mut self, x: u16, y: u16) {
+    fn sub_vx(&mut self, x: u8, y: u8) {
         let (new_vx, borrow) =
             self.v_registers[y as usize].overflowing_sub(self.v_registers[x as usize]);
         let new_vf = if borrow { 0 } else { 1 };
@@ -380,102 +845,142 @@ impl CPU {
     }
 
     // Binary left shifts VX
-    fn bls(&mut self, x: u16) {
-        let msb = (self.v_registers[x as usize] >> 7) & 1;
-        self.v_registers[x as usize] <<= 1;
-        self.v_registers[0xF] = msb;
+    fn bls(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.v_registers[x as usize] = value << 1;
+        self.v_registers[0xF] = (value >> 7) & 1;
+    }
+
+    // Gets the register a shift instruction operates on
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v_registers[y as usize]
+        } else {
+            self.v_registers[x as usize]
+        }
     }
 
     // Skips a line if VX != VY
-    fn skip_neq_vy(&mut self, x: u16, y: u16) {
+    fn skip_neq_vy(&mut self, x: u8, y: u8) {
         if self.v_registers[x as usize] != self.v_registers[y as usize] {
-            self.program_counter += 2
+            self.skip()
         }
     }
 
     // Sets the I register to be the value encoded in the opcode
-    fn seti(&mut self, opcode: u16) {
-        let next_i = opcode & 0xFFF;
+    fn seti(&mut self, next_i: u16) {
         self.i_register = next_i;
     }
 
-    // Sets the program counter to V0 + value in opcode
-    fn setpc(&mut self, opcode: u16) {
-        let opcode_value = opcode & 0xFFF;
-        self.program_counter = (self.v_registers[0] as u16) + opcode_value;
+    // Sets the program counter to V0 (or VX) + value in opcode
+    fn setpc(&mut self, x: u8, opcode_value: u16) {
+        let offset = if self.quirks.jump_uses_vx {
+            self.v_registers[x as usize]
+        } else {
+            self.v_registers[0]
+        };
+        self.program_counter = (offset as u16) + opcode_value;
     }
 
     // Sets VX to be random number & value in opcode
-    fn rand(&mut self, x: u16, opcode: u16) {
-        let opcode_value = (opcode & 0xFF) as u8;
-        let rng: u8 = random();
+    fn rand(&mut self, x: u8, opcode_value: u8) {
+        let rng = self.rng.next_u8();
         self.v_registers[x as usize] = rng & opcode_value;
     }
 
-    // Draws a sprite
-    fn draw(&mut self, x: u16, y: u16, z: u16) {
-        // Get x, y coords of the sprite
-        let x_coord = self.v_registers[x as usize] as u16;
-        let y_coord = self.v_registers[y as usize] as u16;
+    // Draws a sprite, a height of 0 draws a 16x16 SUPER-CHIP sprite
+    // With several bitplanes selected the sprite data for each plane follows the last
+    fn draw(&mut self, x: u8, y: u8, z: u8) -> Result<(), Chip8Error> {
+        let (width, height) = (self.width(), self.height());
+
+        // Get x, y coords of the sprite, the starting position always wraps
+        let x_coord = self.v_registers[x as usize] as usize % width;
+        let y_coord = self.v_registers[y as usize] as usize % height;
 
-        // Get height of sprite
-        let num_rows = z;
+        // Get size of sprite
+        let (num_rows, num_cols) = if z == 0 { (16, 16) } else { (z as usize, 8) };
+        let bytes_per_row = num_cols / 8;
+        let sprite_size = num_rows * bytes_per_row;
 
         // Keeps track of if any pixels are flipped
         let mut flipped = false;
 
-        // Iterates over all the rows in the sprite
-
-        for y_line in 0..num_rows {
-            // Get rows memory address
-            let addr = self.i_register + y_line as u16;
-            let pixels = self.ram[addr as usize];
+        // Get the start of the sprite data
+        let mut addr = self.i_register as usize;
 
-            // Iterates through each column in the row
-            for x_line in 0..8 {
-                // Use a mask to get the current pixels bit, only flip it if it is a 1
-                if (pixels & (0b10000000 >> x_line)) != 0 {
-                    let sx = (x_coord + x_line) as usize % SCREEN_WIDTH;
-                    let sy = (y_coord + y_line) as usize % SCREEN_HEIGHT;
+        // Iterates over each selected bitplane
+        for plane in [1u8, 2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
 
-                    let index = sx + SCREEN_WIDTH * sy;
+            // Iterates over all the rows in the sprite
+            for y_line in 0..num_rows {
+                // Get rows memory address
+                let row_addr = addr + y_line * bytes_per_row;
+                let mut pixels = 0u16;
+                for byte in 0..bytes_per_row {
+                    pixels = (pixels << 8) | self.read(row_addr + byte)? as u16;
+                }
 
-                    flipped |= self.screen[index];
-                    self.screen[index] ^= true;
+                // Iterates through each column in the row
+                for x_line in 0..num_cols {
+                    // Use a mask to get the current pixels bit, only flip it if it is a 1
+                    if (pixels & (1 << (num_cols - 1 - x_line))) != 0 {
+                        let sx = x_coord + x_line;
+                        let sy = y_coord + y_line;
+
+                        // Pixels off the edge are either dropped or wrapped around
+                        if self.quirks.clip_sprites && (sx >= width || sy >= height) {
+                            continue;
+                        }
+                        let index = (sx % width) + width * (sy % height);
+
+                        flipped |= self.screen[index] & plane != 0;
+                        self.screen[index] ^= plane;
+                    }
                 }
             }
-        }
 
-        if flipped {
-            self.v_registers[0xF] = 1;
-        } else {
-            self.v_registers[0xF] = 0;
+            addr += sprite_size;
         }
+
+        self.v_registers[0xF] = flipped as u8;
+        Ok(())
     }
 
     // Skips if a key is pressed
-    fn skip_kp(&mut self, x: u16) {
-        let key = self.v_registers[x as usize];
-        if self.keypad[key as usize] {
-            self.program_counter += 2
+    fn skip_kp(&mut self, x: u8) -> Result<(), Chip8Error> {
+        if self.key_pressed(x)? {
+            self.skip()
         }
+        Ok(())
     }
 
     // Skips if a key is not pressed
-    fn skip_knp(&mut self, x: u16) {
-        let key = self.v_registers[x as usize];
-        if !self.keypad[key as usize] {
-            self.program_counter += 2
+    fn skip_knp(&mut self, x: u8) -> Result<(), Chip8Error> {
+        if !self.key_pressed(x)? {
+            self.skip()
         }
+        Ok(())
+    }
+
+    // Checks if the key stored in VX is pressed
+    fn key_pressed(&self, x: u8) -> Result<bool, Chip8Error> {
+        let key = self.v_registers[x as usize];
+        self.keypad
+            .get(key as usize)
+            .copied()
+            .ok_or(Chip8Error::InvalidKey { key })
     }
 
     // Sets the value of VX to the delay counter
-    fn vx_to_dt(&mut self, x: u16) {
+    fn vx_to_dt(&mut self, x: u8) {
         self.v_registers[x as usize] = self.delay_timer;
     }
 
     // Waits for a key to be pressed
-    fn wait(&mut self, x: u16) {
+    fn wait(&mut self, x: u8) -> StepOutcome {
         let mut pressed = false;
         for i in 0..self.keypad.len() {
             if self.keypad[i] {
@@ -486,58 +991,212 @@ impl CPU {
         }
 
         if !pressed {
-            self.program_counter -= 2;
+            self.program_counter = self.program_counter.wrapping_sub(2);
+            return StepOutcome::WaitingForKey;
         }
+        StepOutcome::Continue
     }
 
     // Sets the delay timer to VX
-    fn dt_to_vx(&mut self, x: u16) {
+    fn dt_to_vx(&mut self, x: u8) {
         self.delay_timer = self.v_registers[x as usize];
     }
 
     // Sets the sound timer to VX
-    fn st_to_vx(&mut self, x: u16) {
+    fn st_to_vx(&mut self, x: u8) {
         self.sound_timer = self.v_registers[x as usize];
     }
 
     // Increments the I register by VX
-    fn incr_i(&mut self, x: u16) {
+    fn incr_i(&mut self, x: u8) {
         let increment_by = self.v_registers[x as usize] as u16;
         self.i_register = self.i_register.wrapping_add(increment_by);
     }
 
     // Sets the I register to a font address
-    fn seti_font(&mut self, x: u16) {
+    fn seti_font(&mut self, x: u8) {
         let number = self.v_registers[x as usize] as u16;
         self.i_register = number * 5;
     }
 
+    // Sets the I register to the word following the opcode
+    fn seti_long(&mut self) -> Result<(), Chip8Error> {
+        self.i_register = self.peek(self.program_counter)?;
+        self.program_counter = self.program_counter.wrapping_add(2);
+        Ok(())
+    }
+
+    // Loads 16 bytes from ram into the audio pattern buffer
+    fn load_audio_pattern(&mut self) -> Result<(), Chip8Error> {
+        let i = self.i_register as usize;
+        for index in 0..AUDIO_PATTERN_SIZE {
+            self.audio_pattern[index] = self.read(i + index)?;
+        }
+        Ok(())
+    }
+
+    // Sets the audio pitch to VX
+    fn set_pitch(&mut self, x: u8) {
+        self.pitch = self.v_registers[x as usize];
+    }
+
+    // Sets the I register to a big font address
+    fn seti_big_font(&mut self, x: u8) {
+        let number = (self.v_registers[x as usize] & 0xF) as u16;
+        self.i_register = BIG_FONT_ADDRESS as u16 + number * 10;
+    }
+
     // Stores the Binary Coded decimal of VX in ram
-    fn bcd(&mut self, x: u16) {
+    fn bcd(&mut self, x: u8) -> Result<(), Chip8Error> {
         let vx = self.v_registers[x as usize] as f32;
 
         let hundreds = (vx / 100.).floor() as u8;
         let tens = ((vx / 10.) % 10.).floor() as u8;
         let units = (vx % 10.).floor() as u8;
 
-        self.ram[self.i_register as usize] = hundreds;
-        self.ram[(self.i_register + 1) as usize] = tens;
-        self.ram[(self.i_register + 2) as usize] = units;
+        let i = self.i_register as usize;
+        self.write(i, hundreds)?;
+        self.write(i + 1, tens)?;
+        self.write(i + 2, units)
     }
 
     // Stores V0 -> VX in ram
-    fn store_v(&mut self, x: u16) {
+    fn store_v(&mut self, x: u8) -> Result<(), Chip8Error> {
         let i = self.i_register as usize;
         for index in 0..=x as usize {
-            self.ram[i + index] = self.v_registers[index];
+            self.write(i + index, self.v_registers[index])?;
         }
+        self.advance_i(x);
+        Ok(())
     }
 
     // Loads V0 -> VX from ram
-    fn load_v(&mut self, x: u16) {
+    fn load_v(&mut self, x: u8) -> Result<(), Chip8Error> {
         let i = self.i_register as usize;
         for index in 0..=x as usize {
-            self.v_registers[index] = self.ram[i + index];
+            self.v_registers[index] = self.read(i + index)?;
+        }
+        self.advance_i(x);
+        Ok(())
+    }
+
+    // Stores VX -> VY in ram without changing I, in either order
+    fn store_range(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let i = self.i_register as usize;
+        for (offset, reg) in register_range(x, y).into_iter().enumerate() {
+            self.write(i + offset, self.v_registers[reg])?;
+        }
+        Ok(())
+    }
+
+    // Loads VX -> VY from ram without changing I, in either order
+    fn load_range(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let i = self.i_register as usize;
+        for (offset, reg) in register_range(x, y).into_iter().enumerate() {
+            self.v_registers[reg] = self.read(i + offset)?;
+        }
+        Ok(())
+    }
+
+    // Stores V0 -> VX in the RPL user flags
+    fn store_rpl(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.v_registers[..count]);
+    }
+
+    // Loads V0 -> VX from the RPL user flags
+    fn load_rpl(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.v_registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+    }
+
+    // Moves I past the registers used by FX55/FX65 if the quirk is enabled
+    fn advance_i(&mut self, x: u8) {
+        if self.quirks.load_store_increments_i {
+            let by_x = self.quirks.load_store_increments_i_by_x;
+            self.i_register = self.i_register.wrapping_add(x as u16 + !by_x as u16);
+        }
+    }
+}
+
+// Registers from X to Y inclusive, counting down if X > Y
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cpu about to run opcode from the last word of XO-CHIP's 64 KiB,
+    // where fetch wraps the program counter round to 0
+    fn at_end_of_ram(opcode: u16) -> CPU {
+        let mut cpu = CPU::new(Platform::XoChip, Quirks::xochip(), Some(0));
+        cpu.ram[0xFFFE..].copy_from_slice(&opcode.to_be_bytes());
+        cpu.program_counter = 0xFFFE;
+        cpu
+    }
+
+    #[test]
+    fn exit_at_end_of_ram_stays_put() {
+        let mut cpu = at_end_of_ram(0x00FD);
+        assert_eq!(cpu.tick(), Ok(StepOutcome::Exit));
+        assert_eq!(cpu.program_counter(), 0xFFFE);
+    }
+
+    #[test]
+    fn key_wait_at_end_of_ram_stays_put() {
+        let mut cpu = at_end_of_ram(0xF00A);
+        assert_eq!(cpu.tick(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(cpu.program_counter(), 0xFFFE);
+    }
+
+    #[test]
+    fn unknown_opcode_at_end_of_ram_reports_its_address() {
+        let mut cpu = at_end_of_ram(0xE000);
+        assert_eq!(
+            cpu.tick(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0xFFFE,
+                opcode: 0xE000
+            })
+        );
+    }
+
+    #[test]
+    fn skips_over_long_loads_on_every_platform() {
+        for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            let mut cpu = CPU::new(platform, platform.default_quirks(), Some(0));
+            // SE V0, 0 then LD I, LONG 0x1234
+            cpu.ram[0x200..0x208]
+                .copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]);
+            cpu.tick().unwrap();
+            assert_eq!(cpu.program_counter(), 0x206, "{:?}", platform);
+
+            // And runs them whole when not skipped
+            cpu.program_counter = 0x202;
+            cpu.tick().unwrap();
+            assert_eq!((cpu.program_counter(), cpu.i_register), (0x206, 0x1234));
+        }
+    }
+
+    #[test]
+    fn store_moves_i_as_each_preset_says() {
+        for (quirks, i) in [
+            (Quirks::vip(), 0x304),
+            (Quirks::chip48(), 0x303),
+            (Quirks::schip(), 0x300),
+        ] {
+            let mut cpu = CPU::new(Platform::Chip8, quirks, Some(0));
+            cpu.ram[0x200..0x204].copy_from_slice(&[0xA3, 0x00, 0xF3, 0x55]);
+            cpu.tick().unwrap();
+            cpu.tick().unwrap();
+            assert_eq!(cpu.i_register, i, "{:?}", quirks);
         }
     }
 }
@@ -0,0 +1,350 @@
+use crate::checksum::{crc32, sha1};
+use crate::state::{
+    platform_from_byte, platform_to_byte, quirks_from_byte, quirks_to_byte, timing_from_byte,
+    timing_to_byte, Reader,
+};
+use crate::{Platform, Quirks, Rom, StateError, Timing, CPU};
+use std::fmt;
+
+// Movie layout, all values little endian:
+//   magic, version, rom SHA-1, seed, platform, quirks, timing, IPS,
+//   load address, hash interval, keypad bitmask for every frame, state
+//   hashes, then a CRC-32 of all of it
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 2;
+const CHECKSUM_SIZE: usize = 4;
+// Frames between state hashes
+const HASH_INTERVAL: u16 = 60;
+
+// Errors raised when a movie can't be read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieError {
+    // Data doesn't start with the movie magic
+    BadMagic,
+    // Written by a newer or older format
+    UnsupportedVersion(u16),
+    // Data has been corrupted
+    ChecksumMismatch,
+    // Data ends before the movie is complete
+    Truncated,
+    // A field holds a value that can't be played back
+    Invalid,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie version {}", version)
+            }
+            MovieError::ChecksumMismatch => write!(f, "movie is corrupted"),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::Invalid => write!(f, "movie contains invalid values"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+// Movies are read with the save state reader
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::Truncated => MovieError::Truncated,
+            _ => MovieError::Invalid,
+        }
+    }
+}
+
+// The keys held during every frame of a run, plus everything needed to
+// start the same run again. A frame is one 60 Hz timer tick. Replaying
+// only gives the same result when the scheduler runs in lockstep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_sha1: [u8; 20],
+    pub seed: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub ips: u32,
+    pub load_address: u16,
+    hash_interval: u16,
+    frames: Vec<u16>, // Keypad bitmask for each frame
+    hashes: Vec<u32>, // State hash after every hash_interval frames
+}
+
+impl Movie {
+    // Starts recording a run of rom on a CPU that has just loaded it
+    pub fn new(rom: &Rom, cpu: &CPU, ips: u32) -> Movie {
+        Movie {
+            rom_sha1: rom.sha1(),
+            seed: cpu.seed(),
+            platform: cpu.platform(),
+            quirks: cpu.quirks(),
+            timing: cpu.timing(),
+            ips,
+            load_address: rom.load_address(),
+            hash_interval: HASH_INTERVAL,
+            frames: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    // Whether the movie was recorded with this rom
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        sha1(rom) == self.rom_sha1
+    }
+
+    // A CPU set up the way the recording started, ready for the rom
+    pub fn cpu(&self) -> CPU {
+        let mut cpu = CPU::new(self.platform, self.quirks, Some(self.seed));
+        cpu.set_timing(self.timing);
+        cpu
+    }
+
+    // The rom loaded where it was during the recording
+    pub fn rom(&self, data: &[u8]) -> Rom {
+        Rom::with_load_address(data, self.load_address)
+    }
+
+    // Number of frames recorded
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Adds a frame that ran with keys held, leaving cpu as it is now
+    pub fn record(&mut self, keys: u16, cpu: &CPU) {
+        self.frames.push(keys);
+        if self
+            .frames
+            .len()
+            .is_multiple_of(self.hash_interval as usize)
+        {
+            self.hashes.push(cpu.state_hash());
+        }
+    }
+
+    // Keys held during a frame, None past the end of the movie
+    pub fn keys(&self, frame: usize) -> Option<u16> {
+        self.frames.get(frame).copied()
+    }
+
+    // Checks cpu against the recording after a frame has run. Frames
+    // without a stored hash always match.
+    pub fn verify(&self, frame: usize, cpu: &CPU) -> bool {
+        let count = frame + 1;
+        if !count.is_multiple_of(self.hash_interval as usize) {
+            return true;
+        }
+        match self.hashes.get(count / self.hash_interval as usize - 1) {
+            Some(hash) => *hash == cpu.state_hash(),
+            None => true,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        out.extend_from_slice(&self.rom_sha1);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.push(platform_to_byte(self.platform));
+        out.push(quirks_to_byte(self.quirks));
+        out.push(timing_to_byte(self.timing));
+        out.extend_from_slice(&self.ips.to_le_bytes());
+        out.extend_from_slice(&self.load_address.to_le_bytes());
+        out.extend_from_slice(&self.hash_interval.to_le_bytes());
+
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for keys in &self.frames {
+            out.extend_from_slice(&keys.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.hashes.len() as u32).to_le_bytes());
+        for hash in &self.hashes {
+            out.extend_from_slice(&hash.to_le_bytes());
+        }
+
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        if data.len() < MAGIC.len() + 2 + CHECKSUM_SIZE {
+            return Err(MovieError::Truncated);
+        }
+
+        let (body, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+        let version = u16::from_le_bytes([body[4], body[5]]);
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        if crc32(body).to_le_bytes() != checksum {
+            return Err(MovieError::ChecksumMismatch);
+        }
+
+        let mut reader = Reader {
+            data: body,
+            pos: MAGIC.len() + 2,
+        };
+        let rom_sha1 = reader.bytes(20)?.try_into().unwrap();
+        let seed = reader.long()?;
+        let platform = platform_from_byte(reader.byte()?)?;
+        let quirks = quirks_from_byte(reader.byte()?);
+        let timing = timing_from_byte(reader.byte()?)?;
+        let ips = reader.int()?;
+        let load_address = reader.word()?;
+        let hash_interval = reader.word()?;
+        if ips == 0 || hash_interval == 0 {
+            return Err(MovieError::Invalid);
+        }
+
+        let frame_count = reader.int()? as usize;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            frames.push(reader.word()?);
+        }
+        let hash_count = reader.int()? as usize;
+        let mut hashes = Vec::new();
+        for _ in 0..hash_count {
+            hashes.push(reader.int()?);
+        }
+
+        if reader.pos != body.len() {
+            return Err(MovieError::Invalid);
+        }
+
+        Ok(Movie {
+            rom_sha1,
+            seed,
+            platform,
+            quirks,
+            timing,
+            ips,
+            load_address,
+            hash_interval,
+            frames,
+            hashes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOAD_ADDRESS: u16 = 0x300;
+    const IPS: u32 = 600;
+    const FRAMES: usize = 200;
+
+    // Draws random pixels while key 5 isn't held, loaded at 0x300 so its
+    // LD I only finds the sprite when loaded there
+    const PROGRAM: [u8; 17] = [
+        0x61, 0x05, // 300: LD V1, 0x05
+        0xE1, 0x9E, // 302: SKP V1
+        0x72, 0x01, // 304: ADD V2, 0x01
+        0xC3, 0x1F, // 306: RND V3, 0x1F
+        0xA3, 0x10, // 308: LD I, 0x310
+        0xD2, 0x31, // 30A: DRW V2, V3, 1
+        0x13, 0x02, // 30C: JP 0x302
+        0x00, 0x00, // 30E
+        0x80, // 310: sprite
+    ];
+
+    // Key 5 is held for part of the run
+    fn keys(frame: usize) -> u16 {
+        if (50..90).contains(&frame) {
+            1 << 5
+        } else {
+            0
+        }
+    }
+
+    fn run_frame(cpu: &mut CPU, keys: u16) {
+        cpu.set_keys(keys);
+        for _ in 0..IPS / 60 {
+            cpu.tick().unwrap();
+        }
+        cpu.tick_timers();
+    }
+
+    fn record() -> Movie {
+        let rom = Rom::with_load_address(&PROGRAM, LOAD_ADDRESS);
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::chip48(), Some(1234));
+        cpu.load_rom(&rom).unwrap();
+        let mut movie = Movie::new(&rom, &cpu, IPS);
+        for frame in 0..FRAMES {
+            run_frame(&mut cpu, keys(frame));
+            movie.record(keys(frame), &cpu);
+        }
+        movie
+    }
+
+    // Plays a movie back, returning the first frame that doesn't match
+    fn replay(movie: &Movie, rom: &Rom) -> Option<usize> {
+        let mut cpu = movie.cpu();
+        cpu.load_rom(rom).unwrap();
+        (0..movie.len()).find(|&frame| {
+            run_frame(&mut cpu, movie.keys(frame).unwrap());
+            !movie.verify(frame, &cpu)
+        })
+    }
+
+    #[test]
+    fn round_trip_replays_in_sync() {
+        let recorded = record();
+        assert_eq!(recorded.len(), FRAMES);
+        assert_eq!(recorded.hashes.len(), FRAMES / HASH_INTERVAL as usize);
+
+        let movie = Movie::from_bytes(&recorded.to_bytes()).unwrap();
+        assert_eq!(movie, recorded);
+        assert!(movie.matches_rom(&PROGRAM));
+        assert_eq!(movie.load_address, LOAD_ADDRESS);
+        assert_eq!(replay(&movie, &movie.rom(&PROGRAM)), None);
+    }
+
+    #[test]
+    fn replay_at_another_address_desyncs() {
+        let movie = record();
+        let rom = Rom::new(&PROGRAM);
+        assert_eq!(replay(&movie, &rom), Some(HASH_INTERVAL as usize - 1));
+    }
+
+    #[test]
+    fn different_input_desyncs() {
+        let mut movie = record();
+        movie.frames[70] = 0;
+        assert_eq!(replay(&movie, &movie.rom(&PROGRAM)), Some(119));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut data = record().to_bytes();
+        data[4..6].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(
+            Movie::from_bytes(&data),
+            Err(MovieError::UnsupportedVersion(1))
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_movies() {
+        let mut data = record().to_bytes();
+        assert_eq!(
+            Movie::from_bytes(&data[..data.len() - 10]),
+            Err(MovieError::ChecksumMismatch)
+        );
+        data[40] ^= 1;
+        assert_eq!(Movie::from_bytes(&data), Err(MovieError::ChecksumMismatch));
+        assert_eq!(Movie::from_bytes(b"C8ST"), Err(MovieError::BadMagic));
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt;
+
+// A colour, shown as #rrggbb
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    // Parses #rrggbb or the short #rgb, the # is optional
+    pub fn from_hex(text: &str) -> Option<Rgb> {
+        let hex = text.trim().strip_prefix('#').unwrap_or(text.trim());
+        // from_str_radix would also take a + sign
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i: usize, len: usize| u8::from_str_radix(&hex[i * len..(i + 1) * len], 16);
+        match hex.len() {
+            6 => Some(Rgb::new(
+                digit(0, 2).ok()?,
+                digit(1, 2).ok()?,
+                digit(2, 2).ok()?,
+            )),
+            3 => Some(Rgb::new(
+                digit(0, 1).ok()? * 17,
+                digit(1, 1).ok()? * 17,
+                digit(2, 1).ok()? * 17,
+            )),
+            _ => None,
+        }
+    }
+
+    // Part of the way from this colour to another, 0 to 1
+    fn mix(self, other: Rgb, amount: f32) -> Rgb {
+        let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+        Rgb::new(
+            channel(self.r, other.r),
+            channel(self.g, other.g),
+            channel(self.b, other.b),
+        )
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+// Display colours indexed by pixel value, so one for each combination of
+// the two XO-CHIP bitplanes: neither, the first, the second and both.
+// Programs that only draw on the first plane just use the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    // Names accepted by Palette::from_name
+    pub const NAMES: [&'static str; 5] = ["classic", "amber", "lcd", "lime", "octo"];
+
+    // Background and foreground, with the other planes shaded in between
+    pub fn two_color(background: Rgb, foreground: Rgb) -> Palette {
+        Palette {
+            colors: [
+                background,
+                foreground,
+                background.mix(foreground, 2. / 3.),
+                background.mix(foreground, 1. / 3.),
+            ],
+        }
+    }
+
+    // White on black
+    pub fn classic() -> Palette {
+        Palette::two_color(Rgb::new(0, 0, 0), Rgb::new(255, 255, 255))
+    }
+
+    // Amber monochrome monitor
+    pub fn amber() -> Palette {
+        Palette::two_color(Rgb::new(0x1a, 0x0f, 0x00), Rgb::new(0xff, 0xb0, 0x00))
+    }
+
+    // Green reflective LCD, dark pixels on a light screen
+    pub fn lcd() -> Palette {
+        Palette {
+            colors: [
+                Rgb::new(0x9b, 0xbc, 0x0f),
+                Rgb::new(0x0f, 0x38, 0x0f),
+                Rgb::new(0x30, 0x62, 0x30),
+                Rgb::new(0x8b, 0xac, 0x0f),
+            ],
+        }
+    }
+
+    // Green phosphor, the CSS lime, green and darkgreen
+    pub fn lime() -> Palette {
+        Palette {
+            colors: [
+                Rgb::new(0, 0, 0),
+                Rgb::new(0x00, 0xff, 0x00),
+                Rgb::new(0x00, 0x80, 0x00),
+                Rgb::new(0x00, 0x64, 0x00),
+            ],
+        }
+    }
+
+    // Octo's default colours, made for XO-CHIP
+    pub fn octo() -> Palette {
+        Palette {
+            colors: [
+                Rgb::new(0x99, 0x66, 0x00),
+                Rgb::new(0xff, 0xcc, 0x00),
+                Rgb::new(0xff, 0x66, 0x00),
+                Rgb::new(0x66, 0x22, 0x00),
+            ],
+        }
+    }
+
+    // Looks up a preset by name
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name.to_ascii_lowercase().as_str() {
+            "classic" => Some(Palette::classic()),
+            "amber" => Some(Palette::amber()),
+            "lcd" => Some(Palette::lcd()),
+            "lime" => Some(Palette::lime()),
+            "octo" => Some(Palette::octo()),
+            _ => None,
+        }
+    }
+
+    // Parses a preset name or a comma separated list of colours, either
+    // background and foreground or all four
+    pub fn parse(text: &str) -> Option<Palette> {
+        if let Some(palette) = Palette::from_name(text.trim()) {
+            return Some(palette);
+        }
+        let colors = text
+            .split(',')
+            .map(Rgb::from_hex)
+            .collect::<Option<Vec<_>>>()?;
+        Palette::from_colors(&colors)
+    }
+
+    // Background and foreground, or all four colours
+    pub fn from_colors(colors: &[Rgb]) -> Option<Palette> {
+        match *colors {
+            [background, foreground] => Some(Palette::two_color(background, foreground)),
+            [a, b, c, d] => Some(Palette {
+                colors: [a, b, c, d],
+            }),
+            _ => None,
+        }
+    }
+
+    // Colour for a pixel value from CPU::get_display
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors[pixel as usize & 3]
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::classic()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colours() {
+        assert_eq!(Rgb::from_hex("#1a2B3c"), Some(Rgb::new(0x1A, 0x2B, 0x3C)));
+        assert_eq!(Rgb::from_hex("1a2b3c"), Some(Rgb::new(0x1A, 0x2B, 0x3C)));
+        assert_eq!(Rgb::from_hex(" #f80 "), Some(Rgb::new(0xFF, 0x88, 0x00)));
+        assert_eq!(Rgb::from_hex("000"), Some(Rgb::new(0, 0, 0)));
+        for bad in [
+            "", "#", "#ff", "#ffff", "#fffff", "#fffffff", "#ggg", "#+12345", "##fff", "#ffé",
+            "#ééé",
+        ] {
+            assert_eq!(Rgb::from_hex(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn colours_display_as_they_parse() {
+        let colour = Rgb::new(0x0A, 0xBC, 0xFF);
+        assert_eq!(colour.to_string(), "#0abcff");
+        assert_eq!(Rgb::from_hex(&colour.to_string()), Some(colour));
+        for preset in Palette::NAMES.map(|name| Palette::from_name(name).unwrap()) {
+            for colour in preset.colors {
+                assert_eq!(Rgb::from_hex(&colour.to_string()), Some(colour));
+            }
+        }
+    }
+
+    #[test]
+    fn parses_preset_names() {
+        for name in Palette::NAMES {
+            assert_eq!(Palette::parse(name), Palette::from_name(name), "{}", name);
+        }
+        assert_eq!(Palette::parse(" Amber "), Some(Palette::amber()));
+        assert_eq!(Palette::parse("sepia"), None);
+        assert_eq!(Palette::default(), Palette::classic());
+    }
+
+    #[test]
+    fn parses_colour_lists() {
+        // The other planes are shaded between background and foreground
+        let two = Palette::parse("#000000, #ffffff").unwrap();
+        assert_eq!(two, Palette::classic());
+        assert_eq!(two.colors[2], Rgb::new(170, 170, 170));
+        assert_eq!(two.colors[3], Rgb::new(85, 85, 85));
+
+        let four = Palette::parse("#000,#f00,#0f0,#00f").unwrap();
+        assert_eq!(four.color(3), Rgb::new(0, 0, 0xFF));
+        assert_eq!(four.color(0b111), Rgb::new(0, 0, 0xFF));
+        assert_eq!(four.background(), Rgb::new(0, 0, 0));
+
+        for bad in [
+            "#000",
+            "#000,#fff,#f00",
+            "#000,#fff,#f00,#0f0,#00f",
+            "#000,",
+            "#000,amber",
+        ] {
+            assert_eq!(Palette::parse(bad), None, "{}", bad);
+        }
+    }
+}
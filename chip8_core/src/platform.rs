@@ -0,0 +1,48 @@
+use crate::Quirks;
+
+// The machine a rom was written for, ordered oldest first. Each one runs
+// programs for the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    // Names accepted by Platform::from_name
+    pub const NAMES: [&'static str; 3] = ["chip8", "schip", "xochip"];
+
+    // Looks up a platform by name
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // Short name, the one from NAMES
+    pub fn name(self) -> &'static str {
+        Platform::NAMES[self as usize]
+    }
+
+    // Amount of addressable ram
+    pub fn ram_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    // Quirks most roms for the platform expect
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::vip(),
+            Platform::SuperChip => Quirks::schip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+}
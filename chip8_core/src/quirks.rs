@@ -0,0 +1,116 @@
+// Behaviour switches for instructions that differ between CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    // FX55/FX65 leave I pointing after the last register stored/loaded
+    pub load_store_increments_i: bool,
+    // ...but one short, on the last register, as CHIP-48 does
+    pub load_store_increments_i_by_x: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // Sprites are clipped at the screen edge instead of wrapping around
+    pub clip_sprites: bool,
+}
+
+impl Quirks {
+    // Names accepted by Quirks::from_name
+    pub const NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "xochip"];
+
+    // Original COSMAC VIP interpreter
+    pub const fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub const fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: true,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+        }
+    }
+
+    // SUPER-CHIP 1.1
+    pub const fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub const fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_increments_i_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+        }
+    }
+
+    // Looks up a preset by name
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Quirks::vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::schip()),
+            "xochip" | "xo-chip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::vip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip48_and_schip_differ_in_how_i_moves() {
+        let (chip48, schip) = (Quirks::chip48(), Quirks::schip());
+        assert!(chip48.load_store_increments_i && chip48.load_store_increments_i_by_x);
+        assert!(!schip.load_store_increments_i);
+        assert_eq!(
+            Quirks {
+                load_store_increments_i: false,
+                load_store_increments_i_by_x: false,
+                ..chip48
+            },
+            schip
+        );
+    }
+
+    #[test]
+    fn presets_are_all_different() {
+        let presets = Quirks::NAMES.map(|name| Quirks::from_name(name).unwrap());
+        for (i, a) in presets.iter().enumerate() {
+            for b in &presets[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use crate::CPU;
+use std::collections::VecDeque;
+
+// Keeps a bounded history of save states so play can be stepped backwards.
+// Only the newest state is kept in full, older ones are stored as the
+// run-length encoded XOR against the state after them.
+pub struct Rewinder {
+    interval: u32,           // Frames between snapshots
+    capacity: usize,         // Maximum number of older snapshots kept
+    frames: u32,             // Frames since the last snapshot
+    latest: Option<Vec<u8>>, // Newest snapshot
+    deltas: VecDeque<Delta>, // Steps back from the newest snapshot, oldest first
+}
+
+enum Delta {
+    // XOR against the following snapshot, run-length encoded
+    Xor(Vec<u8>),
+    // Snapshot with a different size to the following one, stored whole
+    Full(Vec<u8>),
+}
+
+impl Rewinder {
+    pub fn new(interval: u32, capacity: usize) -> Rewinder {
+        Rewinder {
+            interval: interval.max(1),
+            capacity,
+            frames: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Call once per frame, takes a snapshot every interval frames
+    pub fn record(&mut self, cpu: &CPU) {
+        self.frames += 1;
+        if self.latest.is_some() && self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = cpu.save_state();
+        if let Some(previous) = &self.latest {
+            self.deltas.push_back(encode_delta(previous, &state));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Steps back one snapshot, returns false once the history is used up.
+    // The history is left alone if the snapshot can't be loaded.
+    pub fn rewind(&mut self, cpu: &mut CPU) -> bool {
+        let (Some(delta), Some(latest)) = (self.deltas.back(), &self.latest) else {
+            return false;
+        };
+
+        let state = match delta {
+            Delta::Xor(runs) => {
+                let mut state = latest.clone();
+                apply_xor(&mut state, runs);
+                state
+            }
+            Delta::Full(state) => state.clone(),
+        };
+        if cpu.load_state(&state).is_err() {
+            return false;
+        }
+        self.deltas.pop_back();
+        self.latest = Some(state);
+        self.frames = 0;
+        true
+    }
+
+    // Call once per frame while rewinding, steps back a snapshot every
+    // interval frames so history plays back at the speed it was recorded.
+    // Returns false once the history is used up.
+    pub fn rewind_frame(&mut self, cpu: &mut CPU) -> bool {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return !self.is_empty();
+        }
+        self.rewind(cpu)
+    }
+
+    // Forgets all snapshots, used when a new rom is loaded
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // Number of snapshots that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+}
+
+// Encodes previous as the difference from current, as (count, byte) pairs
+fn encode_delta(previous: &[u8], current: &[u8]) -> Delta {
+    if previous.len() != current.len() {
+        return Delta::Full(previous.to_vec());
+    }
+
+    let mut runs = Vec::new();
+    let mut diff = previous.iter().zip(current).map(|(a, b)| a ^ b).peekable();
+    while let Some(byte) = diff.next() {
+        let mut count = 1u8;
+        while count < u8::MAX && diff.peek() == Some(&byte) {
+            diff.next();
+            count += 1;
+        }
+        runs.push(count);
+        runs.push(byte);
+    }
+    Delta::Xor(runs)
+}
+
+// Turns a snapshot back into the one before it
+fn apply_xor(state: &mut [u8], runs: &[u8]) {
+    let mut pos = 0;
+    for run in runs.chunks_exact(2) {
+        let (count, byte) = (run[0] as usize, run[1]);
+        for value in &mut state[pos..pos + count] {
+            *value ^= byte;
+        }
+        pos += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks, Rom};
+
+    // Counts up in V0 and stores it at 0x300, so every frame's state differs
+    const PROGRAM: [u8; 8] = [
+        0x70, 0x01, // 200: ADD V0, 1
+        0xA3, 0x00, // 202: LD I, 0x300
+        0xF0, 0x55, // 204: LD [I], V0
+        0x12, 0x00, // 206: JP 0x200
+    ];
+
+    fn cpu() -> CPU {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::chip48(), Some(0));
+        cpu.load_rom(&Rom::new(&PROGRAM)).unwrap();
+        cpu
+    }
+
+    fn frame(cpu: &mut CPU) {
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+        cpu.tick_timers();
+    }
+
+    #[test]
+    fn deltas_undo_the_change() {
+        let previous: Vec<u8> = (0..1000).map(|i| (i / 7) as u8).collect();
+        let mut current = previous.clone();
+        current[3] ^= 0xFF;
+        current[500..520].fill(0xAA);
+
+        let Delta::Xor(runs) = encode_delta(&previous, &current) else {
+            panic!("expected an XOR delta");
+        };
+        // Long runs of unchanged bytes are split at 255
+        assert!(runs.len() < 100);
+        apply_xor(&mut current, &runs);
+        assert_eq!(current, previous);
+
+        let Delta::Full(state) = encode_delta(&previous, &previous[1..]) else {
+            panic!("expected a full snapshot");
+        };
+        assert_eq!(state, previous);
+    }
+
+    #[test]
+    fn rewinds_to_the_exact_earlier_states() {
+        let (mut cpu, mut rewinder) = (cpu(), Rewinder::new(1, 100));
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            rewinder.record(&cpu);
+            states.push(cpu.save_state());
+            frame(&mut cpu);
+        }
+        assert_eq!(rewinder.len(), 9);
+
+        // The newest snapshot is where play already is, so the first step
+        // goes to the one before it
+        states.pop();
+        while let Some(state) = states.pop() {
+            assert!(rewinder.rewind(&mut cpu));
+            assert_eq!(cpu.save_state(), state);
+        }
+        assert!(!rewinder.rewind(&mut cpu));
+        assert!(rewinder.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots_past_capacity() {
+        let (mut cpu, mut rewinder) = (cpu(), Rewinder::new(1, 3));
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            rewinder.record(&cpu);
+            states.push(cpu.save_state());
+            frame(&mut cpu);
+        }
+        assert_eq!(rewinder.len(), 3);
+        for _ in 0..3 {
+            assert!(rewinder.rewind(&mut cpu));
+        }
+        assert!(!rewinder.rewind(&mut cpu));
+        assert_eq!(cpu.save_state(), states[6]);
+    }
+
+    #[test]
+    fn rewind_fails_without_history() {
+        let (mut cpu, mut rewinder) = (cpu(), Rewinder::new(4, 10));
+        assert!(!rewinder.rewind(&mut cpu));
+        rewinder.record(&cpu);
+        assert!(!rewinder.rewind(&mut cpu));
+
+        rewinder.record(&cpu);
+        rewinder.clear();
+        assert!(!rewinder.rewind(&mut cpu));
+    }
+
+    #[test]
+    fn failed_loads_keep_the_history() {
+        let (mut cpu, mut rewinder) = (cpu(), Rewinder::new(1, 10));
+        rewinder.record(&cpu);
+        frame(&mut cpu);
+        rewinder.record(&cpu);
+        rewinder.deltas.push_back(Delta::Full(vec![0; 4]));
+        let latest = rewinder.latest.clone();
+
+        assert!(!rewinder.rewind(&mut cpu));
+        assert_eq!((rewinder.len(), &rewinder.latest), (2, &latest));
+    }
+
+    #[test]
+    fn rewinds_frames_at_the_recorded_speed() {
+        let (mut cpu, mut rewinder) = (cpu(), Rewinder::new(4, 100));
+        let mut states = Vec::new();
+        for _ in 0..9 {
+            rewinder.record(&cpu);
+            states.push(cpu.save_state());
+            frame(&mut cpu);
+        }
+        // Snapshots from frames 0, 4 and 8
+        assert_eq!(rewinder.len(), 2);
+
+        for _ in 0..3 {
+            assert!(rewinder.rewind_frame(&mut cpu));
+            assert_eq!(rewinder.len(), 2);
+        }
+        assert!(rewinder.rewind_frame(&mut cpu));
+        assert_eq!(cpu.save_state(), states[4]);
+        for _ in 0..3 {
+            rewinder.rewind_frame(&mut cpu);
+        }
+        assert!(rewinder.rewind_frame(&mut cpu));
+        assert_eq!(cpu.save_state(), states[0]);
+        assert!(!rewinder.rewind_frame(&mut cpu));
+    }
+}
@@ -0,0 +1,24 @@
+// SplitMix64, small enough to keep in save states and the same on every
+// platform, so a seed always produces the same sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rng {
+    pub(crate) state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
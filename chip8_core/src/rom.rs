@@ -0,0 +1,196 @@
+use crate::checksum::{crc32, sha1};
+use crate::{Chip8Error, Disassembly, Platform, START_ADDRESS};
+
+// A program to load and the address it's loaded at, usually 0x200 but
+// 0x600 for ETI-660 programs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    data: Vec<u8>,
+    load_address: u16,
+}
+
+impl Rom {
+    // A rom loaded at the usual 0x200
+    pub fn new(data: &[u8]) -> Rom {
+        Rom::with_load_address(data, START_ADDRESS)
+    }
+
+    // A rom loaded somewhere else, execution starts there too
+    pub fn with_load_address(data: &[u8], load_address: u16) -> Rom {
+        Rom {
+            data: data.to_vec(),
+            load_address,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn sha1(&self) -> [u8; 20] {
+        sha1(&self.data)
+    }
+
+    // Lowercase hex SHA-1, the usual way roms are identified
+    pub fn sha1_hex(&self) -> String {
+        self.sha1().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.data)
+    }
+
+    // Checks the rom fits in a platform's ram above the interpreter
+    pub fn check(&self, platform: Platform) -> Result<(), Chip8Error> {
+        let start = self.load_address as usize;
+        let ram_size = platform.ram_size();
+        if self.load_address < START_ADDRESS || start >= ram_size {
+            return Err(Chip8Error::InvalidLoadAddress {
+                addr: self.load_address,
+            });
+        }
+        if start + self.data.len() > ram_size {
+            return Err(Chip8Error::RomTooLarge {
+                size: self.data.len(),
+                max: ram_size - start,
+            });
+        }
+        Ok(())
+    }
+
+    // Guesses the platform a rom was written for from the newest
+    // instructions its code uses, only following paths execution can take
+    // so sprite data isn't mistaken for code. Roms too big for CHIP-8 ram
+    // must be XO-CHIP.
+    pub fn guess_platform(&self) -> Platform {
+        let disassembly = Disassembly::new(&self.data, self.load_address);
+        let used = disassembly
+            .instructions()
+            .map(|(_, instruction)| instruction.platform())
+            .max()
+            .unwrap_or_default();
+        match self.check(Platform::Chip8) {
+            Ok(()) => used,
+            Err(_) => Platform::XoChip,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rejects_roms_that_dont_fit() {
+        for platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip] {
+            let max = platform.ram_size() - START_ADDRESS as usize;
+            assert_eq!(Rom::new(&vec![0; max]).check(platform), Ok(()));
+            assert_eq!(
+                Rom::new(&vec![0; max + 1]).check(platform),
+                Err(Chip8Error::RomTooLarge { size: max + 1, max })
+            );
+        }
+
+        // Loading higher leaves less room
+        let rom = Rom::with_load_address(&[0; 0xA01], 0x600);
+        assert_eq!(
+            rom.check(Platform::Chip8),
+            Err(Chip8Error::RomTooLarge {
+                size: 0xA01,
+                max: 0xA00
+            })
+        );
+    }
+
+    #[test]
+    fn check_rejects_bad_load_addresses() {
+        let rom = Rom::with_load_address(&[0; 2], 0x1FF);
+        assert_eq!(
+            rom.check(Platform::Chip8),
+            Err(Chip8Error::InvalidLoadAddress { addr: 0x1FF })
+        );
+
+        // Past the end of 4K, but still inside XO-CHIP's 64K
+        let rom = Rom::with_load_address(&[0; 2], 0x1000);
+        assert_eq!(
+            rom.check(Platform::SuperChip),
+            Err(Chip8Error::InvalidLoadAddress { addr: 0x1000 })
+        );
+        assert_eq!(rom.check(Platform::XoChip), Ok(()));
+        assert_eq!(
+            Rom::with_load_address(&[0; 2], 0x600).check(Platform::Chip8),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn checksums_match_known_vectors() {
+        let hex = |data: &[u8]| Rom::new(data).sha1_hex();
+        assert_eq!(hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // Long enough that the padding takes a second block
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            hex(&[b'a'; 1_000_000]),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+
+        assert_eq!(Rom::new(b"").crc32(), 0);
+        assert_eq!(Rom::new(b"123456789").crc32(), 0xCBF43926);
+        assert_eq!(
+            Rom::new(b"The quick brown fox jumps over the lazy dog").crc32(),
+            0x414FA339
+        );
+    }
+
+    #[test]
+    fn guesses_platform_from_reachable_code() {
+        let guess = |data: &[u8]| Rom::new(data).guess_platform();
+        // LD V0, 1 then loop
+        assert_eq!(guess(&[0x60, 0x01, 0x12, 0x02]), Platform::Chip8);
+        // HIGH, then DRW with N = 0 for a 16x16 sprite
+        assert_eq!(
+            guess(&[0x00, 0xFF, 0xD0, 0x10, 0x12, 0x04]),
+            Platform::SuperChip
+        );
+        assert_eq!(guess(&[0x00, 0xFB, 0x12, 0x02]), Platform::SuperChip);
+        // PLANE 3, and SAVE V0 - V3
+        assert_eq!(guess(&[0xF3, 0x01, 0x12, 0x02]), Platform::XoChip);
+        assert_eq!(guess(&[0x50, 0x32, 0x00, 0xFD]), Platform::XoChip);
+        // LD I, LONG 0x2000 skipped over by SE
+        assert_eq!(
+            guess(&[0x30, 0x00, 0xF0, 0x00, 0x20, 0x00, 0x12, 0x06]),
+            Platform::XoChip
+        );
+    }
+
+    #[test]
+    fn guess_ignores_data_that_looks_like_code() {
+        // JP over sprite bytes that would decode as SCR and PLANE
+        let rom = [0x12, 0x06, 0x00, 0xFB, 0xF3, 0x01, 0x12, 0x06];
+        assert_eq!(Rom::new(&rom).guess_platform(), Platform::Chip8);
+    }
+
+    #[test]
+    fn roms_too_big_for_4k_are_xo_chip() {
+        let mut data = vec![0; 0xE01];
+        data[..4].copy_from_slice(&[0x12, 0x00, 0x12, 0x00]);
+        assert_eq!(Rom::new(&data).guess_platform(), Platform::XoChip);
+        assert_eq!(Rom::new(&data[..0xE00]).guess_platform(), Platform::Chip8);
+    }
+}
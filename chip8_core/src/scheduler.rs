@@ -0,0 +1,231 @@
+use crate::{timing::VIP_FRAME_BUDGET, Timing};
+use std::time::Duration;
+
+pub const DEFAULT_IPS: u32 = 600;
+pub const TIMER_HZ: u32 = 60;
+
+// Longest gap caught up in one go, so a stall doesn't fast forward the game
+const MAX_ELAPSED: Duration = Duration::from_millis(250);
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+// Work due since the last advance. Slices ending in a timer tick should
+// run their instructions then call tick_timers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slice {
+    pub instructions: usize,
+    pub tick_timers: bool,
+}
+
+// Turns wall-clock time into instructions at a fixed rate and timer ticks
+// at exactly 60 Hz, independent of how often the display refreshes.
+// Counts are derived from total elapsed time so rounding never drifts.
+// With VIP timing the instructions per second are ignored, each tick gives
+// the CPU enough instructions to use up its frame of cycles.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    ips: u32,
+    timing: Timing,
+    lockstep: bool,    // Only run instructions alongside timer ticks
+    elapsed: u128,     // Nanoseconds since the counts were last reset
+    instructions: u64, // Instructions handed out since then
+    timer_ticks: u64,  // Timer ticks handed out since then
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(DEFAULT_IPS)
+    }
+}
+
+impl Scheduler {
+    pub fn new(ips: u32) -> Scheduler {
+        Scheduler {
+            ips,
+            timing: Timing::default(),
+            lockstep: false,
+            elapsed: 0,
+            instructions: 0,
+            timer_ticks: 0,
+        }
+    }
+
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    // Changes speed from now on, without affecting the timers
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips;
+        self.instructions = self.instructions_at(self.elapsed);
+    }
+
+    // Should match the timing the CPU is using
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    // In lockstep every timer tick gets the same share of instructions
+    // however the frames fall, so runs with the same input can be replayed
+    // exactly. Instructions are then only handed out 60 times a second.
+    pub fn set_lockstep(&mut self, lockstep: bool) {
+        self.lockstep = lockstep;
+    }
+
+    // Returns the work due after elapsed more time, in order
+    pub fn advance(&mut self, elapsed: Duration) -> Vec<Slice> {
+        self.elapsed += elapsed.min(MAX_ELAPSED).as_nanos();
+
+        let mut slices = Vec::new();
+        let ticks_due = (self.elapsed * TIMER_HZ as u128 / NANOS_PER_SECOND) as u64;
+        while self.timer_ticks < ticks_due {
+            self.timer_ticks += 1;
+            let due = self.timer_ticks * self.ips as u64 / TIMER_HZ as u64;
+            slices.push(self.slice_to(due, true));
+        }
+        if !self.lockstep && self.timing == Timing::Fixed {
+            let rest = self.slice_to(self.instructions_at(self.elapsed), false);
+            if rest.instructions > 0 {
+                slices.push(rest);
+            }
+        }
+
+        // Start counting again each second to keep the numbers small
+        if self.timer_ticks >= TIMER_HZ as u64 {
+            let second = self.instructions_at(NANOS_PER_SECOND);
+            self.elapsed -= NANOS_PER_SECOND;
+            self.instructions = self.instructions.saturating_sub(second);
+            self.timer_ticks -= TIMER_HZ as u64;
+        }
+        slices
+    }
+
+    fn instructions_at(&self, time: u128) -> u64 {
+        (time * self.ips as u128 / NANOS_PER_SECOND) as u64
+    }
+
+    // Hands out instructions until due have been run in total
+    fn slice_to(&mut self, due: u64, tick_timers: bool) -> Slice {
+        let due = due.max(self.instructions);
+        let mut instructions = (due - self.instructions) as usize;
+        self.instructions = due;
+        // Every instruction takes at least a cycle, the CPU stops itself
+        // once the frame's cycles run out
+        if self.timing == Timing::Vip {
+            instructions = VIP_FRAME_BUDGET as usize;
+        }
+        Slice {
+            instructions,
+            tick_timers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Totals over some advances of step each
+    fn run(scheduler: &mut Scheduler, step: Duration, count: u32) -> (usize, usize) {
+        let slices: Vec<Slice> = (0..count).flat_map(|_| scheduler.advance(step)).collect();
+        let instructions = slices.iter().map(|slice| slice.instructions).sum();
+        let ticks = slices.iter().filter(|slice| slice.tick_timers).count();
+        (instructions, ticks)
+    }
+
+    #[test]
+    fn splits_a_second_exactly() {
+        for (ips, step, count) in [(600, 10, 100), (1000, 7, 142), (1000, 250, 4), (7, 1, 1000)] {
+            let mut scheduler = Scheduler::new(ips);
+            let step = Duration::from_millis(step);
+            let (mut instructions, mut ticks) = run(&mut scheduler, step, count);
+            // Make up the rest of the second
+            let rest = Duration::from_secs(1) - step * count;
+            if !rest.is_zero() {
+                let (more, more_ticks) = run(&mut scheduler, rest, 1);
+                instructions += more;
+                ticks += more_ticks;
+            }
+            assert_eq!((instructions, ticks), (ips as usize, 60), "{} ips", ips);
+        }
+    }
+
+    #[test]
+    fn timer_ticks_share_instructions_evenly() {
+        let mut scheduler = Scheduler::new(1000);
+        scheduler.set_lockstep(true);
+        let slices = scheduler.advance(Duration::from_millis(250));
+        assert_eq!(slices.len(), 15);
+        // 1000 / 60 is 16.67, so every third tick gets 16
+        let shares: Vec<usize> = slices.iter().map(|slice| slice.instructions).collect();
+        assert_eq!(shares[..6], [16, 17, 17, 16, 17, 17]);
+    }
+
+    #[test]
+    fn carries_fractions_between_calls() {
+        let mut scheduler = Scheduler::new(600);
+        // 0.6 instructions a millisecond
+        let counts: Vec<usize> = (0..5)
+            .map(|_| run(&mut scheduler, Duration::from_millis(1), 1).0)
+            .collect();
+        assert_eq!(counts, [0, 1, 0, 1, 1]);
+
+        // No drift over many seconds of uneven steps
+        let mut scheduler = Scheduler::new(600);
+        let (instructions, ticks) = run(&mut scheduler, Duration::from_micros(2_999), 10_003);
+        let (more, more_ticks) = run(&mut scheduler, Duration::from_micros(1_003), 1);
+        assert_eq!((instructions + more, ticks + more_ticks), (18_000, 1_800));
+    }
+
+    #[test]
+    fn lockstep_only_runs_instructions_with_timer_ticks() {
+        let mut scheduler = Scheduler::new(600);
+        scheduler.set_lockstep(true);
+        assert_eq!(scheduler.advance(Duration::from_millis(10)), []);
+        let tick = Slice {
+            instructions: 10,
+            tick_timers: true,
+        };
+        assert_eq!(scheduler.advance(Duration::from_millis(10)), [tick]);
+        assert_eq!(
+            run(&mut scheduler, Duration::from_millis(5), 196),
+            (590, 59)
+        );
+    }
+
+    #[test]
+    fn large_gaps_are_capped() {
+        let mut scheduler = Scheduler::new(600);
+        assert_eq!(run(&mut scheduler, Duration::from_secs(10), 1), (150, 15));
+        assert_eq!(run(&mut scheduler, Duration::MAX, 1), (150, 15));
+    }
+
+    #[test]
+    fn changing_speed_only_affects_what_comes_after() {
+        let mut scheduler = Scheduler::new(600);
+        assert_eq!(
+            run(&mut scheduler, Duration::from_millis(250), 4),
+            (600, 60)
+        );
+        scheduler.set_ips(1200);
+        assert_eq!(
+            run(&mut scheduler, Duration::from_millis(250), 2),
+            (600, 30)
+        );
+    }
+
+    #[test]
+    fn vip_timing_gives_each_tick_a_frame_budget() {
+        let mut scheduler = Scheduler::new(600);
+        scheduler.set_timing(Timing::Vip);
+        let slices = scheduler.advance(Duration::from_millis(40));
+        let tick = Slice {
+            instructions: VIP_FRAME_BUDGET as usize,
+            tick_timers: true,
+        };
+        assert_eq!(slices, [tick, tick]);
+    }
+}
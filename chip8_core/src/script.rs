@@ -0,0 +1,154 @@
+use crate::CPU;
+
+// One scripted change to the keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u32,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+// Keypad input given frame by frame, for runs without a player
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    events: Vec<KeyEvent>, // Sorted by frame
+    next: usize,           // First event not applied yet
+}
+
+impl InputScript {
+    // Parses statements like `frame 120: press 5; frame 130: release 5`.
+    // Statements are separated by semicolons or newlines, several actions
+    // for the same frame by commas, and # starts a comment.
+    pub fn parse(script: &str) -> Result<InputScript, String> {
+        let mut events = Vec::new();
+        let statements = script
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap_or_default().split(';'));
+        for statement in statements {
+            let statement = statement.trim();
+            if statement.is_empty() {
+                continue;
+            }
+
+            let (head, actions) = statement
+                .split_once(':')
+                .ok_or(format!("expected `frame N: ACTION` in `{}`", statement))?;
+            let frame = match head.split_whitespace().collect::<Vec<_>>()[..] {
+                ["frame", number] => number
+                    .parse()
+                    .map_err(|_| format!("invalid frame number `{}`", number))?,
+                _ => return Err(format!("expected `frame N` in `{}`", statement)),
+            };
+
+            for action in actions.split(',') {
+                let (pressed, key) = match action.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["press", key] => (true, key),
+                    ["release", key] => (false, key),
+                    _ => {
+                        return Err(format!(
+                            "expected `press K` or `release K` in `{}`",
+                            action.trim()
+                        ))
+                    }
+                };
+                let key = usize::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|key| *key < 16)
+                    .ok_or(format!("invalid key `{}`, use 0 to F", key))?;
+                events.push(KeyEvent {
+                    frame,
+                    key,
+                    pressed,
+                });
+            }
+        }
+        // Stable, so events for the same frame keep their order
+        events.sort_by_key(|event| event.frame);
+        Ok(InputScript { events, next: 0 })
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    // Presses and releases the keys due by the start of a frame
+    pub fn apply(&mut self, cpu: &mut CPU, frame: u32) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.frame > frame {
+                break;
+            }
+            cpu.keypress(event.key, event.pressed);
+            self.next += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks};
+
+    fn event(frame: u32, key: usize, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            frame,
+            key,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn parses_statements_in_frame_order() {
+        let script = InputScript::parse(
+            "frame 10: release 5\nframe 2: press 5, press a; frame 2: release A",
+        )
+        .unwrap();
+        assert_eq!(
+            script.events(),
+            [
+                event(2, 5, true),
+                event(2, 0xA, true),
+                event(2, 0xA, false),
+                event(10, 5, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let script = "# Start the game\n\n  frame 1: press 5 # and hold it\n#frame 2: release 5\n";
+        let script = InputScript::parse(script).unwrap();
+        assert_eq!(script.events(), [event(1, 5, true)]);
+        assert_eq!(InputScript::parse("").unwrap().events(), []);
+    }
+
+    #[test]
+    fn rejects_bad_statements() {
+        for bad in [
+            "press 5",
+            "frame: press 5",
+            "frame x: press 5",
+            "frame 1 2: press 5",
+            "frame 1: hold 5",
+            "frame 1: press",
+            "frame 1: press 10",
+            "frame 1: press 5,",
+        ] {
+            assert!(InputScript::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn applies_events_due_by_each_frame() {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::vip(), Some(0));
+        let mut script =
+            InputScript::parse("frame 0: press 1; frame 3: press 2, release 1").unwrap();
+
+        script.apply(&mut cpu, 0);
+        assert_eq!(cpu.keys(), 0b010);
+        script.apply(&mut cpu, 2);
+        assert_eq!(cpu.keys(), 0b010);
+        // Frames can be skipped over
+        script.apply(&mut cpu, 5);
+        assert_eq!(cpu.keys(), 0b100);
+    }
+}
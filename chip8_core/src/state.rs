@@ -0,0 +1,412 @@
+use crate::checksum::crc32;
+use crate::timing::VIP_FRAME_BUDGET;
+use crate::*;
+use std::fmt;
+
+// Save state layout, all values little endian:
+//   magic, version, platform, quirks, registers, timers, stack, keypad,
+//   display, SUPER-CHIP and XO-CHIP state, random number generator, timing,
+//   ram, then a CRC-32 of all of it
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 3;
+// Version 1 had no random number generator state and version 2 no timing
+const OLDEST_VERSION: u16 = 1;
+const CHECKSUM_SIZE: usize = 4;
+
+// Errors raised when a save state can't be restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    // Data doesn't start with the save state magic
+    BadMagic,
+    // Written by a newer or older format
+    UnsupportedVersion(u16),
+    // Data has been corrupted
+    ChecksumMismatch,
+    // Data ends before the state is complete
+    Truncated,
+    // A field holds a value the CPU can't have
+    Invalid,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::ChecksumMismatch => write!(f, "save state is corrupted"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid => write!(f, "save state contains invalid values"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl CPU {
+    // Serializes the full machine state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        out.push(platform_to_byte(self.platform));
+        out.push(quirks_to_byte(self.quirks));
+
+        out.extend_from_slice(&self.program_counter.to_le_bytes());
+        out.extend_from_slice(&self.i_register.to_le_bytes());
+        out.extend_from_slice(&self.v_registers);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+
+        out.extend_from_slice(&self.stack_pointer.to_le_bytes());
+        for val in self.stack {
+            out.extend_from_slice(&val.to_le_bytes());
+        }
+
+        out.extend_from_slice(&self.keys().to_le_bytes());
+
+        out.push(self.hires as u8);
+        out.push(self.planes);
+        out.extend_from_slice(&self.screen);
+
+        out.extend_from_slice(&self.rpl_flags);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.rng.state.to_le_bytes());
+
+        out.push(timing_to_byte(self.timing));
+        out.extend_from_slice(&(self.cycles_left as u16).to_le_bytes());
+
+        out.extend_from_slice(&self.ram[..self.platform.ram_size()]);
+
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    // Checksum of the full machine state, used to spot two runs drifting apart
+    pub fn state_hash(&self) -> u32 {
+        let state = self.save_state();
+        crc32(&state[..state.len() - CHECKSUM_SIZE])
+    }
+
+    // Restores a state from save_state, leaving the CPU untouched on error
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        if data.len() < MAGIC.len() + 2 + CHECKSUM_SIZE {
+            return Err(StateError::Truncated);
+        }
+
+        let (body, checksum) = data.split_at(data.len() - CHECKSUM_SIZE);
+        let version = u16::from_le_bytes([body[4], body[5]]);
+        if !(OLDEST_VERSION..=VERSION).contains(&version) {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if crc32(body).to_le_bytes() != checksum {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        let mut reader = Reader {
+            data: body,
+            pos: MAGIC.len() + 2,
+        };
+        let mut cpu = self.clone();
+
+        cpu.platform = platform_from_byte(reader.byte()?)?;
+        cpu.quirks = quirks_from_byte(reader.byte()?);
+
+        cpu.program_counter = reader.word()?;
+        cpu.i_register = reader.word()?;
+        cpu.v_registers.copy_from_slice(reader.bytes(NUM_REGS)?);
+        cpu.delay_timer = reader.byte()?;
+        cpu.sound_timer = reader.byte()?;
+
+        cpu.stack_pointer = reader.word()?;
+        if cpu.stack_pointer as usize > STACK_SIZE {
+            return Err(StateError::Invalid);
+        }
+        for val in cpu.stack.iter_mut() {
+            *val = reader.word()?;
+        }
+
+        cpu.set_keys(reader.word()?);
+
+        cpu.hires = reader.byte()? != 0;
+        cpu.planes = reader.byte()?;
+        if cpu.planes > 0b11 {
+            return Err(StateError::Invalid);
+        }
+        cpu.screen
+            .copy_from_slice(reader.bytes(HIRES_WIDTH * HIRES_HEIGHT)?);
+
+        cpu.rpl_flags.copy_from_slice(reader.bytes(NUM_RPL_FLAGS)?);
+        cpu.audio_pattern
+            .copy_from_slice(reader.bytes(AUDIO_PATTERN_SIZE)?);
+        cpu.pitch = reader.byte()?;
+
+        // Older states keep the current generator
+        if version >= 2 {
+            cpu.seed = reader.long()?;
+            cpu.rng.state = reader.long()?;
+        }
+        if version >= 3 {
+            cpu.timing = timing_from_byte(reader.byte()?)?;
+            cpu.cycles_left = reader.word()? as u32;
+            if cpu.cycles_left > VIP_FRAME_BUDGET {
+                return Err(StateError::Invalid);
+            }
+        }
+
+        let ram_size = cpu.platform.ram_size();
+        cpu.ram = [0; MAX_RAM_SIZE];
+        cpu.ram[..ram_size].copy_from_slice(reader.bytes(ram_size)?);
+
+        if reader.pos != body.len() {
+            return Err(StateError::Invalid);
+        }
+
+        *self = cpu;
+        Ok(())
+    }
+}
+
+// Reads little endian values in order, shared with movies
+pub(crate) struct Reader<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(StateError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn word(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn int(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub(crate) fn long(&mut self) -> Result<u64, StateError> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+pub(crate) fn platform_to_byte(platform: Platform) -> u8 {
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+pub(crate) fn platform_from_byte(byte: u8) -> Result<Platform, StateError> {
+    match byte {
+        0 => Ok(Platform::Chip8),
+        1 => Ok(Platform::SuperChip),
+        2 => Ok(Platform::XoChip),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+pub(crate) fn timing_to_byte(timing: Timing) -> u8 {
+    match timing {
+        Timing::Fixed => 0,
+        Timing::Vip => 1,
+    }
+}
+
+pub(crate) fn timing_from_byte(byte: u8) -> Result<Timing, StateError> {
+    match byte {
+        0 => Ok(Timing::Fixed),
+        1 => Ok(Timing::Vip),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+pub(crate) fn quirks_to_byte(quirks: Quirks) -> u8 {
+    (quirks.shift_uses_vy as u8)
+        | (quirks.load_store_increments_i as u8) << 1
+        | (quirks.jump_uses_vx as u8) << 2
+        | (quirks.logic_resets_vf as u8) << 3
+        | (quirks.clip_sprites as u8) << 4
+        | (quirks.load_store_increments_i_by_x as u8) << 5
+}
+
+pub(crate) fn quirks_from_byte(byte: u8) -> Quirks {
+    Quirks {
+        shift_uses_vy: byte & 1 != 0,
+        load_store_increments_i: byte & (1 << 1) != 0,
+        jump_uses_vx: byte & (1 << 2) != 0,
+        logic_resets_vf: byte & (1 << 3) != 0,
+        clip_sprites: byte & (1 << 4) != 0,
+        load_store_increments_i_by_x: byte & (1 << 5) != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A machine part way through something, with every section of the
+    // state holding something other than its starting value
+    fn busy_cpu() -> CPU {
+        let mut cpu = CPU::new(Platform::XoChip, Quirks::xochip(), Some(7));
+        cpu.program_counter = 0x2A4;
+        cpu.i_register = 0x1234;
+        cpu.v_registers[3] = 0x56;
+        cpu.delay_timer = 9;
+        cpu.sound_timer = 4;
+        cpu.stack_pointer = 2;
+        cpu.stack[..2].copy_from_slice(&[0x202, 0x30A]);
+        cpu.keypress(0xB, true);
+        cpu.hires = true;
+        cpu.planes = 3;
+        cpu.screen[100] = 2;
+        cpu.rpl_flags[1] = 0x77;
+        cpu.audio_pattern[0] = 0xAA;
+        cpu.pitch = 100;
+        cpu.rng.state = 0xDEADBEEF;
+        cpu.timing = Timing::Vip;
+        cpu.cycles_left = 1000;
+        cpu.ram[0xF000] = 0x42;
+        cpu
+    }
+
+    // Fixes up the checksum after editing a state
+    fn resign(mut state: Vec<u8>) -> Vec<u8> {
+        let body = state.len() - CHECKSUM_SIZE;
+        let checksum = crc32(&state[..body]);
+        state[body..].copy_from_slice(&checksum.to_le_bytes());
+        state
+    }
+
+    // Offset of the stack pointer: magic, version, platform, quirks, pc, I,
+    // V0-VF and the timers
+    const STACK_POINTER: usize = 4 + 2 + 2 + 4 + NUM_REGS + 2;
+    const PLANES: usize = STACK_POINTER + 2 + STACK_SIZE * 2 + 2 + 1;
+
+    #[test]
+    fn round_trip() {
+        let cpu = busy_cpu();
+        let state = cpu.save_state();
+        let mut loaded = CPU::default();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.state_hash(), cpu.state_hash());
+        assert_eq!(loaded.program_counter(), 0x2A4);
+        assert_eq!(loaded.ram[0xF000], 0x42);
+    }
+
+    // Version 2 is version 3 without the timing section
+    fn older_version(state: &[u8], version: u16) -> Vec<u8> {
+        let ram_size = Platform::XoChip.ram_size();
+        let ram = state.len() - CHECKSUM_SIZE - ram_size;
+        let mut end = ram - 3; // Timing and cycles left
+        if version < 2 {
+            end -= 16; // Seed and generator state
+        }
+        let mut old = state[..end].to_vec();
+        old[4..6].copy_from_slice(&version.to_le_bytes());
+        old.extend_from_slice(&state[ram..]);
+        resign(old)
+    }
+
+    #[test]
+    fn loads_older_versions() {
+        let state = busy_cpu().save_state();
+        for version in [1, 2] {
+            let mut cpu = CPU::new(Platform::Chip8, Quirks::vip(), Some(99));
+            let rng = cpu.rng.state;
+            cpu.load_state(&older_version(&state, version)).unwrap();
+            assert_eq!(cpu.platform, Platform::XoChip);
+            assert_eq!(cpu.i_register, 0x1234);
+            assert_eq!(cpu.ram[0xF000], 0x42);
+            // Things older versions didn't store are left as they were
+            assert_eq!(cpu.timing, Timing::Fixed);
+            if version == 1 {
+                assert_eq!((cpu.seed, cpu.rng.state), (99, rng));
+            } else {
+                assert_eq!((cpu.seed, cpu.rng.state), (7, 0xDEADBEEF));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let mut state = busy_cpu().save_state();
+        state[20] ^= 1;
+        assert_eq!(
+            CPU::default().load_state(&state),
+            Err(StateError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_states() {
+        let state = busy_cpu().save_state();
+        let short = resign(state[..state.len() - 100].to_vec());
+        assert_eq!(
+            CPU::default().load_state(&short),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(
+            CPU::default().load_state(&state[..7]),
+            Err(StateError::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_other_data_and_versions() {
+        assert_eq!(
+            CPU::default().load_state(b"not a save state"),
+            Err(StateError::BadMagic)
+        );
+        let mut state = busy_cpu().save_state();
+        state[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            CPU::default().load_state(&resign(state)),
+            Err(StateError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        let state = busy_cpu().save_state();
+        let mut cpu = CPU::default();
+        let before = cpu.save_state();
+
+        let mut bad = state.clone();
+        bad[STACK_POINTER..STACK_POINTER + 2]
+            .copy_from_slice(&(STACK_SIZE as u16 + 1).to_le_bytes());
+        assert_eq!(cpu.load_state(&resign(bad)), Err(StateError::Invalid));
+
+        let mut bad = state.clone();
+        assert_eq!(bad[PLANES], 3);
+        bad[PLANES] = 4;
+        assert_eq!(cpu.load_state(&resign(bad)), Err(StateError::Invalid));
+
+        // The CPU is left untouched
+        assert_eq!(cpu.save_state(), before);
+    }
+}
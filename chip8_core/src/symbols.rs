@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashMap};
+
+// Label addresses from an assembler's symbol map, so a debugger can show
+// names instead of bare addresses and accept them in commands
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    addresses: HashMap<String, u16>, // Label names to addresses
+    names: BTreeMap<u16, String>,    // First label at each address
+}
+
+impl Symbols {
+    // Parses lines of `0x2A4 name`, as written by chip8-asm --symbols.
+    // Blank lines are skipped.
+    pub fn parse(map: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (number, line) in map.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = || format!("line {}: expected `ADDRESS NAME`", number + 1);
+            let [address, name] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(error());
+            };
+            let address = address
+                .strip_prefix("0x")
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .ok_or_else(error)?;
+            if symbols.addresses.contains_key(name) {
+                return Err(format!(
+                    "line {}: `{}` is already defined",
+                    number + 1,
+                    name
+                ));
+            }
+            symbols.addresses.insert(name.to_string(), address);
+            symbols.names.entry(address).or_insert(name.to_string());
+        }
+        Ok(symbols)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    // Address of a label
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    // Nearest label at or before an address, with the offset from it
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        self.names
+            .range(..=address)
+            .next_back()
+            .map(|(start, name)| (name.as_str(), address - start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_labels_both_ways() {
+        let symbols = Symbols::parse("0x200 start\n\n0x20A loop\n0x20A again\n").unwrap();
+        assert_eq!(symbols.address("loop"), Some(0x20A));
+        assert_eq!(symbols.address("again"), Some(0x20A));
+        assert_eq!(symbols.address("end"), None);
+
+        assert_eq!(symbols.locate(0x200), Some(("start", 0)));
+        assert_eq!(symbols.locate(0x208), Some(("start", 8)));
+        assert_eq!(symbols.locate(0x20C), Some(("loop", 2)));
+        assert_eq!(symbols.locate(0x1FE), None);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for bad in [
+            "start",
+            "200 start",
+            "0xZZ start",
+            "0x200 a b",
+            "0x200 a\n0x202 a",
+        ] {
+            assert!(Symbols::parse(bad).is_err(), "{}", bad);
+        }
+        assert!(Symbols::parse("").unwrap().is_empty());
+    }
+}
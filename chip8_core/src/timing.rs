@@ -0,0 +1,166 @@
+use crate::{Instruction, CPU};
+
+// COSMAC VIP machine cycles in a 60 Hz frame, 1.7609 MHz with 8 clocks per cycle
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+// Cycles taken each frame by the display interrupt and DMA
+const VIP_DISPLAY_CYCLES: u32 = 1832;
+// Cycles the interpreter gets to run instructions in each frame
+pub(crate) const VIP_FRAME_BUDGET: u32 = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+// Cycles the interpreter loop spends fetching and decoding every instruction
+const VIP_FETCH_CYCLES: u32 = 68;
+// Extra cycles when a skip instruction jumps over the next instruction
+const VIP_SKIP_CYCLES: u32 = 4;
+
+// How long instructions take to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    // Every instruction takes the same time, the Scheduler runs a fixed
+    // number of them per second
+    #[default]
+    Fixed,
+    // Each instruction uses its COSMAC VIP machine cycles out of a budget
+    // refilled every frame, and drawing waits for the vertical blank
+    Vip,
+}
+
+impl Timing {
+    // Names accepted by Timing::from_name
+    pub const NAMES: [&'static str; 2] = ["fixed", "vip"];
+
+    // Looks up a timing model by name
+    pub fn from_name(name: &str) -> Option<Timing> {
+        match name.to_ascii_lowercase().as_str() {
+            "fixed" => Some(Timing::Fixed),
+            "vip" | "cosmac" => Some(Timing::Vip),
+            _ => None,
+        }
+    }
+}
+
+// Machine cycles the VIP interpreter takes to run an instruction that has
+// just executed. SUPER-CHIP and XO-CHIP instructions never existed on the
+// VIP so only pay for being fetched.
+pub(crate) fn vip_cycles(cpu: &CPU, instruction: Instruction, skipped: bool) -> u32 {
+    let skip = if skipped { VIP_SKIP_CYCLES } else { 0 };
+    let cycles = match instruction {
+        Instruction::Cls => 3078,
+        Instruction::Ret => 10,
+        Instruction::Jump(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SkipEqByte(..) | Instruction::SkipNeByte(..) => 10 + skip,
+        Instruction::SkipEqReg(..) | Instruction::SkipNeReg(..) => 14 + skip,
+        Instruction::LoadByte(..) => 6,
+        Instruction::AddByte(..) => 10,
+        Instruction::LoadReg(..) => 12,
+        Instruction::Or(..)
+        | Instruction::And(..)
+        | Instruction::Xor(..)
+        | Instruction::AddReg(..)
+        | Instruction::Sub(..)
+        | Instruction::ShiftRight(..)
+        | Instruction::SubReverse(..)
+        | Instruction::ShiftLeft(..) => 44,
+        Instruction::LoadI(_) => 12,
+        Instruction::JumpOffset(..) => 22,
+        Instruction::Random(..) => 36,
+        // Waits for the vertical blank, the CPU ends the frame after it
+        Instruction::Draw(_, _, n) => 26 + 34 * n as u32,
+        Instruction::SkipKey(_) | Instruction::SkipNotKey(_) => 14 + skip,
+        Instruction::LoadDelay(_) => 10,
+        Instruction::WaitKey(_) => 19,
+        Instruction::SetDelay(_) | Instruction::SetSound(_) => 10,
+        Instruction::AddI(_) | Instruction::Font(_) => 16,
+        // Digits are found by repeated subtraction
+        Instruction::Bcd(x) => {
+            let value = cpu.v_registers[x as usize] as u32;
+            80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+        }
+        Instruction::Store(x) | Instruction::Load(x) => 14 + 14 * (x as u32 + 1),
+        _ => 0,
+    };
+    VIP_FETCH_CYCLES + cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks};
+
+    fn cycles(opcode: u16, skipped: bool) -> u32 {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::vip(), Some(0));
+        cpu.v_registers[0] = 255;
+        vip_cycles(&cpu, Instruction::decode(opcode), skipped)
+    }
+
+    // Each count is the instruction's own cycles on top of the 68 spent
+    // fetching and decoding it
+    #[test]
+    fn matches_vip_cycle_counts() {
+        let table = [
+            (0x00E0, 68 + 3078), // CLS
+            (0x00EE, 68 + 10),   // RET
+            (0x1234, 68 + 12),   // JP
+            (0x2234, 68 + 26),   // CALL
+            (0x6012, 68 + 6),    // LD VX, NN
+            (0x7012, 68 + 10),   // ADD VX, NN
+            (0x8010, 68 + 12),   // LD VX, VY
+            (0x8011, 68 + 44),   // OR
+            (0x8012, 68 + 44),   // AND
+            (0x8013, 68 + 44),   // XOR
+            (0x8014, 68 + 44),   // ADD
+            (0x8015, 68 + 44),   // SUB
+            (0x8016, 68 + 44),   // SHR
+            (0x8017, 68 + 44),   // SUBN
+            (0x801E, 68 + 44),   // SHL
+            (0xA123, 68 + 12),   // LD I
+            (0xB123, 68 + 22),   // JP V0
+            (0xC0FF, 68 + 36),   // RND
+            (0xD011, 68 + 26 + 34),
+            (0xD015, 68 + 26 + 5 * 34),
+            (0xD01F, 68 + 26 + 15 * 34),
+            (0xF007, 68 + 10),      // LD VX, DT
+            (0xF00A, 68 + 19),      // LD VX, K
+            (0xF01E, 68 + 16),      // ADD I, VX
+            (0xF029, 68 + 16),      // LD F, VX
+            (0xF055, 68 + 14 + 14), // LD [I], V0
+            (0xF355, 68 + 14 + 56), // LD [I], V3
+            (0xFF55, 68 + 14 + 224),
+            (0xF065, 68 + 14 + 14), // LD V0, [I]
+            (0xFF65, 68 + 14 + 224),
+        ];
+        for (opcode, expected) in table {
+            assert_eq!(cycles(opcode, false), expected, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn skips_cost_more_when_taken() {
+        for opcode in [0x3012, 0x4012, 0x5010, 0x9010, 0xE09E, 0xE0A1] {
+            assert_eq!(cycles(opcode, true), cycles(opcode, false) + 4);
+        }
+        assert_eq!(cycles(0x3012, false), 68 + 10);
+        assert_eq!(cycles(0x5010, false), 68 + 14);
+        assert_eq!(cycles(0xE09E, false), 68 + 14);
+    }
+
+    #[test]
+    fn bcd_takes_longer_for_bigger_digits() {
+        // V0 is 255, so the digits add up to 12
+        assert_eq!(cycles(0xF033, false), 68 + 80 + 16 * 12);
+        let cpu = CPU::new(Platform::Chip8, Quirks::vip(), Some(0));
+        assert_eq!(
+            vip_cycles(&cpu, Instruction::decode(0xF133), false),
+            68 + 80
+        );
+    }
+
+    #[test]
+    fn later_instructions_only_pay_for_the_fetch() {
+        // SCD, SCR, HIGH, LD HF and PLANE
+        for opcode in [0x00C1, 0x00FB, 0x00FF, 0xF030, 0xF201] {
+            assert_eq!(cycles(opcode, false), 68, "{:04X}", opcode);
+        }
+        // DRW with N = 0 is a sprite with no rows on the VIP
+        assert_eq!(cycles(0xD010, false), 68 + 26);
+    }
+}
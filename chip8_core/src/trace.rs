@@ -0,0 +1,403 @@
+use crate::{Instruction, CPU, NUM_REGS};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+// Start of binary trace files, followed by a version byte
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+const RECORD_SIZE: usize = 4 + (NUM_REGS + 3) * 2;
+
+// Registers an instruction can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRegisters {
+    pub v: [u8; NUM_REGS],
+    pub i: u16,
+    pub sp: u8, // The stack only holds 16 addresses
+}
+
+impl TraceRegisters {
+    pub(crate) fn of(cpu: &CPU) -> TraceRegisters {
+        TraceRegisters {
+            v: cpu.v_registers,
+            i: cpu.i_register,
+            sp: cpu.stack_pointer as u8,
+        }
+    }
+}
+
+// An executed instruction with the registers either side of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    pub before: TraceRegisters,
+    pub after: TraceRegisters,
+}
+
+impl TraceEntry {
+    pub fn instruction(&self) -> Instruction {
+        Instruction::decode(self.opcode)
+    }
+
+    // Reads back a binary trace, None if it isn't one
+    pub fn read_binary(data: &[u8]) -> Option<Vec<TraceEntry>> {
+        let records = data.strip_prefix(MAGIC)?.strip_prefix(&[VERSION])?;
+        if records.len() % RECORD_SIZE != 0 {
+            return None;
+        }
+        let word = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+        let registers = |bytes: &[u8]| TraceRegisters {
+            v: bytes[..NUM_REGS].try_into().unwrap(),
+            i: word(&bytes[NUM_REGS..]),
+            sp: bytes[NUM_REGS + 2],
+        };
+        let entries = records
+            .chunks(RECORD_SIZE)
+            .map(|record| TraceEntry {
+                pc: word(record),
+                opcode: word(&record[2..]),
+                before: registers(&record[4..]),
+                after: registers(&record[4 + NUM_REGS + 3..]),
+            })
+            .collect();
+        Some(entries)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    // A line per instruction with the registers before it in KEY=VALUE
+    // form, the mnemonic and the registers it changed
+    #[default]
+    Text,
+    // MAGIC and VERSION, then 42 byte records: pc, opcode, then V0-VF, I
+    // and SP before and after, with 16 bit values big endian
+    Binary,
+}
+
+impl TraceFormat {
+    // Names accepted by TraceFormat::from_name
+    pub const NAMES: [&'static str; 2] = ["text", "binary"];
+
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Some(TraceFormat::Text),
+            "binary" | "bin" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+// Which instructions get traced, everything by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub pcs: Option<(u16, u16)>, // Inclusive range of addresses
+    pub classes: u16,            // Bit N set traces opcodes starting with hex digit N
+}
+
+impl TraceFilter {
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        let in_range = match self.pcs {
+            Some((start, end)) => (start..=end).contains(&entry.pc),
+            None => true,
+        };
+        in_range && self.classes & (1 << (entry.opcode >> 12)) != 0
+    }
+
+    // Parses START-END, with 0x prefixed hex or decimal addresses
+    pub fn parse_pcs(text: &str) -> Option<(u16, u16)> {
+        let (start, end) = text.split_once('-')?;
+        let (start, end) = (parse_address(start.trim())?, parse_address(end.trim())?);
+        (start <= end).then_some((start, end))
+    }
+
+    // Parses a comma separated list of opcode classes by their first hex
+    // digit, like 8,D,F or 8XYN,DXYN,FXNN
+    pub fn parse_classes(text: &str) -> Option<u16> {
+        text.split(',').try_fold(0, |classes, class| {
+            let digit = class.trim().chars().next()?.to_digit(16)?;
+            Some(classes | 1 << digit)
+        })
+    }
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter {
+            pcs: None,
+            classes: 0xFFFF,
+        }
+    }
+}
+
+// Writes trace entries that pass a filter, either as they come or, in
+// ring mode, holding just the most recent ones until dump is called
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    ring: Option<(VecDeque<TraceEntry>, usize)>,
+    started: bool, // Binary header written
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, format: TraceFormat) -> Tracer<W> {
+        Tracer {
+            writer,
+            format,
+            filter: TraceFilter::default(),
+            ring: None,
+            started: false,
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    // Keeps only the last capacity entries, written out by dump
+    pub fn set_ring(&mut self, capacity: usize) {
+        self.ring = Some((VecDeque::with_capacity(capacity), capacity));
+    }
+
+    // Adds entries from CPU::take_trace
+    pub fn record(&mut self, entries: &[TraceEntry]) -> io::Result<()> {
+        let filter = self.filter;
+        for entry in entries.iter().filter(|entry| filter.matches(entry)) {
+            match &mut self.ring {
+                Some((ring, capacity)) => {
+                    if ring.len() == *capacity {
+                        ring.pop_front();
+                    }
+                    if *capacity > 0 {
+                        ring.push_back(*entry);
+                    }
+                }
+                None => self.write(entry)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Writes out what the ring holds, for when the program has crashed
+    pub fn dump(&mut self) -> io::Result<()> {
+        if let Some((ring, _)) = &mut self.ring {
+            let entries: Vec<TraceEntry> = ring.drain(..).collect();
+            for entry in &entries {
+                self.write(entry)?;
+            }
+        }
+        self.flush()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", text_line(entry)),
+            TraceFormat::Binary => {
+                if !self.started {
+                    self.writer.write_all(MAGIC)?;
+                    self.writer.write_all(&[VERSION])?;
+                    self.started = true;
+                }
+                self.writer.write_all(&record(entry))
+            }
+        }
+    }
+}
+
+// Like PC=0200 OP=6E05 V0=00 .. VF=00 I=0000 SP=0  LD VE, 0x05  -> VE=05
+fn text_line(entry: &TraceEntry) -> String {
+    let (before, after) = (&entry.before, &entry.after);
+    let mut line = format!("PC={:04X} OP={:04X}", entry.pc, entry.opcode);
+    for (x, v) in before.v.iter().enumerate() {
+        line += &format!(" V{:X}={:02X}", x, v);
+    }
+    line += &format!(
+        " I={:04X} SP={:X}  {}",
+        before.i,
+        before.sp,
+        entry.instruction()
+    );
+
+    let mut changes = Vec::new();
+    for x in 0..NUM_REGS {
+        if after.v[x] != before.v[x] {
+            changes.push(format!("V{:X}={:02X}", x, after.v[x]));
+        }
+    }
+    if after.i != before.i {
+        changes.push(format!("I={:04X}", after.i));
+    }
+    if after.sp != before.sp {
+        changes.push(format!("SP={:X}", after.sp));
+    }
+    if !changes.is_empty() {
+        line += &format!("  -> {}", changes.join(" "));
+    }
+    line
+}
+
+fn record(entry: &TraceEntry) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(RECORD_SIZE);
+    bytes.extend_from_slice(&entry.pc.to_be_bytes());
+    bytes.extend_from_slice(&entry.opcode.to_be_bytes());
+    for registers in [&entry.before, &entry.after] {
+        bytes.extend_from_slice(&registers.v);
+        bytes.extend_from_slice(&registers.i.to_be_bytes());
+        bytes.push(registers.sp);
+    }
+    bytes
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pc: u16, opcode: u16) -> TraceEntry {
+        let before = TraceRegisters {
+            v: [0; NUM_REGS],
+            i: 0x300,
+            sp: 1,
+        };
+        TraceEntry {
+            pc,
+            opcode,
+            before,
+            after: before,
+        }
+    }
+
+    fn text(tracer: Tracer<Vec<u8>>) -> Vec<String> {
+        let text = String::from_utf8(tracer.writer).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_address_ranges() {
+        assert_eq!(TraceFilter::parse_pcs("0x200-0x2FF"), Some((0x200, 0x2FF)));
+        assert_eq!(
+            TraceFilter::parse_pcs(" 512 - 0X300 "),
+            Some((0x200, 0x300))
+        );
+        assert_eq!(TraceFilter::parse_pcs("0x200-0x200"), Some((0x200, 0x200)));
+        for bad in [
+            "0x300-0x200",
+            "0x200",
+            "0x200-",
+            "0xZZ-0x300",
+            "0-65536",
+            "-1-2",
+        ] {
+            assert_eq!(TraceFilter::parse_pcs(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_opcode_classes() {
+        assert_eq!(TraceFilter::parse_classes("8,D,f"), Some(0xA100));
+        assert_eq!(TraceFilter::parse_classes("8XYN, DXYN"), Some(0x2100));
+        assert_eq!(TraceFilter::parse_classes("0"), Some(1));
+        for bad in ["", "8,,D", "G", "8,X"] {
+            assert_eq!(TraceFilter::parse_classes(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn filters_by_address_and_class() {
+        let filter = TraceFilter {
+            pcs: Some((0x200, 0x202)),
+            classes: 1 << 0xD,
+        };
+        assert!(filter.matches(&entry(0x202, 0xD015)));
+        assert!(!filter.matches(&entry(0x204, 0xD015)));
+        assert!(!filter.matches(&entry(0x200, 0x6005)));
+        assert!(TraceFilter::default().matches(&entry(0xFFFE, 0xF000)));
+    }
+
+    #[test]
+    fn formats_text_lines() {
+        let mut entry = entry(0x200, 0x6E05);
+        entry.after.v[0xE] = 5;
+        entry.after.sp = 2;
+        assert_eq!(
+            text_line(&entry),
+            "PC=0200 OP=6E05 V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 \
+             V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=00 I=0300 SP=1  LD VE, 0x05  -> VE=05 SP=2"
+        );
+        // Nothing changed
+        assert!(text_line(&self::entry(0x200, 0x1200)).ends_with("SP=1  JP 0x200"));
+    }
+
+    #[test]
+    fn binary_traces_read_back() {
+        let mut entries: Vec<TraceEntry> = (0..5).map(|n| entry(0x200 + n * 2, 0x7001)).collect();
+        entries[2].after.v[0] = 0xFF;
+        entries[3].after.i = 0xFFFF;
+        entries[4].after.sp = 16;
+
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Binary);
+        tracer.record(&entries[..2]).unwrap();
+        tracer.record(&entries[2..]).unwrap();
+        let data = tracer.writer;
+        assert_eq!(data.len(), 5 + 5 * RECORD_SIZE);
+        assert_eq!(TraceEntry::read_binary(&data), Some(entries));
+
+        assert_eq!(TraceEntry::read_binary(&data[..data.len() - 1]), None);
+        assert_eq!(TraceEntry::read_binary(&data[1..]), None);
+        let mut newer = data.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(TraceEntry::read_binary(&newer), None);
+        assert_eq!(TraceEntry::read_binary(b"C8TR\x01"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn ring_keeps_the_latest_entries_until_dumped() {
+        let entries: Vec<TraceEntry> = (0..5).map(|n| entry(0x200 + n * 2, 0x1200)).collect();
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Text);
+        tracer.set_ring(2);
+        tracer.record(&entries).unwrap();
+        assert!(tracer.writer.is_empty());
+
+        tracer.dump().unwrap();
+        // Dumping empties the ring
+        tracer.dump().unwrap();
+        let lines = text(tracer);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("PC=0206"));
+        assert!(lines[1].starts_with("PC=0208"));
+    }
+
+    #[test]
+    fn empty_ring_keeps_nothing() {
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Binary);
+        tracer.set_ring(0);
+        tracer.record(&[entry(0x200, 0x1200)]).unwrap();
+        tracer.dump().unwrap();
+        assert!(tracer.writer.is_empty());
+    }
+
+    #[test]
+    fn dump_without_a_ring_writes_nothing_more() {
+        let mut tracer = Tracer::new(Vec::new(), TraceFormat::Text);
+        tracer.set_filter(TraceFilter {
+            pcs: None,
+            classes: 1 << 1,
+        });
+        tracer
+            .record(&[entry(0x200, 0x1200), entry(0x202, 0x6000)])
+            .unwrap();
+        tracer.dump().unwrap();
+        assert_eq!(text(tracer).len(), 1);
+    }
+}
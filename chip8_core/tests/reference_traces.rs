@@ -0,0 +1,179 @@
+// Runs every rom in roms/ with scripted input and compares each executed
+// instruction against the reference traces in tests/traces, so changes to
+// instructions or quirks that alter what a rom does show up here.
+//
+// After a deliberate change, regenerate the references with
+//
+//   cargo test --test reference_traces -- --ignored regenerate
+//
+// and check the new traces in.
+
+use chip8_core::*;
+use std::{fs, path::PathBuf};
+
+const FRAMES: u32 = 60;
+const TICKS_PER_FRAME: usize = 10;
+const SEED: u64 = 0;
+
+fn roms_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../roms")
+}
+
+fn traces_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/traces")
+}
+
+fn roms() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(roms_dir())
+        .expect("roms directory is missing")
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+// Keys to press, from NAME.input if the rom has one or default.input,
+// read the same way as chip8-headless --input-file
+fn input(name: &str) -> InputScript {
+    let own = traces_dir().join(format!("{}.input", name));
+    let path = if own.exists() {
+        own
+    } else {
+        traces_dir().join("default.input")
+    };
+    let script = fs::read_to_string(&path).expect("input script is missing");
+    InputScript::parse(&script)
+        .unwrap_or_else(|e| panic!("invalid input script {}: {}", path.display(), e))
+}
+
+// Every instruction the rom runs, stopping early if it exits or errors
+fn run(name: &str) -> Vec<TraceEntry> {
+    let data = fs::read(roms_dir().join(name)).unwrap();
+    let rom = Rom::new(&data);
+    let platform = rom.guess_platform();
+    let mut cpu = CPU::new(platform, platform.default_quirks(), Some(SEED));
+    cpu.load_rom(&rom).unwrap();
+    cpu.set_tracing(true);
+
+    let mut input = input(name);
+    let mut trace = Vec::new();
+    'frames: for frame in 0..FRAMES {
+        input.apply(&mut cpu, frame);
+        for _ in 0..TICKS_PER_FRAME {
+            let outcome = cpu.tick();
+            trace.extend(cpu.take_trace());
+            if !matches!(
+                outcome,
+                Ok(StepOutcome::Continue | StepOutcome::WaitingForKey)
+            ) {
+                break 'frames;
+            }
+        }
+        cpu.tick_timers();
+    }
+    trace
+}
+
+fn encode(trace: &[TraceEntry]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut tracer = Tracer::new(&mut data, TraceFormat::Binary);
+    tracer.record(trace).unwrap();
+    drop(tracer);
+    data
+}
+
+// The registers that differ between two states
+fn register_diff(expected: &TraceRegisters, actual: &TraceRegisters) -> Vec<String> {
+    let mut diff = Vec::new();
+    for x in 0..16 {
+        if expected.v[x] != actual.v[x] {
+            diff.push(format!(
+                "V{:X}: expected {:02X}, got {:02X}",
+                x, expected.v[x], actual.v[x]
+            ));
+        }
+    }
+    if expected.i != actual.i {
+        diff.push(format!(
+            "I: expected {:04X}, got {:04X}",
+            expected.i, actual.i
+        ));
+    }
+    if expected.sp != actual.sp {
+        diff.push(format!("SP: expected {}, got {}", expected.sp, actual.sp));
+    }
+    diff
+}
+
+// Describes the first instruction that differs from the reference
+fn first_divergence(expected: &[TraceEntry], actual: &[TraceEntry]) -> Option<String> {
+    for (index, (want, got)) in expected.iter().zip(actual).enumerate() {
+        let location = format!(
+            "instruction {}: expected {:04X} {:04X} ({})",
+            index,
+            want.pc,
+            want.opcode,
+            want.instruction()
+        );
+        if (want.pc, want.opcode) != (got.pc, got.opcode) {
+            return Some(format!(
+                "{}, ran {:04X} {:04X} ({})",
+                location,
+                got.pc,
+                got.opcode,
+                got.instruction()
+            ));
+        }
+        let before = register_diff(&want.before, &got.before);
+        if !before.is_empty() {
+            return Some(format!(
+                "{}, before it\n    {}",
+                location,
+                before.join("\n    ")
+            ));
+        }
+        let after = register_diff(&want.after, &got.after);
+        if !after.is_empty() {
+            return Some(format!(
+                "{}, after it\n    {}",
+                location,
+                after.join("\n    ")
+            ));
+        }
+    }
+    if expected.len() != actual.len() {
+        return Some(format!(
+            "expected {} instructions, ran {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    None
+}
+
+#[test]
+fn roms_match_reference_traces() {
+    let mut failures = Vec::new();
+    for name in roms() {
+        let path = traces_dir().join(format!("{}.trace", name));
+        let Ok(data) = fs::read(&path) else {
+            failures.push(format!("{}: no reference trace, regenerate them", name));
+            continue;
+        };
+        let expected = TraceEntry::read_binary(&data).expect("invalid reference trace");
+        if let Some(divergence) = first_divergence(&expected, &run(&name)) {
+            failures.push(format!("{}: {}", name, divergence));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "rewrites the reference traces"]
+fn regenerate() {
+    for name in roms() {
+        let path = traces_dir().join(format!("{}.trace", name));
+        fs::write(&path, encode(&run(&name))).unwrap();
+        println!("Wrote {}", path.display());
+    }
+}
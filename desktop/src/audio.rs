@@ -0,0 +1,38 @@
+use chip8_core::Synth;
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    Sdl,
+};
+
+const SAMPLE_RATE: i32 = 44100;
+const BUFFER_SAMPLES: u16 = 512;
+
+// Feeds the synth to SDL from the audio thread
+pub struct Speaker {
+    pub synth: Synth,
+    sample_rate: u32,
+}
+
+impl AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.synth.fill(out, self.sample_rate);
+    }
+}
+
+// Opens the default output device and starts it playing silence
+pub fn open(sdl_context: &Sdl, synth: Synth) -> Result<AudioDevice<Speaker>, String> {
+    let audio_subsystem = sdl_context.audio()?;
+    let desired = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(BUFFER_SAMPLES),
+    };
+    let device = audio_subsystem.open_playback(None, &desired, |spec| Speaker {
+        synth,
+        sample_rate: spec.freq as u32,
+    })?;
+    device.resume();
+    Ok(device)
+}
@@ -0,0 +1,535 @@
+use chip8_core::{Frame, TIMER_HZ};
+use sdl2::pixels::Color;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+// Containers a recording can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    Gif, // Animated, frames that don't change are merged into one
+    Y4m, // Uncompressed 4:4:4 video at 60 fps, for feeding to an encoder
+}
+
+impl VideoFormat {
+    // Names accepted by VideoFormat::from_name
+    pub const NAMES: [&'static str; 2] = ["gif", "y4m"];
+
+    pub fn from_name(name: &str) -> Option<VideoFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+        }
+    }
+}
+
+// A frame scaled up to the window size, one palette index per pixel.
+// Pixel edges are placed the same way draw_screen places them.
+fn scale(frame: &Frame, width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as usize {
+        let row = y * frame.height / height as usize;
+        for x in 0..width as usize {
+            let col = x * frame.width / width as usize;
+            pixels.push(frame.pixels[row * frame.width + col] & 3);
+        }
+    }
+    pixels
+}
+
+// Next path like roms/PONG-3.png that doesn't exist yet
+pub fn next_path(rom_path: &str, extension: &str) -> String {
+    (1..)
+        .map(|n| format!("{}-{}.{}", rom_path, n, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+// Writes the frame as an indexed PNG at the window size
+pub fn screenshot(
+    path: &str,
+    frame: &Frame,
+    width: u32,
+    height: u32,
+    palette: &[Color; 4],
+) -> Result<(), String> {
+    let write = || -> Result<(), png::EncodingError> {
+        let file = File::create(path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(
+            palette
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b])
+                .collect::<Vec<_>>(),
+        );
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&scale(frame, width, height))
+    };
+    write().map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+// A recording in progress, frames are added at 60 Hz and streamed to disk
+pub struct Recorder {
+    out: BufWriter<File>,
+    format: VideoFormat,
+    width: u32,
+    height: u32,
+    palette: [Color; 4],
+    pending: Option<Vec<u8>>, // GIF frame waiting to find out how long it lasts
+    shown: Option<Vec<u8>>,   // Last GIF frame written
+    frames: u64,              // Frames added so far
+    written: u64,             // Hundredths of a second of GIF written so far
+}
+
+impl Recorder {
+    pub fn create(
+        path: &str,
+        format: VideoFormat,
+        width: u32,
+        height: u32,
+        palette: &[Color; 4],
+    ) -> io::Result<Recorder> {
+        let mut recorder = Recorder {
+            out: BufWriter::new(File::create(path)?),
+            format,
+            width,
+            height,
+            palette: *palette,
+            pending: None,
+            shown: None,
+            frames: 0,
+            written: 0,
+        };
+        recorder.write_header()?;
+        Ok(recorder)
+    }
+
+    // Number of 60 Hz frames recorded
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn add_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let pixels = scale(frame, self.width, self.height);
+        match self.format {
+            VideoFormat::Gif => {
+                if self.pending.as_ref() != Some(&pixels) {
+                    self.flush_pending()?;
+                    self.pending = Some(pixels);
+                }
+            }
+            VideoFormat::Y4m => self.write_y4m_frame(&pixels)?,
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Writes out anything buffered and ends the file
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == VideoFormat::Gif {
+            self.flush_pending()?;
+            self.out.write_all(&[0x3B])?;
+        }
+        self.out.flush()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.format {
+            VideoFormat::Gif => {
+                self.out.write_all(b"GIF89a")?;
+                self.out.write_all(&(self.width as u16).to_le_bytes())?;
+                self.out.write_all(&(self.height as u16).to_le_bytes())?;
+                // Global colour table of 4 entries, background 0, square pixels
+                self.out.write_all(&[0x81, 0, 0])?;
+                for color in self.palette {
+                    self.out.write_all(&[color.r, color.g, color.b])?;
+                }
+                // Loop forever
+                self.out.write_all(&[0x21, 0xFF, 11])?;
+                self.out.write_all(b"NETSCAPE2.0")?;
+                self.out.write_all(&[3, 1, 0, 0, 0])
+            }
+            VideoFormat::Y4m => writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                self.width, self.height, TIMER_HZ
+            ),
+        }
+    }
+
+    // Writes the pending GIF frame, which lasts until the current frame.
+    // Delays are in hundredths of a second, so they're worked out from the
+    // total time to stop rounding from adding up.
+    fn flush_pending(&mut self) -> io::Result<()> {
+        let Some(pixels) = self.pending.take() else {
+            return Ok(());
+        };
+        let end = (self.frames * 100 + TIMER_HZ as u64 / 2) / TIMER_HZ as u64;
+        let delay = (end - self.written).min(u16::MAX as u64) as u16;
+        self.written = end;
+
+        // Only the part that changed is stored, drawn over the frame before
+        let (left, top, width, height) = match &self.shown {
+            Some(shown) => changed_area(shown, &pixels, self.width as usize),
+            None => (0, 0, self.width as usize, self.height as usize),
+        };
+        let area: Vec<u8> = pixels
+            .chunks(self.width as usize)
+            .skip(top)
+            .take(height)
+            .flat_map(|row| &row[left..left + width])
+            .copied()
+            .collect();
+
+        // Graphic control extension with the delay, keeping the frame
+        // underneath
+        self.out.write_all(&[0x21, 0xF9, 4, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        // Image descriptor
+        self.out.write_all(&[0x2C])?;
+        for value in [left, top, width, height] {
+            self.out.write_all(&(value as u16).to_le_bytes())?;
+        }
+        self.out.write_all(&[0])?;
+
+        self.out.write_all(&[GIF_MIN_CODE_SIZE])?;
+        for block in lzw_encode(&area).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])?;
+        self.shown = Some(pixels);
+        Ok(())
+    }
+
+    // Y4M stores the Y, U and V planes one after another
+    fn write_y4m_frame(&mut self, pixels: &[u8]) -> io::Result<()> {
+        let yuv = self.palette.map(to_yuv);
+        let mut planes = [Vec::new(), Vec::new(), Vec::new()];
+        for pixel in pixels {
+            for (plane, value) in planes.iter_mut().zip(yuv[*pixel as usize]) {
+                plane.push(value);
+            }
+        }
+        self.out.write_all(b"FRAME\n")?;
+        for plane in planes {
+            self.out.write_all(&plane)?;
+        }
+        Ok(())
+    }
+}
+
+// Smallest rectangle holding every pixel that differs, as left, top, width
+// and height. Frames are only compared once they're known to differ.
+fn changed_area(before: &[u8], after: &[u8], width: usize) -> (usize, usize, usize, usize) {
+    let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+    for (i, _) in before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+    {
+        let (x, y) = (i % width, i / width);
+        left = left.min(x);
+        top = top.min(y);
+        right = right.max(x);
+        bottom = bottom.max(y);
+    }
+    (left, top, right - left + 1, bottom - top + 1)
+}
+
+// BT.601 studio range, what Y4M readers assume without a colour range tag
+fn to_yuv(color: Color) -> [u8; 3] {
+    let (r, g, b) = (color.r as f32, color.g as f32, color.b as f32);
+    let y = 16. + (65.481 * r + 128.553 * g + 24.966 * b) / 255.;
+    let u = 128. + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.;
+    let v = 128. + (112.0 * r - 93.786 * g - 18.214 * b) / 255.;
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
+
+// Smallest code size GIF allows, enough for the 4 colour palette
+const GIF_MIN_CODE_SIZE: u8 = 2;
+const GIF_MAX_CODES: u16 = 4096;
+
+// Variable width LZW as used by GIF, codes are packed least significant
+// bit first
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = GIF_MIN_CODE_SIZE as u32 + 1;
+
+    let mut out = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut emit = |code: u16, size: u32| {
+        bits |= (code as u32) << count;
+        count += size;
+        while count >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            count -= 8;
+        }
+    };
+
+    emit(clear, size);
+    let mut prefix: Option<u16> = None;
+    for &pixel in pixels {
+        let Some(code) = prefix else {
+            prefix = Some(pixel as u16);
+            continue;
+        };
+        if let Some(&longer) = table.get(&(code, pixel)) {
+            prefix = Some(longer);
+            continue;
+        }
+        emit(code, size);
+        if next < GIF_MAX_CODES {
+            table.insert((code, pixel), next);
+            // Decoders widen their codes once the table outgrows them
+            if next == 1 << size && size < 12 {
+                size += 1;
+            }
+            next += 1;
+        } else {
+            emit(clear, size);
+            table.clear();
+            next = end + 1;
+            size = GIF_MIN_CODE_SIZE as u32 + 1;
+        }
+        prefix = Some(pixel as u16);
+    }
+    if let Some(code) = prefix {
+        emit(code, size);
+        // Decoders add an entry for the last code too, which can widen the
+        // end code
+        if next == 1 << size && size < 12 {
+            size += 1;
+        }
+    }
+    emit(end, size);
+    if count > 0 {
+        out.push(bits as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    // Reference GIF LZW decoder, returning the pixels and how many times
+    // the table was cleared
+    fn lzw_decode(data: &[u8]) -> (Vec<u8>, usize) {
+        let clear = 1usize << GIF_MIN_CODE_SIZE;
+        let end = clear + 1;
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            table.clear();
+            table.extend((0..clear).map(|i| vec![i as u8]));
+            table.extend([Vec::new(), Vec::new()]);
+        };
+        let mut table = Vec::new();
+        reset(&mut table);
+        let mut size = GIF_MIN_CODE_SIZE as usize + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let (mut pixels, mut clears) = (Vec::new(), 0);
+
+        let mut pos = 0;
+        loop {
+            let code = (0..size).fold(0, |code, bit| {
+                let bit_pos = pos + bit;
+                code | ((data[bit_pos / 8] as usize >> (bit_pos % 8)) & 1) << bit
+            });
+            pos += size;
+
+            if code == clear {
+                reset(&mut table);
+                size = GIF_MIN_CODE_SIZE as usize + 1;
+                prev = None;
+                clears += 1;
+                continue;
+            }
+            if code == end {
+                return (pixels, clears);
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [&prev[..], &prev[..1]].concat(),
+                (None, None) => panic!("code {} before any pixels", code),
+            };
+            pixels.extend_from_slice(&entry);
+            if let Some(prev) = prev {
+                if table.len() < GIF_MAX_CODES as usize {
+                    table.push([&prev[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for pixels in [
+            vec![0],
+            vec![3; 1000],
+            vec![1, 2, 1, 1, 3, 0, 2, 0, 1, 0, 0],
+        ] {
+            assert_eq!(lzw_decode(&lzw_encode(&pixels)), (pixels, 1));
+        }
+    }
+
+    // Every short image, which between them end on every code width change
+    #[test]
+    fn lzw_round_trip_short_images() {
+        for len in 1..=8 {
+            for n in 0..1 << (2 * len) {
+                let pixels: Vec<u8> = (0..len).map(|i| (n >> (2 * i)) as u8 & 3).collect();
+                assert_eq!(lzw_decode(&lzw_encode(&pixels)).0, pixels);
+            }
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip_through_table_resets() {
+        let mut state = 1u32;
+        let pixels: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8 & 3
+            })
+            .collect();
+        let (decoded, clears) = lzw_decode(&lzw_encode(&pixels));
+        assert_eq!(decoded, pixels);
+        assert!(clears > 1);
+    }
+
+    // What a GIF holds: the delay and area of each image, with its pixels
+    struct Image {
+        delay: u16,
+        area: [u16; 4],
+        pixels: Vec<u8>,
+    }
+
+    fn read_gif(data: &[u8]) -> Vec<Image> {
+        let word = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+        // Sub-blocks from pos, returning them joined and the position after
+        let blocks = |mut pos: usize| {
+            let mut joined = Vec::new();
+            while data[pos] != 0 {
+                let len = data[pos] as usize;
+                joined.extend_from_slice(&data[pos + 1..pos + 1 + len]);
+                pos += 1 + len;
+            }
+            (joined, pos + 1)
+        };
+
+        assert_eq!(&data[..6], b"GIF89a");
+        let mut pos = 13 + 4 * 3;
+        let mut images = Vec::new();
+        let mut delay = 0;
+        loop {
+            match data[pos] {
+                0x21 => {
+                    if data[pos + 1] == 0xF9 {
+                        delay = word(pos + 4);
+                    }
+                    pos = blocks(pos + 2).1;
+                }
+                0x2C => {
+                    let area = [word(pos + 1), word(pos + 3), word(pos + 5), word(pos + 7)];
+                    assert_eq!(data[pos + 10], GIF_MIN_CODE_SIZE);
+                    let (compressed, next) = blocks(pos + 11);
+                    let pixels = lzw_decode(&compressed).0;
+                    images.push(Image {
+                        delay,
+                        area,
+                        pixels,
+                    });
+                    pos = next;
+                }
+                0x3B => return images,
+                other => panic!("unexpected block {:#04X}", other),
+            }
+        }
+    }
+
+    const PALETTE: [Color; 4] = [Color::BLACK, Color::WHITE, Color::RED, Color::BLUE];
+
+    // Records frames of a 4x2 screen at twice the size, returning the GIF
+    fn record_gif(name: &str, frames: &[[u8; 8]]) -> Vec<u8> {
+        let path = env::temp_dir().join(format!("chip8_capture_{}_{}.gif", name, process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = Recorder::create(path, VideoFormat::Gif, 8, 4, &PALETTE).unwrap();
+        for pixels in frames {
+            let frame = Frame {
+                width: 4,
+                height: 2,
+                pixels,
+            };
+            recorder.add_frame(&frame).unwrap();
+        }
+        assert_eq!(recorder.frames(), frames.len() as u64);
+        recorder.finish().unwrap();
+        let data = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        data
+    }
+
+    #[test]
+    fn gif_merges_repeated_frames() {
+        let blank = [0; 8];
+        let mut dot = [0; 8];
+        dot[5] = 1;
+        let mut frames = vec![blank; 30];
+        frames.extend([dot; 45]);
+        frames.extend([blank; 1]);
+
+        let images = read_gif(&record_gif("merge", &frames));
+        assert_eq!(images.len(), 3);
+        // Half a second, then three quarters, then the last frame rounded
+        let delays: Vec<u16> = images.iter().map(|image| image.delay).collect();
+        assert_eq!(delays, [50, 75, 2]);
+
+        // The first image is the whole screen, later ones just what changed
+        assert_eq!(images[0].area, [0, 0, 8, 4]);
+        assert_eq!(images[0].pixels, [0; 32]);
+        assert_eq!(images[1].area, [2, 2, 2, 2]);
+        assert_eq!(images[1].pixels, [1; 4]);
+        assert_eq!(images[2].area, [2, 2, 2, 2]);
+        assert_eq!(images[2].pixels, [0; 4]);
+    }
+
+    #[test]
+    fn gif_delays_add_up_to_the_recording() {
+        // Frames alternating every 1 to 4 ticks don't drift from rounding
+        let mut frames = Vec::new();
+        for run in 0..200 {
+            let mut pixels = [0; 8];
+            pixels[run % 8] = 3;
+            frames.extend(vec![pixels; run % 4 + 1]);
+        }
+        let images = read_gif(&record_gif("delays", &frames));
+        let total: u64 = images.iter().map(|image| image.delay as u64).sum();
+        assert_eq!(total, (frames.len() as u64 * 100 + 30) / 60);
+        assert!(images.iter().all(|image| image.delay > 0));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::controller;
+use chip8_core::{Keymap, Palette, Rom};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, io::ErrorKind, slice};
+
+// Read from the working directory when no --config is given
+pub const DEFAULT_PATH: &str = "chip8.toml";
+
+// Settings read from a TOML file like:
+//
+//   layout = "azerty"
+//
+//   # A preset, or background and foreground colours, or all four
+//   # colours used with XO-CHIP bitplanes
+//   palette = "amber"
+//
+//   [keys]
+//   5 = ["Z", "Up"]
+//
+//   # Game controller buttons, by SDL name
+//   [controller]
+//   5 = ["a", "rightshoulder"]
+//
+//   # Overrides for one rom, keyed by its SHA-1
+//   [roms.b9272ae1acdaaa79ab649f6b48b72088ca2b1d74]
+//   palette = "#000000,#33ff66"
+//
+//   [roms.b9272ae1acdaaa79ab649f6b48b72088ca2b1d74.keys]
+//   1 = "Up"
+//   4 = "Down"
+//
+//   [roms.b9272ae1acdaaa79ab649f6b48b72088ca2b1d74.controller]
+//   1 = "dpup"
+//   4 = "dpdown"
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    layout: Option<String>,
+    palette: Option<String>,
+    keys: BTreeMap<String, HostKeys>,
+    controller: BTreeMap<String, HostKeys>,
+    roms: BTreeMap<String, RomConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RomConfig {
+    layout: Option<String>,
+    palette: Option<String>,
+    keys: BTreeMap<String, HostKeys>,
+    controller: BTreeMap<String, HostKeys>,
+}
+
+// Host keys for a keypad key, either one name or a list of them
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HostKeys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl HostKeys {
+    fn names(&self) -> &[String] {
+        match self {
+            HostKeys::One(host) => slice::from_ref(host),
+            HostKeys::Many(hosts) => hosts,
+        }
+    }
+}
+
+impl Config {
+    // Reads a config file, the default one is allowed to be missing
+    pub fn load(path: &str) -> Result<Config, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound && path == DEFAULT_PATH => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(format!("Unable to read {}: {}", path, e)),
+        };
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path, e))
+    }
+
+    // Keymap for a rom, with its overrides on top of the global keys
+    pub fn keymap(&self, rom: &Rom) -> Result<Keymap, String> {
+        let overrides = self.roms.get(&rom.sha1_hex());
+        let layout = overrides
+            .and_then(|rom| rom.layout.as_ref())
+            .or(self.layout.as_ref());
+        let mut keymap = match layout {
+            Some(name) => {
+                Keymap::from_name(name).ok_or(format!("Unknown keyboard layout: {}", name))?
+            }
+            None => Keymap::default(),
+        };
+
+        set_keys(&mut keymap, &self.keys)?;
+        if let Some(rom) = overrides {
+            set_keys(&mut keymap, &rom.keys)?;
+        }
+        Ok(keymap)
+    }
+
+    // Controller profile for a rom, built up the same way as the keymap on
+    // top of the buttons the rom database recommends
+    pub fn controller_profile(
+        &self,
+        rom: &Rom,
+        recommended: &[(String, u8)],
+    ) -> Result<Keymap, String> {
+        let overrides = self.roms.get(&rom.sha1_hex());
+        let mut profile = controller::rom_profile(recommended);
+
+        set_buttons(&mut profile, &self.controller)?;
+        if let Some(rom) = overrides {
+            set_buttons(&mut profile, &rom.controller)?;
+        }
+        Ok(profile)
+    }
+
+    // Palette for a rom, its own if it has one, then the global one, then
+    // the one the rom database recommends for it
+    pub fn palette(&self, rom: &Rom, recommended: Option<Palette>) -> Result<Palette, String> {
+        let spec = self
+            .roms
+            .get(&rom.sha1_hex())
+            .and_then(|rom| rom.palette.as_ref())
+            .or(self.palette.as_ref());
+        match (spec, recommended) {
+            (Some(spec), _) => Palette::parse(spec).ok_or(format!("Invalid palette: {}", spec)),
+            (None, Some(palette)) => Ok(palette),
+            (None, None) => Ok(Palette::default()),
+        }
+    }
+}
+
+fn set_keys(keymap: &mut Keymap, keys: &BTreeMap<String, HostKeys>) -> Result<(), String> {
+    for (name, hosts) in keys {
+        let key = Keymap::parse_key(name).ok_or(format!("Unknown keypad key: {}", name))?;
+        keymap.set(key, hosts.names());
+    }
+    Ok(())
+}
+
+fn set_buttons(profile: &mut Keymap, buttons: &BTreeMap<String, HostKeys>) -> Result<(), String> {
+    for button in buttons.values().flat_map(HostKeys::names) {
+        if !controller::is_button(button) {
+            return Err(format!("Unknown controller button: {}", button));
+        }
+    }
+    set_keys(profile, buttons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn config(text: &str) -> Config {
+        let text = text.replace("SHA1", &Rom::new(&ROM).sha1_hex());
+        toml::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn configured_palettes_win_over_the_recommended_one() {
+        let rom = Rom::new(&ROM);
+        let recommended = Some(Palette::octo());
+
+        let global = config("palette = \"amber\"");
+        assert_eq!(global.palette(&rom, recommended), Ok(Palette::amber()));
+
+        let own = config("palette = \"amber\"\n[roms.SHA1]\npalette = \"lcd\"");
+        assert_eq!(own.palette(&rom, recommended), Ok(Palette::lcd()));
+        // Only for that rom
+        let other = Rom::new(&[0x12, 0x02]);
+        assert_eq!(own.palette(&other, recommended), Ok(Palette::amber()));
+    }
+
+    #[test]
+    fn recommended_palette_fills_in_when_none_is_configured() {
+        let rom = Rom::new(&ROM);
+        let empty = config("");
+        assert_eq!(
+            empty.palette(&rom, Some(Palette::octo())),
+            Ok(Palette::octo())
+        );
+        assert_eq!(empty.palette(&rom, None), Ok(Palette::default()));
+
+        let invalid = config("palette = \"#12345\"");
+        assert!(invalid.palette(&rom, Some(Palette::octo())).is_err());
+    }
+}
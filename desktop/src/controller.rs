@@ -0,0 +1,200 @@
+use chip8_core::{Keymap, CPU};
+use sdl2::{
+    controller::{Button, GameController},
+    GameControllerSubsystem,
+};
+
+// Buttons bound when the config doesn't say otherwise. The D-pad sits on
+// 2/4/6/8, the arrows of the keypad, with A on 5 in the middle of them.
+const DEFAULT_PROFILE: [(Button, u8); 12] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::A, 0x5),
+    (Button::B, 0x0),
+    (Button::X, 0xA),
+    (Button::Y, 0xB),
+    (Button::LeftShoulder, 0x1),
+    (Button::RightShoulder, 0xC),
+    (Button::Back, 0xE),
+    (Button::Start, 0xF),
+];
+
+// Controller profiles are keymaps from SDL button names like "dpup"
+fn default_profile() -> Keymap {
+    let mut profile = Keymap::new();
+    for (button, key) in DEFAULT_PROFILE {
+        profile.bind(&button.string(), key);
+    }
+    profile
+}
+
+// Where the rom database's key hints go on the controller
+const ROM_BUTTONS: [(&str, Button); 5] = [
+    ("up", Button::DPadUp),
+    ("down", Button::DPadDown),
+    ("left", Button::DPadLeft),
+    ("right", Button::DPadRight),
+    ("a", Button::A),
+];
+
+// The default profile with buttons moved onto the keys the rom database
+// gives for a rom, like PONG's 1 and 4 on up and down. Two player roms
+// get player one's keys.
+pub fn rom_profile(keys: &[(String, u8)]) -> Keymap {
+    let mut profile = default_profile();
+    for (action, key) in keys {
+        let action = action.strip_prefix("player1").unwrap_or(action);
+        let button = ROM_BUTTONS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(action));
+        if let Some((_, button)) = button {
+            profile.bind(&button.string(), *key);
+        }
+    }
+    profile
+}
+
+// Presses or releases whatever keypad key a button is bound to
+pub fn press(cpu: &mut CPU, profile: &Keymap, button: Button, pressed: bool) {
+    if let Some(k) = profile.key(&button.string()) {
+        cpu.keypress(k, pressed);
+    }
+}
+
+pub fn is_button(name: &str) -> bool {
+    Button::from_string(&name.to_lowercase()).is_some()
+}
+
+// Game controllers currently plugged in. SDL reports ones already
+// connected at startup as added too, so everything goes through add.
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    open: Vec<GameController>,
+}
+
+impl Controllers {
+    pub fn new(subsystem: GameControllerSubsystem) -> Controllers {
+        Controllers {
+            subsystem,
+            open: Vec::new(),
+        }
+    }
+
+    // Opens the controller at a joystick index
+    pub fn add(&mut self, index: u32) {
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                println!("Controller connected: {}", controller.name());
+                self.open.push(controller);
+            }
+            Err(e) => eprintln!("Unable to open controller {}: {}", index, e),
+        }
+    }
+
+    // Closes the controller with a joystick instance id
+    pub fn remove(&mut self, id: u32) {
+        if let Some(pos) = self.open.iter().position(|c| c.instance_id() == id) {
+            let controller = self.open.remove(pos);
+            println!("Controller disconnected: {}", controller.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::{event::Event, hint, sys};
+
+    fn keys(pairs: &[(&str, u8)]) -> Vec<(String, u8)> {
+        pairs
+            .iter()
+            .map(|(name, key)| (name.to_string(), *key))
+            .collect()
+    }
+
+    #[test]
+    fn rom_keys_move_buttons() {
+        let pong = keys(&[
+            ("player1Up", 0x1),
+            ("player1Down", 0x4),
+            ("player2Up", 0xC),
+            ("player2Down", 0xD),
+        ]);
+        let profile = rom_profile(&pong);
+        assert_eq!(profile.key("dpup"), Some(0x1));
+        assert_eq!(profile.key("dpdown"), Some(0x4));
+        // Buttons the rom doesn't mention keep their defaults
+        assert_eq!(profile.key("dpleft"), Some(0x4));
+        assert_eq!(profile.key("a"), Some(0x5));
+
+        let profile = rom_profile(&keys(&[("left", 0x7), ("a", 0x0)]));
+        assert_eq!(profile.key("dpleft"), Some(0x7));
+        assert_eq!(profile.key("a"), Some(0x0));
+    }
+
+    // Drives the keypad from an SDL virtual controller, the way main does
+    // with a real one
+    #[test]
+    fn virtual_controller_presses_keys() {
+        hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
+        let sdl = sdl2::init().unwrap();
+        let joysticks = sdl.joystick().unwrap();
+        let subsystem = sdl.game_controller().unwrap();
+        let mut events = sdl.event_pump().unwrap();
+
+        let index = unsafe {
+            sys::SDL_JoystickAttachVirtual(
+                sys::SDL_JoystickType::SDL_JOYSTICK_TYPE_GAMECONTROLLER,
+                0,
+                15,
+                0,
+            )
+        };
+        assert!(index >= 0, "{}", sdl2::get_error());
+        let index = index as u32;
+
+        // Its buttons in SDL's own order, so the D-pad is 11 to 14
+        let guid = joysticks.device_guid(index).unwrap();
+        subsystem
+            .add_mapping(&format!(
+                "{},Virtual,a:b0,b:b1,x:b2,y:b3,back:b4,guide:b5,start:b6,leftstick:b7,\
+                 rightstick:b8,leftshoulder:b9,rightshoulder:b10,dpup:b11,dpdown:b12,\
+                 dpleft:b13,dpright:b14,",
+                guid
+            ))
+            .unwrap();
+        let mut controllers = Controllers::new(subsystem);
+        controllers.add(index);
+        let id = controllers.open[0].instance_id();
+        let joystick = unsafe { sys::SDL_JoystickFromInstanceID(id as i32) };
+
+        let profile = rom_profile(&keys(&[("player1Up", 0x1), ("player1Down", 0x4)]));
+        let mut cpu = CPU::default();
+        let mut set_button = |button: i32, pressed: bool| {
+            unsafe { sys::SDL_JoystickSetVirtualButton(joystick, button, pressed as u8) };
+            for event in events.poll_iter() {
+                match event {
+                    Event::ControllerButtonDown { button, .. } => {
+                        press(&mut cpu, &profile, button, true)
+                    }
+                    Event::ControllerButtonUp { button, .. } => {
+                        press(&mut cpu, &profile, button, false)
+                    }
+                    _ => (),
+                }
+            }
+            cpu.keys()
+        };
+
+        assert_eq!(set_button(11, true), 1 << 0x1);
+        assert_eq!(set_button(11, false), 0);
+        assert_eq!(set_button(12, true), 1 << 0x4);
+        assert_eq!(set_button(14, true), 1 << 0x4 | 1 << 0x6);
+        assert_eq!(set_button(12, false), 1 << 0x6);
+
+        controllers.remove(id);
+        unsafe { sys::SDL_JoystickDetachVirtual(index as i32) };
+    }
+}
@@ -0,0 +1,348 @@
+use chip8_core::*;
+use std::{
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+// Instructions run per command before handing back to the frame loop
+const STEP_BUDGET: usize = 1000;
+
+const HELP: &str = "\
+Addresses can also be labels from the symbol map given with --symbols.
+
+Commands:
+  break ADDR             add a breakpoint            (b)
+  delete ADDR            remove a breakpoint         (d)
+  watch ADDR [r|w|rw]    add a memory watchpoint
+  unwatch ADDR           remove watchpoints on ADDR
+  cond REG OP VALUE      break when e.g. `V3 == 0x10` becomes true
+  uncond N               remove conditional breakpoint N
+  info                   list breakpoints, watchpoints and conditions
+  step [N]               execute N instructions      (s)
+  next                   step over a call            (n)
+  finish                 run until the subroutine returns
+  continue               run until something breaks  (c)
+  pause                  stop a running program
+  regs                   show registers
+  stack                  show the call stack
+  mem ADDR [LEN]         dump memory
+  quit                   exit the emulator           (q)";
+
+// Debugger REPL reading commands from stdin
+pub struct Console {
+    commands: Receiver<String>,
+    paused: bool,
+    symbols: Symbols, // Labels to show and accept in place of addresses
+}
+
+impl Console {
+    // Starts reading stdin on a background thread, the program starts paused
+    pub fn spawn(symbols: Symbols) -> Console {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("Debugger started, type `help` for commands");
+        prompt();
+        Console {
+            commands,
+            paused: true,
+            symbols,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    // Handles any commands typed since the last frame, returns false to quit
+    pub fn poll(&mut self, debugger: &mut Debugger) -> bool {
+        while let Ok(line) = self.commands.try_recv() {
+            match self.execute(debugger, &line) {
+                Ok(true) => (),
+                Ok(false) => return false,
+                Err(msg) => println!("{}", msg),
+            }
+            if self.paused {
+                prompt();
+            }
+        }
+        true
+    }
+
+    // Reports why the program stopped and waits for the next command
+    pub fn stopped(&mut self, debugger: &Debugger, reason: StopReason) {
+        self.halt(debugger, reason);
+        prompt();
+    }
+
+    // Reports why the program stopped and pauses it
+    fn halt(&mut self, debugger: &Debugger, reason: StopReason) {
+        match reason {
+            StopReason::Breakpoint { pc } => {
+                println!("Breakpoint at {:#05X}{}", pc, label(&self.symbols, pc))
+            }
+            StopReason::Watchpoint { pc, addr, access } => {
+                println!(
+                    "{:?} of {:#05X} by instruction at {:#05X}",
+                    access, addr, pc
+                )
+            }
+            StopReason::Condition { index } => println!("Condition {} is true", index),
+            StopReason::Step => (),
+            StopReason::Exit => println!("Program exited"),
+            StopReason::Error { pc, error } => println!("Error at {:#05X}: {}", pc, error),
+        }
+        self.paused = true;
+        print_location(debugger.cpu(), &self.symbols);
+    }
+
+    fn execute(&mut self, debugger: &mut Debugger, line: &str) -> Result<bool, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+
+        match *command {
+            "help" | "h" => println!("{}", HELP),
+            "break" | "b" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                debugger.add_breakpoint(addr);
+                println!("Breakpoint at {:#05X}", addr);
+            }
+            "delete" | "d" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                if !debugger.remove_breakpoint(addr) {
+                    println!("No breakpoint at {:#05X}", addr);
+                }
+            }
+            "watch" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                let kinds: &[Access] = match args.get(1).copied().unwrap_or("rw") {
+                    "r" => &[Access::Read],
+                    "w" => &[Access::Write],
+                    "rw" => &[Access::Read, Access::Write],
+                    other => return Err(format!("Unknown access `{}`, use r, w or rw", other)),
+                };
+                for access in kinds {
+                    debugger.add_watchpoint(addr, *access);
+                }
+                println!("Watching {:#05X}", addr);
+            }
+            "unwatch" => {
+                let addr = parse_address(args.first(), &self.symbols)?;
+                if !debugger.remove_watchpoint(addr) {
+                    println!("No watchpoint on {:#05X}", addr);
+                }
+            }
+            "cond" => {
+                let [register, comparison, value] = args else {
+                    return Err("Usage: cond REG OP VALUE".to_string());
+                };
+                let condition = Condition {
+                    register: parse_register(register)?,
+                    comparison: parse_comparison(comparison)?,
+                    value: parse_number(Some(value))?,
+                };
+                let index = debugger.add_condition(condition);
+                println!("Condition {} added", index);
+            }
+            "uncond" => {
+                let index = parse_number(args.first())? as usize;
+                if !debugger.remove_condition(index) {
+                    println!("No condition {}", index);
+                }
+            }
+            "info" => print_info(debugger),
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(_) => parse_number(args.first())?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    let reason = debugger.step();
+                    if reason != StopReason::Step {
+                        self.halt(debugger, reason);
+                        return Ok(true);
+                    }
+                }
+                print_location(debugger.cpu(), &self.symbols);
+            }
+            "next" | "n" => {
+                let reason = debugger.step_over(STEP_BUDGET);
+                self.resume(debugger, reason);
+            }
+            "finish" => {
+                let reason = debugger.step_out(STEP_BUDGET);
+                self.resume(debugger, reason);
+            }
+            "continue" | "c" => self.paused = false,
+            "pause" => {
+                if !self.paused {
+                    self.halt(debugger, StopReason::Step);
+                }
+            }
+            "regs" | "r" => print_registers(debugger.cpu()),
+            "stack" => print_stack(debugger.cpu(), &self.symbols),
+            "mem" | "m" => {
+                let addr = parse_address(args.first(), &self.symbols)? as usize;
+                let len = match args.get(1) {
+                    Some(_) => parse_number(args.get(1))? as usize,
+                    None => 16,
+                };
+                print_memory(debugger.cpu(), addr, len);
+            }
+            "quit" | "q" => return Ok(false),
+            _ => return Err(format!("Unknown command `{}`, type `help`", command)),
+        }
+        Ok(true)
+    }
+
+    // Keeps running a step over or step out that didn't finish in one go
+    fn resume(&mut self, debugger: &Debugger, reason: Option<StopReason>) {
+        match reason {
+            Some(reason) => self.halt(debugger, reason),
+            None => self.paused = false,
+        }
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    io::stdout().flush().ok();
+}
+
+// Accepts 0x prefixed hex or decimal
+fn parse_number(arg: Option<&&str>) -> Result<u16, String> {
+    let arg = arg.ok_or("Missing number")?;
+    let parsed = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number `{}`", arg))
+}
+
+// A number, or a label from the symbol map
+fn parse_address(arg: Option<&&str>, symbols: &Symbols) -> Result<u16, String> {
+    match arg.and_then(|name| symbols.address(name)) {
+        Some(addr) => Ok(addr),
+        None => parse_number(arg),
+    }
+}
+
+// Names an address after the label it falls under, like ` <draw+0x4>`
+fn label(symbols: &Symbols, addr: u16) -> String {
+    match symbols.locate(addr) {
+        Some((name, 0)) => format!(" <{}>", name),
+        Some((name, offset)) => format!(" <{}+{:#X}>", name, offset),
+        None => String::new(),
+    }
+}
+
+fn parse_register(name: &str) -> Result<Register, String> {
+    let upper = name.to_ascii_uppercase();
+    let register = match upper.as_str() {
+        "I" => Register::I,
+        "PC" => Register::Pc,
+        "SP" => Register::Sp,
+        "DT" => Register::Dt,
+        "ST" => Register::St,
+        _ => {
+            let index = upper
+                .strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .ok_or(format!("Unknown register `{}`", name))?;
+            Register::V(index)
+        }
+    };
+    Ok(register)
+}
+
+fn parse_comparison(op: &str) -> Result<Comparison, String> {
+    match op {
+        "==" => Ok(Comparison::Eq),
+        "!=" => Ok(Comparison::Ne),
+        "<" => Ok(Comparison::Lt),
+        "<=" => Ok(Comparison::Le),
+        ">" => Ok(Comparison::Gt),
+        ">=" => Ok(Comparison::Ge),
+        _ => Err(format!("Unknown comparison `{}`", op)),
+    }
+}
+
+fn print_location(cpu: &CPU, symbols: &Symbols) {
+    let pc = cpu.program_counter() as usize;
+    match cpu.memory().get(pc..pc + 2) {
+        Some(bytes) => {
+            let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+            let instruction = Instruction::decode(opcode);
+            let label = label(symbols, pc as u16);
+            println!("{:#05X}{}: {:04X}  {}", pc, label, opcode, instruction);
+        }
+        None => println!("{:#05X}: out of bounds", pc),
+    }
+}
+
+fn print_registers(cpu: &CPU) {
+    for (row, regs) in cpu.v_registers().chunks(8).enumerate() {
+        let line: Vec<String> = regs
+            .iter()
+            .enumerate()
+            .map(|(i, val)| format!("V{:X}={:02X}", row * 8 + i, val))
+            .collect();
+        println!("{}", line.join(" "));
+    }
+    println!(
+        "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+        cpu.program_counter(),
+        cpu.i_register(),
+        cpu.stack().len(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    );
+}
+
+fn print_stack(cpu: &CPU, symbols: &Symbols) {
+    if cpu.stack().is_empty() {
+        println!("Stack is empty");
+    }
+    for (depth, addr) in cpu.stack().iter().enumerate().rev() {
+        println!("#{} {:#05X}{}", depth, addr, label(symbols, *addr));
+    }
+}
+
+fn print_memory(cpu: &CPU, addr: usize, len: usize) {
+    let memory = cpu.memory();
+    let end = (addr + len).min(memory.len());
+    if addr >= end {
+        println!("Address out of bounds");
+        return;
+    }
+    for (row, bytes) in memory[addr..end].chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("{:#06X}: {}", addr + row * 16, hex.join(" "));
+    }
+}
+
+fn print_info(debugger: &Debugger) {
+    for addr in debugger.breakpoints() {
+        println!("Breakpoint {:#05X}", addr);
+    }
+    for (addr, access) in debugger.watchpoints() {
+        println!("Watchpoint {:#05X} {:?}", addr, access);
+    }
+    for (index, condition) in debugger.conditions().iter().enumerate() {
+        println!(
+            "Condition {}: {:?} {:?} {:#X}",
+            index, condition.register, condition.comparison, condition.value
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use chip8_core::*;
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+// What the connection thread passes to the frame loop
+enum Message {
+    Connected(TcpStream),
+    Received(Vec<u8>),
+    Disconnected,
+}
+
+// Serves the gdb remote protocol on a localhost port. The program waits for
+// gdb to connect and continue it, and runs freely again once gdb detaches.
+pub struct GdbServer {
+    messages: Receiver<Message>,
+    stream: Option<TcpStream>, // Where replies go, while gdb is connected
+    stub: GdbStub,
+}
+
+impl GdbServer {
+    // Accepts one connection at a time on a background thread
+    pub fn listen(port: u16) -> Result<GdbServer, String> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| format!("Unable to listen on port {}: {}", port, e))?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let Ok(writer) = stream.try_clone() else {
+                    continue;
+                };
+                if sender.send(Message::Connected(writer)).is_err() {
+                    return;
+                }
+                let mut buffer = [0; 4096];
+                loop {
+                    let message = match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => Message::Received(buffer[..len].to_vec()),
+                    };
+                    if sender.send(message).is_err() {
+                        return;
+                    }
+                }
+                if sender.send(Message::Disconnected).is_err() {
+                    return;
+                }
+            }
+        });
+
+        println!("Waiting for gdb on localhost:{}", port);
+        Ok(GdbServer {
+            messages,
+            stream: None,
+            stub: GdbStub::new(),
+        })
+    }
+
+    pub fn paused(&self) -> bool {
+        !self.stub.is_running()
+    }
+
+    // Gets stops from the debugger while gdb is connected
+    pub fn connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Handles anything gdb sent since the last frame, returns false to quit
+    pub fn poll(&mut self, debugger: &mut Debugger) -> bool {
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Connected(stream) => {
+                    println!("gdb connected");
+                    self.stream = Some(stream);
+                    self.stub = GdbStub::new();
+                }
+                Message::Received(bytes) => {
+                    let reply = self.stub.receive(debugger, &bytes);
+                    self.send(&reply);
+                    if self.stub.is_killed() {
+                        return false;
+                    }
+                }
+                Message::Disconnected => {
+                    println!("gdb disconnected");
+                    self.stream = None;
+                    self.stub.detach(debugger);
+                }
+            }
+        }
+        true
+    }
+
+    // Tells gdb why the program stopped
+    pub fn stopped(&mut self, reason: StopReason) {
+        let reply = self.stub.stopped(reason);
+        self.send(&reply);
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        if bytes.is_empty() {
+            return;
+        }
+        if let Err(e) = stream.write_all(bytes) {
+            eprintln!("Unable to reply to gdb: {}", e);
+        }
+    }
+}
@@ -1,11 +1,30 @@
+mod audio;
+mod capture;
+mod config;
+mod controller;
+mod debug;
+mod gdb;
+mod movie;
+
+use capture::{Recorder, VideoFormat};
 use chip8_core::*;
+use config::Config;
+use controller::Controllers;
+use debug::Console;
+use gdb::GdbServer;
+use movie::Tape;
 use std::{
     env, 
-    fs::File, 
-    io::Read
+    fmt::Display,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    time::Instant
 };
 use sdl2::{
+    audio::AudioDevice,
     event::Event,
+    messagebox::{show_simple_message_box, MessageBoxFlag},
     pixels::Color,
     rect::Rect,
     render::Canvas,
@@ -16,13 +35,57 @@ use sdl2::{
 const SCALE: u32 = 15;
 const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * SCALE;
 const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * SCALE;
-const TICKS_PER_FRAME: usize = 5;
+const NUM_STATE_SLOTS: u32 = 10;
+const REWIND_INTERVAL: u32 = 4;
+const REWIND_CAPACITY: usize = 900;
+const DEFAULT_FREQUENCY: f32 = 440.;
+const DEFAULT_VOLUME: f32 = 0.25;
+
+struct Options {
+    rom_path: String,
+    platform: Option<Platform>, // Guessed from the rom when not given
+    quirks: Option<Quirks>,     // The platform's when not given
+    load_address: Option<u16>,  // From the rom database, or 0x200
+    seed: Option<u64>,
+    ips: Option<u32>,           // From the rom database, or the default
+    timing: Timing,
+    frequency: f32,
+    volume: f32,
+    muted: bool,
+    debug: bool,
+    symbols: Option<String>,  // Symbol map for the debugger to show labels from
+    gdb: Option<u16>,         // Port to serve the gdb remote protocol on
+    record: Option<String>,   // Where to write an input movie
+    play: Option<String>,     // Input movie to play back
+    config: String,
+    video: VideoFormat,       // Format F11 records in
+    palette: Option<Palette>, // Overrides the config file
+    database: Option<String>, // chip-8-database directory to use instead of the built in one
+    no_database: bool,
+    trace: Option<String>,    // Where to log executed instructions
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+    trace_ring: Option<usize>, // Only log this many instructions before an error
+}
+
 fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        println!("Run with path to rom as argument");
-        return;
-    }
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            println!(
+                "Usage: desktop [--platform {}] [--quirks {}] [--load-address ADDR] [--seed N] [--ips N] [--timing {}] [--frequency HZ] [--volume 0-100] [--mute] [--debug [--symbols FILE] | --gdb PORT] [--record FILE | --play FILE] [--config FILE] [--video {}] [--palette {}|COLOURS] [--database DIR | --no-database] [--trace FILE] [--trace-format {}] [--trace-pc START-END] [--trace-ops CLASSES] [--trace-ring N] <rom>",
+                Platform::NAMES.join("|"),
+                Quirks::NAMES.join("|"),
+                Timing::NAMES.join("|"),
+                VideoFormat::NAMES.join("|"),
+                Palette::NAMES.join("|"),
+                TraceFormat::NAMES.join("|")
+            );
+            return;
+        }
+    };
 
     // SDL setup
     let sdl_context = sdl2::init().unwrap();
@@ -41,79 +104,674 @@ fn main() {
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut chip8 = CPU::new();
+    // Controllers are optional too, and can come and go while running
+    let mut controllers = match sdl_context.game_controller() {
+        Ok(subsystem) => Some(Controllers::new(subsystem)),
+        Err(e) => {
+            eprintln!("Unable to use game controllers: {}", e);
+            None
+        }
+    };
+
+    // Sound is optional, keep going without it if there's no audio device
+    let mut synth = Synth::new(options.frequency, options.volume);
+    synth.muted = options.muted;
+    let mut speaker = match audio::open(&sdl_context, synth) {
+        Ok(device) => Some(device),
+        Err(e) => {
+            eprintln!("Unable to open audio device: {}", e);
+            None
+        }
+    };
+
+    let data = match fs::read(&options.rom_path) {
+        Ok(data) => data,
+        Err(e) => {
+            show_error(&canvas, &format!("Unable to read {}: {}", options.rom_path, e));
+            return;
+        }
+    };
+
+    // Known roms come with settings that suit them, used unless overridden
+    let database = match load_database(&options) {
+        Ok(database) => database,
+        Err(msg) => {
+            show_error(&canvas, &msg);
+            return;
+        }
+    };
+    let info = database.as_ref().and_then(|database| database.lookup(&Rom::new(&data)));
+    let load_address = options.load_address
+        .or(info.and_then(|info| info.load_address))
+        .unwrap_or(START_ADDRESS);
+    let rom = Rom::with_load_address(&data, load_address);
+
+    // Keys, buttons and colours can be changed in the config file, for every rom or just this one
+    let config = Config::load(&options.config).and_then(|config| {
+        let palette = match options.palette {
+            Some(palette) => palette,
+            None => config.palette(&rom, info.and_then(|info| info.palette))?,
+        };
+        Ok((config.keymap(&rom)?, config.controller_profile(&rom, info.map_or(&[], |info| &info.keys))?, palette))
+    });
+    let (keymap, profile, palette) = match config {
+        Ok(settings) => settings,
+        Err(msg) => {
+            show_error(&canvas, &msg);
+            return;
+        }
+    };
+    let palette = palette.colors.map(|c| Color::RGB(c.r, c.g, c.b));
+    if let Some(info) = info {
+        print_rom_info(info, &keymap);
+        let _ = canvas.window_mut().set_title(&format!("Chip-8 Emulator - {}", info.title));
+    }
+
+    // A movie being played back decides how the emulator is set up
+    let mut tape = match &options.play {
+        Some(path) => match Tape::play(path, rom.data()) {
+            Ok(tape) => Some(tape),
+            Err(msg) => {
+                show_error(&canvas, &msg);
+                return;
+            }
+        },
+        None => None,
+    };
+    let (mut chip8, ips, rom) = match &tape {
+        Some(Tape::Playing { movie, .. }) => (movie.cpu(), movie.ips, movie.rom(rom.data())),
+        _ => {
+            let platform = options.platform
+                .or(info.and_then(|info| info.platform))
+                .unwrap_or_else(|| rom.guess_platform());
+            // The database's quirks are for its platform, not one picked on the command line
+            let recommended = info
+                .filter(|info| info.platform == Some(platform))
+                .and_then(|info| info.quirks);
+            let quirks = options.quirks
+                .or(recommended)
+                .unwrap_or_else(|| platform.default_quirks());
+            let mut chip8 = CPU::new(platform, quirks, options.seed);
+            chip8.set_timing(options.timing);
+            let ips = options.ips
+                .or(info.and_then(|info| info.ips))
+                .unwrap_or(DEFAULT_IPS);
+            (chip8, ips, rom)
+        }
+    };
+
+    if let Err(e) = chip8.load_rom(&rom) {
+        show_error(&canvas, &e);
+        return;
+    }
+    if let Some(path) = &options.record {
+        tape = Some(Tape::record(path, &rom, &chip8, ips));
+    }
+
+    // The debugger runs every frame, with no breakpoints it behaves like tick
+    let mut debugger = Debugger::new(chip8);
+    let symbols = match options.symbols.as_deref().map(load_symbols) {
+        Some(Ok(symbols)) => symbols,
+        Some(Err(msg)) => {
+            show_error(&canvas, &msg);
+            return;
+        }
+        None => Symbols::default(),
+    };
+    let mut console = if options.debug {
+        Some(Console::spawn(symbols))
+    } else {
+        None
+    };
+    let mut gdb = match options.gdb.map(GdbServer::listen) {
+        Some(Ok(server)) => Some(server),
+        Some(Err(msg)) => {
+            show_error(&canvas, &msg);
+            return;
+        }
+        None => None,
+    };
+
+    // Instructions run are logged as they go, or on an error in ring mode
+    let mut tracer = match &options.trace {
+        Some(path) => match File::create(path) {
+            Ok(file) => {
+                let mut tracer = Tracer::new(BufWriter::new(file), options.trace_format);
+                tracer.set_filter(options.trace_filter);
+                if let Some(capacity) = options.trace_ring {
+                    tracer.set_ring(capacity);
+                }
+                debugger.cpu_mut().set_tracing(true);
+                Some(tracer)
+            }
+            Err(e) => {
+                show_error(&canvas, &format!("Unable to write {}: {}", path, e));
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // Quick save slot used by F5/F9, changed with F6/F7
+    let mut slot = 0;
 
-    let mut rom = File::open(&args[1]).expect("Unable to open file");
-    let mut buffer = Vec::new();
-    rom.read_to_end(&mut buffer).unwrap();
-    chip8.load_rom(&buffer);
+    // Holding backspace steps back through recent snapshots
+    let mut rewinder = Rewinder::new(REWIND_INTERVAL, REWIND_CAPACITY);
+    let mut rewinding = false;
+
+    // Gameplay being captured with F11
+    let mut recorder: Option<Recorder> = None;
+
+    // Runs instructions and timers at their own rates whatever the refresh rate.
+    // Movies need every frame to run the same instructions each time.
+    let mut scheduler = Scheduler::new(ips);
+    scheduler.set_lockstep(tape.is_some());
+    let mut last_frame = Instant::now();
 
     'gameloop: loop {
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit{..} => break 'gameloop,
 
+                Event::KeyDown{keycode: Some(Keycode::Backspace), ..} if tape.is_some() => {
+                    println!("Rewinding is disabled while a movie is recording or playing");
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::Backspace), ..} => rewinding = true,
+
+                Event::KeyUp{keycode: Some(Keycode::Backspace), ..} => rewinding = false,
+
+                Event::KeyDown{keycode: Some(Keycode::F5), ..} => {
+                    quick_save(debugger.cpu(), &options.rom_path, slot);
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::F9), ..} if tape.is_some() => {
+                    println!("Loading states is disabled while a movie is recording or playing");
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::F9), ..} => {
+                    quick_load(debugger.cpu_mut(), &options.rom_path, slot);
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::F6), ..} => {
+                    slot = (slot + NUM_STATE_SLOTS - 1) % NUM_STATE_SLOTS;
+                    println!("Save slot {}", slot);
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::F7), ..} => {
+                    slot = (slot + 1) % NUM_STATE_SLOTS;
+                    println!("Save slot {}", slot);
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::F11), ..} => {
+                    toggle_recording(&mut recorder, &options.rom_path, options.video, &palette);
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::F12), ..} => {
+                    take_screenshot(debugger.cpu(), &options.rom_path, &palette);
+                }
+
+                Event::KeyDown{keycode: Some(Keycode::M), ..} => {
+                    if let Some(device) = &mut speaker {
+                        let mut speaker = device.lock();
+                        speaker.synth.muted = !speaker.synth.muted;
+                    }
+                }
+
+                Event::ControllerDeviceAdded{which, ..} => {
+                    if let Some(controllers) = &mut controllers {
+                        controllers.add(which);
+                    }
+                }
+
+                // Keys held by the controller would otherwise stay down
+                Event::ControllerDeviceRemoved{which, ..} => {
+                    if let Some(controllers) = &mut controllers {
+                        controllers.remove(which);
+                    }
+                    if !tape.as_ref().is_some_and(Tape::is_playing) {
+                        debugger.cpu_mut().set_keys(0);
+                    }
+                }
+
+                // Movies being played back hold the keys themselves
+                Event::KeyDown{..} | Event::KeyUp{..}
+                | Event::ControllerButtonDown{..} | Event::ControllerButtonUp{..}
+                    if tape.as_ref().is_some_and(Tape::is_playing) => (),
+
+                Event::ControllerButtonDown{button, ..} => {
+                    controller::press(debugger.cpu_mut(), &profile, button, true);
+                }
+
+                Event::ControllerButtonUp{button, ..} => {
+                    controller::press(debugger.cpu_mut(), &profile, button, false);
+                }
+
                 Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some(k) = convert_keycode(key) {
-                        chip8.keypress(k, true);
+                    if let Some(k) = keymap.key(&key.name()) {
+                        debugger.cpu_mut().keypress(k, true);
                     }
                 }
 
                 Event::KeyUp{keycode: Some(key), ..} => {
-                    if let Some(k) = convert_keycode(key) {
-                        chip8.keypress(k, false);
+                    if let Some(k) = keymap.key(&key.name()) {
+                        debugger.cpu_mut().keypress(k, false);
                     }
                 }
                 _ => ()
             }
         }
 
-        for _ in 0..TICKS_PER_FRAME{
-            chip8.tick();
+        // Loading a state can change the timing
+        scheduler.set_timing(debugger.cpu().timing());
+        let now = Instant::now();
+        let slices = scheduler.advance(now - last_frame);
+        last_frame = now;
+
+        if let Some(console) = &mut console {
+            if !console.poll(&mut debugger) {
+                break 'gameloop;
+            }
+            if console.paused() {
+                silence(&mut speaker);
+                draw_screen(debugger.cpu(), &mut canvas, &palette);
+                continue;
+            }
+        }
+        if let Some(server) = &mut gdb {
+            if !server.poll(&mut debugger) {
+                break 'gameloop;
+            }
+            if server.paused() {
+                silence(&mut speaker);
+                draw_screen(debugger.cpu(), &mut canvas, &palette);
+                continue;
+            }
+        }
+
+        // Steps back at the same rate snapshots were recorded
+        if rewinding {
+            for _ in slices.iter().filter(|slice| slice.tick_timers) {
+                rewinder.rewind_frame(debugger.cpu_mut());
+            }
+            silence(&mut speaker);
+            draw_screen(debugger.cpu(), &mut canvas, &palette);
+            continue;
+        }
+
+        for slice in slices {
+            let keys = tape.as_mut().map(|tape| tape.start_frame(debugger.cpu_mut()));
+            let stop = debugger.run(slice.instructions);
+            if let Some(log) = &mut tracer {
+                let crashed = matches!(stop, Some(StopReason::Error { .. }));
+                if let Err(e) = write_trace(log, &mut debugger, crashed) {
+                    eprintln!("Unable to write trace: {}", e);
+                    debugger.cpu_mut().set_tracing(false);
+                    tracer = None;
+                }
+            }
+            match (stop, &mut console, &mut gdb) {
+                (None, _, _) => (),
+                (Some(reason), Some(console), _) => {
+                    console.stopped(&debugger, reason);
+                    break;
+                }
+                (Some(reason), _, Some(server)) if server.connected() => {
+                    server.stopped(reason);
+                    break;
+                }
+                (Some(StopReason::Error { error, .. }), _, _) => {
+                    show_error(&canvas, &error);
+                    break 'gameloop;
+                }
+                (Some(_), _, _) => break 'gameloop,
+            }
+            if slice.tick_timers {
+                debugger.cpu_mut().tick_timers();
+                rewinder.record(debugger.cpu());
+                if let (Some(tape), Some(keys)) = (&mut tape, keys) {
+                    tape.end_frame(keys, debugger.cpu());
+                }
+                if let Some(video) = &mut recorder {
+                    if let Err(e) = video.add_frame(&debugger.cpu().get_display()) {
+                        eprintln!("Unable to record video: {}", e);
+                        recorder = None;
+                    }
+                }
+            }
+        }
+        if let Some(device) = &mut speaker {
+            device.lock().synth.update(debugger.cpu());
+        }
+        draw_screen(debugger.cpu(), &mut canvas, &palette)
+    }
+
+    if let Some(tape) = tape {
+        tape.finish();
+    }
+    if let Some(log) = &mut tracer {
+        if let Err(e) = write_trace(log, &mut debugger, false).and_then(|_| log.flush()) {
+            eprintln!("Unable to write trace: {}", e);
         }
-        chip8.tick_timers();
-        draw_screen(&chip8, &mut canvas)
+    }
+    if recorder.is_some() {
+        toggle_recording(&mut recorder, &options.rom_path, options.video, &palette);
     }
 }
 
-fn draw_screen(cpu: &CPU, canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGB(0,0,0,));
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut load_address = None;
+    let mut seed = None;
+    let mut ips = None;
+    let mut timing = Timing::default();
+    let mut frequency = DEFAULT_FREQUENCY;
+    let mut volume = DEFAULT_VOLUME;
+    let mut muted = false;
+    let mut debug = false;
+    let mut symbols = None;
+    let mut gdb = None;
+    let mut record = None;
+    let mut play = None;
+    let mut config = config::DEFAULT_PATH.to_string();
+    let mut video = VideoFormat::Gif;
+    let mut palette = None;
+    let mut database = None;
+    let mut no_database = false;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::default();
+    let mut trace_filter = TraceFilter::default();
+    let mut trace_ring = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().ok_or("--platform needs a platform name")?;
+                platform = Some(Platform::from_name(name)
+                    .ok_or(format!("Unknown platform: {}", name))?);
+            }
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a profile name")?;
+                quirks = Some(Quirks::from_name(name)
+                    .ok_or(format!("Unknown quirks profile: {}", name))?);
+            }
+            "--load-address" => {
+                let value = args.next().ok_or("--load-address needs an address")?;
+                load_address = Some(parse_address(value)
+                    .ok_or(format!("Invalid load address: {}", value))?);
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a number")?;
+                seed = Some(value.parse()
+                    .map_err(|_| format!("Invalid seed: {}", value))?);
+            }
+            "--ips" => {
+                let value = args.next().ok_or("--ips needs a number")?;
+                ips = Some(value.parse()
+                    .ok()
+                    .filter(|ips| *ips > 0)
+                    .ok_or(format!("Invalid instructions per second: {}", value))?);
+            }
+            "--timing" => {
+                let name = args.next().ok_or("--timing needs a timing model")?;
+                timing = Timing::from_name(name)
+                    .ok_or(format!("Unknown timing model: {}", name))?;
+            }
+            "--frequency" => {
+                let value = args.next().ok_or("--frequency needs a number")?;
+                frequency = value.parse()
+                    .ok()
+                    .filter(|hz: &f32| *hz > 0.)
+                    .ok_or(format!("Invalid frequency: {}", value))?;
+            }
+            "--volume" => {
+                let value = args.next().ok_or("--volume needs a number")?;
+                let percent: f32 = value.parse()
+                    .ok()
+                    .filter(|percent| (0. ..=100.).contains(percent))
+                    .ok_or(format!("Invalid volume: {}", value))?;
+                volume = percent / 100.;
+            }
+            "--mute" => muted = true,
+            "--debug" => debug = true,
+            "--symbols" => {
+                symbols = Some(args.next().ok_or("--symbols needs a file name")?.clone());
+            }
+            "--gdb" => {
+                let value = args.next().ok_or("--gdb needs a port")?;
+                gdb = Some(value.parse()
+                    .map_err(|_| format!("Invalid port: {}", value))?);
+            }
+            "--record" => {
+                record = Some(args.next().ok_or("--record needs a file name")?.clone());
+            }
+            "--play" => {
+                play = Some(args.next().ok_or("--play needs a file name")?.clone());
+            }
+            "--config" => {
+                config = args.next().ok_or("--config needs a file name")?.clone();
+            }
+            "--video" => {
+                let name = args.next().ok_or("--video needs a format")?;
+                video = VideoFormat::from_name(name)
+                    .ok_or(format!("Unknown video format: {}", name))?;
+            }
+            "--palette" => {
+                let spec = args.next().ok_or("--palette needs a palette name or colours")?;
+                palette = Some(Palette::parse(spec)
+                    .ok_or(format!("Invalid palette: {}", spec))?);
+            }
+            "--database" => {
+                database = Some(args.next().ok_or("--database needs a directory")?.clone());
+            }
+            "--no-database" => no_database = true,
+            "--trace" => {
+                trace = Some(args.next().ok_or("--trace needs a file name")?.clone());
+            }
+            "--trace-format" => {
+                let name = args.next().ok_or("--trace-format needs a format")?;
+                trace_format = TraceFormat::from_name(name)
+                    .ok_or(format!("Unknown trace format: {}", name))?;
+            }
+            "--trace-pc" => {
+                let range = args.next().ok_or("--trace-pc needs an address range")?;
+                trace_filter.pcs = Some(TraceFilter::parse_pcs(range)
+                    .ok_or(format!("Invalid address range: {}", range))?);
+            }
+            "--trace-ops" => {
+                let list = args.next().ok_or("--trace-ops needs opcode classes")?;
+                trace_filter.classes = TraceFilter::parse_classes(list)
+                    .ok_or(format!("Invalid opcode classes: {}", list))?;
+            }
+            "--trace-ring" => {
+                let value = args.next().ok_or("--trace-ring needs a number")?;
+                trace_ring = Some(value.parse()
+                    .map_err(|_| format!("Invalid trace length: {}", value))?);
+            }
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or("Run with path to rom as argument")?;
+    if record.is_some() && play.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+    if debug && gdb.is_some() {
+        return Err("--debug and --gdb can't be used together".to_string());
+    }
+    if symbols.is_some() && !debug {
+        return Err("--symbols needs --debug".to_string());
+    }
+    if trace.is_none() && (trace_ring.is_some() || trace_filter != TraceFilter::default()) {
+        return Err("--trace-pc, --trace-ops and --trace-ring need --trace".to_string());
+    }
+    if database.is_some() && no_database {
+        return Err("--database and --no-database can't be used together".to_string());
+    }
+    Ok(Options {
+        rom_path, platform, quirks, load_address, seed, ips, timing, frequency, volume, muted, debug, symbols, gdb, record, play, config, video, palette, database, no_database, trace, trace_format, trace_filter, trace_ring
+    })
+}
+
+// Reads a symbol map written by chip8-asm --symbols
+fn load_symbols(path: &str) -> Result<Symbols, String> {
+    let map = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    Symbols::parse(&map).map_err(|e| format!("Invalid symbol map {}: {}", path, e))
+}
+
+// Addresses are hex with a 0x prefix, like 0x600, or decimal
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// The built in rom database, or programs.json and sha1-hashes.json from
+// a chip-8-database checkout
+fn load_database(options: &Options) -> Result<Option<RomDatabase>, String> {
+    if options.no_database {
+        return Ok(None);
+    }
+    let Some(dir) = &options.database else {
+        return Ok(Some(RomDatabase::builtin()));
+    };
+    let read = |name: &str| {
+        let path = Path::new(dir).join(name);
+        fs::read_to_string(&path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))
+    };
+    RomDatabase::from_json(&read("programs.json")?, &read("sha1-hashes.json")?)
+        .map(Some)
+        .map_err(|e| format!("Invalid rom database {}: {}", dir, e))
+}
+
+// What the database says about a rom and the keys it uses, by the host keys they're on
+fn print_rom_info(info: &RomInfo, keymap: &Keymap) {
+    match (&info.release, info.authors.is_empty()) {
+        (Some(release), false) => println!("{} by {} ({})", info.title, info.authors.join(", "), release),
+        (None, false) => println!("{} by {}", info.title, info.authors.join(", ")),
+        (Some(release), true) => println!("{} ({})", info.title, release),
+        (None, true) => println!("{}", info.title),
+    }
+    for (action, key) in &info.keys {
+        let hosts: Vec<_> = keymap.hosts(*key).collect();
+        println!("  {}: {:X} ({})", action, key, hosts.join(", "));
+    }
+}
+
+// Logs what ran since the last call, and what a ring holds if the program crashed
+fn write_trace(tracer: &mut Tracer<BufWriter<File>>, debugger: &mut Debugger, crashed: bool) -> std::io::Result<()> {
+    tracer.record(&debugger.cpu_mut().take_trace())?;
+    if crashed {
+        tracer.dump()
+    } else {
+        Ok(())
+    }
+}
+
+// Save states are kept next to the rom, one file per slot
+fn state_path(rom_path: &str, slot: u32) -> String {
+    format!("{}.state{}", rom_path, slot)
+}
+
+fn quick_save(cpu: &CPU, rom_path: &str, slot: u32) {
+    let path = state_path(rom_path, slot);
+    match fs::write(&path, cpu.save_state()) {
+        Ok(()) => println!("Saved state to slot {}", slot),
+        Err(e) => eprintln!("Unable to write {}: {}", path, e),
+    }
+}
+
+fn quick_load(cpu: &mut CPU, rom_path: &str, slot: u32) {
+    let path = state_path(rom_path, slot);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path, e);
+            return;
+        }
+    };
+    match cpu.load_state(&data) {
+        Ok(()) => println!("Loaded state from slot {}", slot),
+        Err(e) => eprintln!("Unable to load {}: {}", path, e),
+    }
+}
+
+// Screenshots and videos are kept next to the rom like save states, at the
+// window's size
+fn take_screenshot(cpu: &CPU, rom_path: &str, palette: &[Color; 4]) {
+    let path = capture::next_path(rom_path, "png");
+    match capture::screenshot(&path, &cpu.get_display(), WINDOW_WIDTH, WINDOW_HEIGHT, palette) {
+        Ok(()) => println!("Saved screenshot to {}", path),
+        Err(msg) => eprintln!("{}", msg),
+    }
+}
+
+fn toggle_recording(recorder: &mut Option<Recorder>, rom_path: &str, format: VideoFormat, palette: &[Color; 4]) {
+    match recorder.take() {
+        Some(video) => {
+            let frames = video.frames();
+            match video.finish() {
+                Ok(()) => println!("Recorded {} frames of video", frames),
+                Err(e) => eprintln!("Unable to record video: {}", e),
+            }
+        }
+        None => {
+            let path = capture::next_path(rom_path, format.extension());
+            match Recorder::create(&path, format, WINDOW_WIDTH, WINDOW_HEIGHT, palette) {
+                Ok(video) => {
+                    println!("Recording video to {}", path);
+                    *recorder = Some(video);
+                }
+                Err(e) => eprintln!("Unable to write {}: {}", path, e),
+            }
+        }
+    }
+}
+
+fn draw_screen(cpu: &CPU, canvas: &mut Canvas<Window>, palette: &[Color; 4]) {
+    canvas.set_draw_color(palette[0]);
     canvas.clear();
 
-    let screen_buf = cpu.get_display();
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    let frame = cpu.get_display();
 
-    for (i, pixel) in screen_buf.iter().enumerate() {
-        if *pixel {
-            
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
+    // Pixel edges are spread evenly over the window so both resolutions fill it
+    let width = frame.width as u32;
+    let height = frame.height as u32;
+    for (i, pixel) in frame.pixels.iter().enumerate() {
+        if *pixel != 0 {
+            let x = (i % frame.width) as u32;
+            let y = (i / frame.width) as u32;
 
-            let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
+            let left = x * WINDOW_WIDTH / width;
+            let top = y * WINDOW_HEIGHT / height;
+            let right = (x + 1) * WINDOW_WIDTH / width;
+            let bottom = (y + 1) * WINDOW_HEIGHT / height;
+
+            let rect = Rect::new(left as i32, top as i32, right - left, bottom - top);
+            canvas.set_draw_color(palette[*pixel as usize & 3]);
             canvas.fill_rect(rect).unwrap();
         }
     }
     canvas.present();
 }
 
-fn convert_keycode(key: Keycode) -> Option<usize>{
-    match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q    => Some(0x4),
-        Keycode::W    => Some(0x5),
-        Keycode::E    => Some(0x6),
-        Keycode::R    => Some(0xD),
-        Keycode::A    => Some(0x7),
-        Keycode::S    => Some(0x8),
-        Keycode::D    => Some(0x9),
-        Keycode::F    => Some(0xE),
-        Keycode::Z    => Some(0xA),
-        Keycode::X    => Some(0x0),
-        Keycode::C    => Some(0xB),
-        Keycode::V    => Some(0xF),
-        _             => None,
-    }
-}
\ No newline at end of file
+fn silence(speaker: &mut Option<AudioDevice<audio::Speaker>>) {
+    if let Some(device) = speaker {
+        device.lock().synth.stop();
+    }
+}
+
+fn show_error(canvas: &Canvas<Window>, err: &dyn Display) {
+    eprintln!("Error: {}", err);
+    show_simple_message_box(
+        MessageBoxFlag::ERROR,
+        "Chip-8 Emulator",
+        &err.to_string(),
+        canvas.window()
+    ).ok();
+}
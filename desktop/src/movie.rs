@@ -0,0 +1,77 @@
+use chip8_core::{Movie, Rom, CPU};
+use std::fs;
+
+// An input movie being recorded or played back alongside the emulator
+pub enum Tape {
+    Recording { movie: Movie, path: String },
+    Playing { movie: Movie, frame: usize, desynced: bool },
+}
+
+impl Tape {
+    // Starts recording from a CPU that has just loaded rom
+    pub fn record(path: &str, rom: &Rom, cpu: &CPU, ips: u32) -> Tape {
+        Tape::Recording {
+            movie: Movie::new(rom, cpu, ips),
+            path: path.to_string(),
+        }
+    }
+
+    // Reads a movie, which has to have been recorded with rom
+    pub fn play(path: &str, rom: &[u8]) -> Result<Tape, String> {
+        let data = fs::read(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let movie = Movie::from_bytes(&data)
+            .map_err(|e| format!("Unable to play {}: {}", path, e))?;
+        if !movie.matches_rom(rom) {
+            return Err(format!("{} was recorded with a different rom", path));
+        }
+        Ok(Tape::Playing { movie, frame: 0, desynced: false })
+    }
+
+    // The movie controls the keypad until it runs out
+    pub fn is_playing(&self) -> bool {
+        match self {
+            Tape::Recording { .. } => false,
+            Tape::Playing { movie, frame, .. } => *frame < movie.len(),
+        }
+    }
+
+    // Call before a frame runs, returns the keys held during it
+    pub fn start_frame(&mut self, cpu: &mut CPU) -> u16 {
+        if let Tape::Playing { movie, frame, .. } = self {
+            if let Some(keys) = movie.keys(*frame) {
+                cpu.set_keys(keys);
+            }
+        }
+        cpu.keys()
+    }
+
+    // Call once a frame has run and the timers have ticked
+    pub fn end_frame(&mut self, keys: u16, cpu: &CPU) {
+        match self {
+            Tape::Recording { movie, .. } => movie.record(keys, cpu),
+            Tape::Playing { movie, frame, desynced } => {
+                if *frame >= movie.len() {
+                    return;
+                }
+                if !*desynced && !movie.verify(*frame, cpu) {
+                    eprintln!("Movie desynced at frame {}", frame);
+                    *desynced = true;
+                }
+                *frame += 1;
+                if *frame == movie.len() {
+                    println!("Movie finished after {} frames", frame);
+                }
+            }
+        }
+    }
+
+    // Writes out a recording, playback has nothing to save
+    pub fn finish(self) {
+        if let Tape::Recording { movie, path } = self {
+            match fs::write(&path, movie.to_bytes()) {
+                Ok(()) => println!("Recorded {} frames to {}", movie.len(), path),
+                Err(e) => eprintln!("Unable to write {}: {}", path, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use chip8_core::*;
+use std::{env, fs, process};
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    if args.len() != 1 {
+        println!("Usage: chip8-disasm <rom>");
+        process::exit(2);
+    }
+
+    let rom = match fs::read(&args[0]) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", args[0], e);
+            process::exit(1);
+        }
+    };
+    print!("{}", Disassembly::new(&rom, START_ADDRESS));
+}
@@ -0,0 +1,284 @@
+use chip8_core::Frame;
+use std::{fs, io::BufWriter, path::Path};
+
+// Grey levels for each combination of the two XO-CHIP bitplanes
+const LEVELS: [u8; 4] = [0, 255, 170, 85];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Pbm, // Plain text bitmap, any lit pixel is black
+    Png, // 8 bit greyscale
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Result<Format, String> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("pbm") => Ok(Format::Pbm),
+            Some("png") => Ok(Format::Png),
+            _ => Err(format!(
+                "Unknown image format for {}, use .pbm or .png",
+                path
+            )),
+        }
+    }
+}
+
+// One byte per pixel, holding what the format stores so images compare
+// exactly after a round trip through a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_frame(frame: &Frame, format: Format) -> Image {
+        let pixels = frame
+            .pixels
+            .iter()
+            .map(|pixel| match format {
+                Format::Pbm => (*pixel != 0) as u8,
+                Format::Png => LEVELS[*pixel as usize & 3],
+            })
+            .collect();
+        Image {
+            width: frame.width,
+            height: frame.height,
+            pixels,
+        }
+    }
+
+    // Number of pixels that differ, or None if the sizes don't match
+    pub fn diff(&self, other: &Image) -> Option<usize> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        let count = self
+            .pixels
+            .iter()
+            .zip(&other.pixels)
+            .filter(|(a, b)| a != b)
+            .count();
+        Some(count)
+    }
+}
+
+pub fn write(path: &str, image: &Image, format: Format) -> Result<(), String> {
+    let result = match format {
+        Format::Pbm => fs::write(path, encode_pbm(image)).map_err(|e| e.to_string()),
+        Format::Png => write_png(path, image),
+    };
+    result.map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+pub fn read(path: &str, format: Format) -> Result<Image, String> {
+    let result = match format {
+        Format::Pbm => fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| decode_pbm(&data)),
+        Format::Png => read_png(path),
+    };
+    result.map_err(|e| format!("Unable to read {}: {}", path, e))
+}
+
+fn encode_pbm(image: &Image) -> String {
+    let mut out = format!("P1\n{} {}\n", image.width, image.height);
+    for row in image.pixels.chunks(image.width) {
+        let bits: Vec<&str> = row
+            .iter()
+            .map(|p| if *p != 0 { "1" } else { "0" })
+            .collect();
+        out.push_str(&bits.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+// Reads plain (P1) or raw (P4) bitmaps
+fn decode_pbm(data: &[u8]) -> Result<Image, String> {
+    let text = String::from_utf8_lossy(data);
+    let mut header = Vec::new();
+    let mut offset = 0;
+
+    // Magic, width and height, skipping comments
+    for line in text.split_inclusive('\n') {
+        if header.len() == 3 {
+            break;
+        }
+        offset += line.len();
+        let line = line.split('#').next().unwrap_or("");
+        header.extend(line.split_whitespace().map(str::to_string));
+    }
+    let [magic, width, height] = &header[..] else {
+        return Err("bad PBM header".to_string());
+    };
+    let width: usize = width.parse().map_err(|_| "bad PBM width")?;
+    let height: usize = height.parse().map_err(|_| "bad PBM height")?;
+    if width == 0 || height == 0 {
+        return Err("PBM has no pixels".to_string());
+    }
+    let size = width.checked_mul(height).ok_or("PBM is too large")?;
+
+    let pixels: Vec<u8> = match magic.as_str() {
+        "P1" => text[offset..]
+            .chars()
+            .filter_map(|c| match c {
+                '0' => Some(0),
+                '1' => Some(1),
+                _ => None,
+            })
+            .collect(),
+        "P4" => {
+            // Rows are padded to whole bytes
+            let stride = width.div_ceil(8);
+            let raster = &data[offset..];
+            if raster.len() / stride < height {
+                return Err("PBM is truncated".to_string());
+            }
+            raster
+                .chunks(stride)
+                .take(height)
+                .flat_map(|row| (0..width).map(move |x| (row[x / 8] >> (7 - x % 8)) & 1))
+                .collect()
+        }
+        _ => return Err(format!("unsupported PBM type {}", magic)),
+    };
+    if pixels.len() < size {
+        return Err("PBM is truncated".to_string());
+    }
+    Ok(Image {
+        width,
+        height,
+        pixels: pixels[..size].to_vec(),
+    })
+}
+
+fn write_png(path: &str, image: &Image) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        image.width as u32,
+        image.height as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&image.pixels)
+        .map_err(|e| e.to_string())
+}
+
+fn read_png(path: &str) -> Result<Image, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = png::Decoder::new(file)
+        .read_info()
+        .map_err(|e| e.to_string())?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(|e| e.to_string())?;
+    if info.color_type != png::ColorType::Grayscale || info.bit_depth != png::BitDepth::Eight {
+        return Err("expected an 8 bit greyscale PNG".to_string());
+    }
+    pixels.truncate(info.buffer_size());
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize, pixels: &[u8]) -> Image {
+        Image {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        }
+    }
+
+    #[test]
+    fn decodes_plain_pbm() {
+        let data = b"P1\n# A comment\n3 2\n1 0 1\n0 1 0\n";
+        assert_eq!(decode_pbm(data), Ok(image(3, 2, &[1, 0, 1, 0, 1, 0])));
+        // Pixels don't need spaces between them
+        assert_eq!(
+            decode_pbm(b"P1\n3 2\n101\n010"),
+            Ok(image(3, 2, &[1, 0, 1, 0, 1, 0]))
+        );
+    }
+
+    #[test]
+    fn decodes_raw_pbm() {
+        // Rows of 10 pixels take two bytes each, the rest is padding
+        let mut data = b"P4\n10 2\n".to_vec();
+        data.extend([0b1000_0001, 0b0100_0000, 0b0111_1110, 0b1011_1111]);
+        let pixels = [1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 1, 1, 1, 1, 1, 1, 0, 1, 0];
+        assert_eq!(decode_pbm(&data), Ok(image(10, 2, &pixels)));
+    }
+
+    #[test]
+    fn encoded_pbm_decodes_to_the_same_image() {
+        let original = image(4, 2, &[1, 1, 0, 0, 0, 1, 0, 1]);
+        assert_eq!(decode_pbm(encode_pbm(&original).as_bytes()), Ok(original));
+    }
+
+    #[test]
+    fn rejects_truncated_pbm() {
+        assert!(decode_pbm(b"P1\n3 2\n1 0 1\n0 1\n").is_err());
+        let mut data = b"P4\n10 2\n".to_vec();
+        data.extend([0xFF, 0xC0, 0xFF]);
+        assert!(decode_pbm(&data).is_err());
+        assert!(decode_pbm(b"P4\n8 1\n").is_err());
+        assert!(decode_pbm(b"P1\n3").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        for data in [
+            &b"P4\n0 2\n"[..],
+            b"P1\n3 0\n",
+            b"P4\n18446744073709551615 2\n",
+            b"P2\n1 1\n0",
+            b"P1\nx 1\n0",
+            b"",
+        ] {
+            assert!(decode_pbm(data).is_err(), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn diff_counts_changed_pixels() {
+        let golden = image(3, 2, &[1, 0, 1, 0, 1, 0]);
+        assert_eq!(golden.diff(&golden.clone()), Some(0));
+        assert_eq!(golden.diff(&image(3, 2, &[0, 0, 1, 0, 1, 1])), Some(2));
+        assert_eq!(golden.diff(&image(2, 3, &[1, 0, 1, 0, 1, 0])), None);
+    }
+
+    #[test]
+    fn frames_compare_against_golden_images() {
+        let frame = Frame {
+            width: 2,
+            height: 2,
+            pixels: &[0, 1, 2, 3],
+        };
+        let pbm = Image::from_frame(&frame, Format::Pbm);
+        assert_eq!(pbm, image(2, 2, &[0, 1, 1, 1]));
+        let png = Image::from_frame(&frame, Format::Png);
+        assert_eq!(png, image(2, 2, &[0, 255, 170, 85]));
+
+        // Through a file and back, like --screenshot then --expect
+        for (image, format, ext) in [(pbm, Format::Pbm, "pbm"), (png, Format::Png, "png")] {
+            let name = format!("chip8-headless-{}.{}", std::process::id(), ext);
+            let path = std::env::temp_dir().join(name);
+            let path = path.to_str().unwrap();
+            assert_eq!(Format::from_path(path), Ok(format));
+            write(path, &image, format).unwrap();
+            let golden = read(path, format);
+            fs::remove_file(path).unwrap();
+            assert_eq!(golden.unwrap().diff(&image), Some(0));
+        }
+    }
+}
@@ -0,0 +1,324 @@
+mod image;
+
+use chip8_core::*;
+use image::{Format, Image};
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    process,
+};
+
+const DEFAULT_FRAMES: u32 = 600;
+// The same speed desktop and tui run at
+const TICKS_PER_FRAME: usize = (DEFAULT_IPS / TIMER_HZ) as usize;
+
+struct Options {
+    rom_path: String,
+    platform: Option<Platform>, // Guessed from the rom when not given
+    quirks: Option<Quirks>,     // The platform's when not given
+    load_address: u16,
+    seed: u64,
+    frames: u32,
+    // Ignored with VIP timing, which runs until the frame's cycles are used up
+    ticks_per_frame: usize,
+    timing: Timing,
+    input: InputScript,
+    screenshot: Option<String>, // Where to dump the last frame
+    registers: Option<String>,  // Where to dump registers as JSON
+    expect: Option<String>,     // Golden image the last frame must match
+    trace: Option<String>,      // Where to log executed instructions
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+    trace_ring: Option<usize>, // Only log this many instructions before an error
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            println!(
+                "Usage: chip8-headless [--platform {}] [--quirks {}] [--seed N] [--frames N] [--ticks N]\n\
+                 \x20                     [--load-address ADDR] [--timing {}] [--input SCRIPT] [--input-file FILE]\n\
+                 \x20                     [--screenshot FILE] [--registers FILE] [--expect FILE]\n\
+                 \x20                     [--trace FILE] [--trace-format {}] [--trace-pc START-END]\n\
+                 \x20                     [--trace-ops CLASSES] [--trace-ring N] <rom>",
+                Platform::NAMES.join("|"),
+                Quirks::NAMES.join("|"),
+                Timing::NAMES.join("|"),
+                TraceFormat::NAMES.join("|")
+            );
+            process::exit(2);
+        }
+    };
+
+    if let Err(msg) = run(&options) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let data = fs::read(&options.rom_path)
+        .map_err(|e| format!("Unable to read {}: {}", options.rom_path, e))?;
+    let rom = Rom::with_load_address(&data, options.load_address);
+    let platform = options.platform.unwrap_or_else(|| rom.guess_platform());
+    let quirks = options.quirks.unwrap_or_else(|| platform.default_quirks());
+    let mut chip8 = CPU::new(platform, quirks, Some(options.seed));
+    chip8.set_timing(options.timing);
+    chip8.load_rom(&rom).map_err(|e| format!("Error: {}", e))?;
+
+    let mut tracer = match &options.trace {
+        Some(path) => {
+            let file =
+                File::create(path).map_err(|e| format!("Unable to write {}: {}", path, e))?;
+            let mut tracer = Tracer::new(BufWriter::new(file), options.trace_format);
+            tracer.set_filter(options.trace_filter);
+            if let Some(capacity) = options.trace_ring {
+                tracer.set_ring(capacity);
+            }
+            chip8.set_tracing(true);
+            Some(tracer)
+        }
+        None => None,
+    };
+
+    let frames = run_frames(&mut chip8, options, tracer.as_mut())?;
+    if let Some(tracer) = &mut tracer {
+        tracer.flush().map_err(|e| trace_error(options, e))?;
+    }
+
+    if let Some(path) = &options.screenshot {
+        let format = Format::from_path(path)?;
+        image::write(
+            path,
+            &Image::from_frame(&chip8.get_display(), format),
+            format,
+        )?;
+    }
+
+    if let Some(path) = &options.registers {
+        fs::write(path, registers_json(&chip8, frames))
+            .map_err(|e| format!("Unable to write {}: {}", path, e))?;
+    }
+
+    if let Some(path) = &options.expect {
+        let format = Format::from_path(path)?;
+        let expected = image::read(path, format)?;
+        let actual = Image::from_frame(&chip8.get_display(), format);
+        match actual.diff(&expected) {
+            Some(0) => println!("Frame {} matches {}", frames, path),
+            Some(count) => {
+                return Err(format!(
+                    "Frame {} differs from {} in {} pixels",
+                    frames, path, count
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "Frame {} is {}x{} but {} is {}x{}",
+                    frames, actual.width, actual.height, path, expected.width, expected.height
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs until the frame count is reached or the program exits, returning
+// the number of frames run. A trace in ring mode is written out if the
+// program hits an error.
+fn run_frames(
+    chip8: &mut CPU,
+    options: &Options,
+    mut tracer: Option<&mut Tracer<BufWriter<File>>>,
+) -> Result<u32, String> {
+    let mut input = options.input.clone();
+    for frame in 0..options.frames {
+        input.apply(chip8, frame);
+
+        let ticks = match options.timing {
+            Timing::Fixed => options.ticks_per_frame,
+            Timing::Vip => usize::MAX,
+        };
+        let result = run_frame(chip8, ticks);
+        if let Some(tracer) = &mut tracer {
+            tracer
+                .record(&chip8.take_trace())
+                .and_then(|_| match result {
+                    Err(_) => tracer.dump(),
+                    Ok(_) => Ok(()),
+                })
+                .map_err(|e| trace_error(options, e))?;
+        }
+        match result {
+            Ok(true) => return Ok(frame),
+            Ok(false) => (),
+            Err(e) => return Err(format!("Error in frame {}: {}", frame, e)),
+        }
+        chip8.tick_timers();
+    }
+    Ok(options.frames)
+}
+
+// Runs a frame's instructions, returning true if the program exited
+fn run_frame(chip8: &mut CPU, ticks: usize) -> Result<bool, Chip8Error> {
+    for _ in 0..ticks {
+        match chip8.tick()? {
+            StepOutcome::Exit => return Ok(true),
+            StepOutcome::WaitingForFrame => break,
+            _ => (),
+        }
+    }
+    Ok(false)
+}
+
+fn trace_error(options: &Options, e: std::io::Error) -> String {
+    let path = options.trace.as_deref().unwrap_or_default();
+    format!("Unable to write {}: {}", path, e)
+}
+
+fn registers_json(chip8: &CPU, frames: u32) -> String {
+    let list = |values: Vec<String>| format!("[{}]", values.join(", "));
+    let v = list(chip8.v_registers().iter().map(|v| v.to_string()).collect());
+    let stack = list(chip8.stack().iter().map(|addr| addr.to_string()).collect());
+    format!(
+        "{{\n  \"frames\": {},\n  \"pc\": {},\n  \"i\": {},\n  \"v\": {},\n  \"stack\": {},\n  \"delay_timer\": {},\n  \"sound_timer\": {}\n}}\n",
+        frames,
+        chip8.program_counter(),
+        chip8.i_register(),
+        v,
+        stack,
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    )
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut load_address = START_ADDRESS;
+    // Fixed by default so runs are reproducible
+    let mut seed = 0;
+    let mut frames = DEFAULT_FRAMES;
+    let mut ticks_per_frame = TICKS_PER_FRAME;
+    let mut timing = Timing::default();
+    let mut script = String::new();
+    let mut screenshot = None;
+    let mut registers = None;
+    let mut expect = None;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::default();
+    let mut trace_filter = TraceFilter::default();
+    let mut trace_ring = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().ok_or("--platform needs a platform name")?;
+                platform =
+                    Some(Platform::from_name(name).ok_or(format!("Unknown platform: {}", name))?);
+            }
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a profile name")?;
+                quirks = Some(
+                    Quirks::from_name(name).ok_or(format!("Unknown quirks profile: {}", name))?,
+                );
+            }
+            "--load-address" => {
+                let value = args.next().ok_or("--load-address needs an address")?;
+                load_address =
+                    parse_address(value).ok_or(format!("Invalid load address: {}", value))?;
+            }
+            "--seed" => seed = parse_number(args.next(), "--seed")?,
+            "--frames" => frames = parse_number(args.next(), "--frames")?,
+            "--ticks" => ticks_per_frame = parse_number(args.next(), "--ticks")?,
+            "--timing" => {
+                let name = args.next().ok_or("--timing needs a timing model")?;
+                timing =
+                    Timing::from_name(name).ok_or(format!("Unknown timing model: {}", name))?;
+            }
+            "--input" => {
+                script.push_str(args.next().ok_or("--input needs a script")?);
+                script.push('\n');
+            }
+            "--input-file" => {
+                let path = args.next().ok_or("--input-file needs a file name")?;
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("Unable to read {}: {}", path, e))?;
+                script.push_str(&text);
+                script.push('\n');
+            }
+            "--screenshot" => screenshot = Some(next_path(args.next(), "--screenshot")?),
+            "--registers" => registers = Some(next_path(args.next(), "--registers")?),
+            "--expect" => expect = Some(next_path(args.next(), "--expect")?),
+            "--trace" => trace = Some(next_path(args.next(), "--trace")?),
+            "--trace-format" => {
+                let name = args.next().ok_or("--trace-format needs a format")?;
+                trace_format = TraceFormat::from_name(name)
+                    .ok_or(format!("Unknown trace format: {}", name))?;
+            }
+            "--trace-pc" => {
+                let range = args.next().ok_or("--trace-pc needs an address range")?;
+                trace_filter.pcs = Some(
+                    TraceFilter::parse_pcs(range)
+                        .ok_or(format!("Invalid address range: {}", range))?,
+                );
+            }
+            "--trace-ops" => {
+                let list = args.next().ok_or("--trace-ops needs opcode classes")?;
+                trace_filter.classes = TraceFilter::parse_classes(list)
+                    .ok_or(format!("Invalid opcode classes: {}", list))?;
+            }
+            "--trace-ring" => trace_ring = Some(parse_number(args.next(), "--trace-ring")?),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or("Run with path to rom as argument")?;
+    if trace.is_none() && (trace_ring.is_some() || trace_filter != TraceFilter::default()) {
+        return Err("--trace-pc, --trace-ops and --trace-ring need --trace".to_string());
+    }
+    let input = InputScript::parse(&script).map_err(|e| format!("Invalid input script: {}", e))?;
+    Ok(Options {
+        rom_path,
+        platform,
+        quirks,
+        load_address,
+        seed,
+        frames,
+        ticks_per_frame,
+        timing,
+        input,
+        screenshot,
+        registers,
+        expect,
+        trace,
+        trace_format,
+        trace_filter,
+        trace_ring,
+    })
+}
+
+// Addresses are hex with a 0x prefix, like 0x600, or decimal
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: Option<&String>, flag: &str) -> Result<T, String> {
+    let arg = arg.ok_or(format!("{} needs a number", flag))?;
+    arg.parse()
+        .map_err(|_| format!("Invalid number for {}: {}", flag, arg))
+}
+
+fn next_path(arg: Option<&String>, flag: &str) -> Result<String, String> {
+    arg.cloned().ok_or(format!("{} needs a file name", flag))
+}
@@ -0,0 +1,144 @@
+use chip8_core::{Keymap, CPU};
+use std::time::{Duration, Instant};
+
+// How long a key stays down after a press, long enough to last until the
+// terminal's auto-repeat kicks in
+pub const DEFAULT_HOLD: Duration = Duration::from_millis(500);
+// How long a key stays down after each repeat once it's auto-repeating
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+// Keypad state driven by terminal key events. Most terminals only report
+// presses, so a release is assumed once a key stops repeating. Terminals
+// with the kitty keyboard protocol report real releases instead.
+pub struct Keys {
+    keymap: Keymap,
+    hold: Duration,
+    releases: bool,              // The terminal reports key releases
+    held: [Option<Instant>; 16], // When each held key gets released
+}
+
+impl Keys {
+    pub fn new(keymap: Keymap, hold: Duration, releases: bool) -> Keys {
+        Keys {
+            keymap,
+            hold,
+            releases,
+            held: [None; 16],
+        }
+    }
+
+    // A press or auto-repeat of a host key
+    pub fn press(&mut self, host: &str, cpu: &mut CPU, now: Instant) {
+        let Some(key) = self.keymap.key(host) else {
+            return;
+        };
+        let repeating = self.held[key].is_some();
+        self.held[key] = Some(now + hold_for(repeating, self.releases, self.hold));
+        cpu.keypress(key, true);
+    }
+
+    // A release reported by the terminal
+    pub fn release(&mut self, host: &str, cpu: &mut CPU) {
+        if let Some(key) = self.keymap.key(host) {
+            self.release_key(key, cpu);
+        }
+    }
+
+    // Releases keys that haven't been seen for long enough
+    pub fn expire(&mut self, cpu: &mut CPU, now: Instant) {
+        if self.releases {
+            return;
+        }
+        for key in expired(&self.held, now) {
+            self.release_key(key, cpu);
+        }
+    }
+
+    fn release_key(&mut self, key: usize, cpu: &mut CPU) {
+        self.held[key] = None;
+        cpu.keypress(key, false);
+    }
+}
+
+// How long a press keeps a key down. Seeing a key again while it's held
+// means it's repeating, so it can be let go of sooner, unless the terminal
+// will say when it's released.
+fn hold_for(repeating: bool, releases: bool, hold: Duration) -> Duration {
+    if repeating && !releases {
+        REPEAT_HOLD
+    } else {
+        hold
+    }
+}
+
+// Held keys due for release by now
+fn expired(held: &[Option<Instant>], now: Instant) -> Vec<usize> {
+    (0..held.len())
+        .filter(|key| matches!(held[*key], Some(until) if until <= now))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::{Platform, Quirks};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn repeats_shorten_the_hold() {
+        assert_eq!(hold_for(false, false, DEFAULT_HOLD), DEFAULT_HOLD);
+        assert_eq!(hold_for(true, false, DEFAULT_HOLD), REPEAT_HOLD);
+        // Real releases come from the terminal instead
+        assert_eq!(hold_for(true, true, DEFAULT_HOLD), DEFAULT_HOLD);
+    }
+
+    #[test]
+    fn keys_expire_once_their_time_is_up() {
+        let start = Instant::now();
+        let mut held = [None; 16];
+        held[1] = Some(start + ms(100));
+        held[5] = Some(start + ms(200));
+        assert_eq!(expired(&held, start), Vec::<usize>::new());
+        assert_eq!(expired(&held, start + ms(100)), [1]);
+        assert_eq!(expired(&held, start + ms(500)), [1, 5]);
+    }
+
+    #[test]
+    fn presses_without_releases_are_let_go_of() {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::default(), Some(0));
+        let mut keys = Keys::new(Keymap::qwerty(), DEFAULT_HOLD, false);
+        let start = Instant::now();
+
+        keys.press("w", &mut cpu, start);
+        assert_eq!(cpu.keys(), 1 << 5);
+        keys.expire(&mut cpu, start + ms(499));
+        assert_eq!(cpu.keys(), 1 << 5);
+
+        // Auto-repeat keeps it down, but only a little longer each time
+        keys.press("w", &mut cpu, start + ms(450));
+        keys.expire(&mut cpu, start + ms(549));
+        assert_eq!(cpu.keys(), 1 << 5);
+        keys.expire(&mut cpu, start + ms(550));
+        assert_eq!(cpu.keys(), 0);
+
+        // Unbound keys do nothing
+        keys.press("p", &mut cpu, start);
+        assert_eq!(cpu.keys(), 0);
+    }
+
+    #[test]
+    fn reported_releases_are_used_instead() {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::default(), Some(0));
+        let mut keys = Keys::new(Keymap::qwerty(), DEFAULT_HOLD, true);
+        let start = Instant::now();
+
+        keys.press("w", &mut cpu, start);
+        keys.expire(&mut cpu, start + ms(10_000));
+        assert_eq!(cpu.keys(), 1 << 5);
+        keys.release("W", &mut cpu);
+        assert_eq!(cpu.keys(), 0);
+    }
+}
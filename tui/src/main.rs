@@ -0,0 +1,324 @@
+mod input;
+mod screen;
+
+use chip8_core::*;
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, terminal,
+};
+use input::Keys;
+use screen::Screen;
+use std::{
+    env, fs,
+    io::{self, Stdout},
+    process,
+    time::{Duration, Instant},
+};
+
+// How often the screen is redrawn and input handled
+const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / TIMER_HZ as u64);
+
+struct Options {
+    rom_path: String,
+    platform: Option<Platform>, // Guessed from the rom when not given
+    quirks: Option<Quirks>,     // The platform's when not given
+    load_address: u16,
+    seed: Option<u64>,
+    ips: u32,
+    timing: Timing,
+    keymap: Keymap,
+    hold: Duration, // How long a key stays down without a repeat
+    palette: Palette,
+    panels: bool,
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            println!("{}", msg);
+            println!(
+                "Usage: chip8-tui [--platform {}] [--quirks {}] [--seed N] [--ips N] [--timing {}]\n\
+                 \x20                [--load-address ADDR] [--layout {}] [--hold MS] [--palette {}|COLOURS] [--panels] <rom>",
+                Platform::NAMES.join("|"),
+                Quirks::NAMES.join("|"),
+                Timing::NAMES.join("|"),
+                Keymap::NAMES.join("|"),
+                Palette::NAMES.join("|")
+            );
+            process::exit(2);
+        }
+    };
+
+    if let Err(msg) = run(options) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let data = fs::read(&options.rom_path)
+        .map_err(|e| format!("Unable to read {}: {}", options.rom_path, e))?;
+    let rom = Rom::with_load_address(&data, options.load_address);
+    let platform = options.platform.unwrap_or_else(|| rom.guess_platform());
+    let quirks = options.quirks.unwrap_or_else(|| platform.default_quirks());
+    let mut cpu = CPU::new(platform, quirks, options.seed);
+    cpu.set_timing(options.timing);
+    cpu.load_rom(&rom).map_err(|e| format!("Error: {}", e))?;
+
+    let terminal = Terminal::enter().map_err(|e| format!("Unable to set up terminal: {}", e))?;
+    let keys = Keys::new(options.keymap, options.hold, terminal.releases);
+    let mut emulator = Emulator {
+        cpu,
+        keys,
+        scheduler: Scheduler::new(options.ips),
+        screen: Screen::new(options.palette),
+        panels: options.panels,
+        paused: false,
+    };
+    let result = emulator.run(&mut io::stdout());
+    // Put the terminal back before anything gets printed
+    drop(terminal);
+    result
+}
+
+// Raw mode on the alternate screen, restored when dropped even if the
+// emulator stops with an error
+struct Terminal {
+    releases: bool, // Key releases are reported
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Terminal> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(Terminal { releases })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.releases {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Emulator {
+    cpu: CPU,
+    keys: Keys,
+    scheduler: Scheduler,
+    screen: Screen,
+    panels: bool,
+    paused: bool,
+}
+
+impl Emulator {
+    // Runs until the program exits or the user quits
+    fn run(&mut self, out: &mut Stdout) -> Result<(), String> {
+        let mut last_frame = Instant::now();
+        loop {
+            // Handle input while waiting for the next frame
+            let next_frame = last_frame + FRAME_TIME;
+            loop {
+                let timeout = next_frame.saturating_duration_since(Instant::now());
+                if !event::poll(timeout).map_err(input_error)? {
+                    break;
+                }
+                match event::read().map_err(input_error)? {
+                    Event::Key(key) if !self.handle_key(key)? => return Ok(()),
+                    Event::Resize(..) => self.screen.invalidate(),
+                    _ => (),
+                }
+            }
+
+            let now = Instant::now();
+            self.keys.expire(&mut self.cpu, now);
+            self.scheduler.set_timing(self.cpu.timing());
+            let slices = self.scheduler.advance(now - last_frame);
+            last_frame = now;
+
+            if !self.paused {
+                for slice in slices {
+                    if !self.run_slice(slice)? {
+                        return Ok(());
+                    }
+                }
+            }
+
+            self.screen
+                .draw(out, &self.cpu, self.panels, self.paused)
+                .map_err(|e| format!("Unable to draw: {}", e))?;
+        }
+    }
+
+    // Returns false once the program has exited
+    fn run_slice(&mut self, slice: Slice) -> Result<bool, String> {
+        for _ in 0..slice.instructions {
+            match self.cpu.tick().map_err(|e| format!("Error: {}", e))? {
+                StepOutcome::Exit => return Ok(false),
+                StepOutcome::WaitingForFrame => break,
+                _ => (),
+            }
+        }
+        if slice.tick_timers {
+            self.cpu.tick_timers();
+        }
+        Ok(true)
+    }
+
+    // Returns false when the user quits
+    fn handle_key(&mut self, key: KeyEvent) -> Result<bool, String> {
+        let host = match key.code {
+            KeyCode::Char(c) => Some(c.to_string()),
+            _ => None,
+        };
+        if key.kind == KeyEventKind::Release {
+            if let Some(host) = host {
+                self.keys.release(&host, &mut self.cpu);
+            }
+            return Ok(true);
+        }
+
+        match key.code {
+            KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false)
+            }
+            KeyCode::F(2) => {
+                self.panels = !self.panels;
+                self.screen.invalidate();
+            }
+            KeyCode::F(5) => self.paused = !self.paused,
+            KeyCode::F(6) if self.paused => self.step()?,
+            _ => {
+                if let Some(host) = host {
+                    self.keys.press(&host, &mut self.cpu, Instant::now());
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // Runs one instruction while paused, starting the next frame first if
+    // VIP timing has used this one up
+    fn step(&mut self) -> Result<(), String> {
+        if self.cpu.is_waiting_for_frame() {
+            self.cpu.tick_timers();
+        }
+        self.cpu.tick().map_err(|e| format!("Error: {}", e))?;
+        Ok(())
+    }
+}
+
+fn input_error(e: io::Error) -> String {
+    format!("Unable to read input: {}", e)
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut platform = None;
+    let mut quirks = None;
+    let mut load_address = START_ADDRESS;
+    let mut seed = None;
+    let mut ips = DEFAULT_IPS;
+    let mut timing = Timing::default();
+    let mut keymap = Keymap::default();
+    let mut hold = input::DEFAULT_HOLD;
+    let mut palette = Palette::default();
+    let mut panels = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().ok_or("--platform needs a platform name")?;
+                platform =
+                    Some(Platform::from_name(name).ok_or(format!("Unknown platform: {}", name))?);
+            }
+            "--quirks" => {
+                let name = args.next().ok_or("--quirks needs a profile name")?;
+                quirks = Some(
+                    Quirks::from_name(name).ok_or(format!("Unknown quirks profile: {}", name))?,
+                );
+            }
+            "--load-address" => {
+                let value = args.next().ok_or("--load-address needs an address")?;
+                load_address =
+                    parse_address(value).ok_or(format!("Invalid load address: {}", value))?;
+            }
+            "--seed" => seed = Some(parse_number(args.next(), "--seed")?),
+            "--ips" => {
+                ips = parse_number(args.next(), "--ips")?;
+                if ips == 0 {
+                    return Err("Invalid number for --ips: 0".to_string());
+                }
+            }
+            "--timing" => {
+                let name = args.next().ok_or("--timing needs a timing model")?;
+                timing =
+                    Timing::from_name(name).ok_or(format!("Unknown timing model: {}", name))?;
+            }
+            "--layout" => {
+                let name = args.next().ok_or("--layout needs a keyboard layout")?;
+                keymap =
+                    Keymap::from_name(name).ok_or(format!("Unknown keyboard layout: {}", name))?;
+            }
+            "--hold" => hold = Duration::from_millis(parse_number(args.next(), "--hold")?),
+            "--palette" => {
+                let spec = args
+                    .next()
+                    .ok_or("--palette needs a palette name or colours")?;
+                palette = Palette::parse(spec).ok_or(format!("Invalid palette: {}", spec))?;
+            }
+            "--panels" => panels = true,
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or("Run with path to rom as argument")?;
+    Ok(Options {
+        rom_path,
+        platform,
+        quirks,
+        load_address,
+        seed,
+        ips,
+        timing,
+        keymap,
+        hold,
+        palette,
+        panels,
+    })
+}
+
+// Addresses are hex with a 0x prefix, like 0x600, or decimal
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: Option<&String>, flag: &str) -> Result<T, String> {
+    let arg = arg.ok_or(format!("{} needs a number", flag))?;
+    arg.parse()
+        .map_err(|_| format!("Invalid number for {}: {}", flag, arg))
+}
@@ -0,0 +1,241 @@
+use chip8_core::{Frame, Instruction, Palette, CPU};
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Color, Print, SetBackgroundColor, SetForegroundColor},
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+
+// Upper half block, drawn in the top pixel's colour over the bottom one's
+const HALF_BLOCK: char = '▀';
+// Stack entries shown in the side panel
+const STACK_ROWS: usize = 12;
+const HELP: &str = "Esc quit  F2 panels  F5 pause  F6 step";
+
+// Draws the display with each terminal cell covering two pixel rows, so
+// 64x32 fits in 64x16 cells and hires 128x64 in 128x32. Only cells that
+// changed since the last draw are sent, which keeps slow links usable.
+pub struct Screen {
+    palette: [Color; 4],
+    width: usize,
+    height: usize,
+    cells: Vec<(u8, u8)>, // Top and bottom pixel of every cell as drawn
+    stale: bool,          // Everything needs drawing again
+}
+
+impl Screen {
+    pub fn new(palette: Palette) -> Screen {
+        Screen {
+            palette: palette.colors.map(|c| Color::Rgb {
+                r: c.r,
+                g: c.g,
+                b: c.b,
+            }),
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+            stale: true,
+        }
+    }
+
+    // Forgets what's on the terminal, after a resize or layout change
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn draw(
+        &mut self,
+        out: &mut impl Write,
+        cpu: &CPU,
+        panels: bool,
+        paused: bool,
+    ) -> io::Result<()> {
+        let frame = cpu.get_display();
+        if frame.width != self.width || frame.height != self.height {
+            self.width = frame.width;
+            self.height = frame.height;
+            self.stale = true;
+        }
+        if self.stale {
+            queue!(out, SetBackgroundColor(Color::Reset), Clear(ClearType::All))?;
+            self.cells = vec![(u8::MAX, u8::MAX); self.width * self.height / 2];
+        }
+
+        let cells = half_blocks(&frame);
+        let mut cursor = None;
+        for (index, &(top, bottom)) in cells.iter().enumerate() {
+            if self.cells[index] == (top, bottom) {
+                continue;
+            }
+            // Printing moves the cursor along, so runs of changed cells
+            // only need one move
+            let (row, col) = (index / self.width, index % self.width);
+            if cursor != Some((row, col)) {
+                queue!(out, MoveTo(col as u16, row as u16))?;
+            }
+            queue!(
+                out,
+                SetForegroundColor(self.palette[top as usize]),
+                SetBackgroundColor(self.palette[bottom as usize]),
+                Print(HALF_BLOCK)
+            )?;
+            cursor = Some((row, col + 1));
+        }
+        self.cells = cells;
+        queue!(
+            out,
+            SetForegroundColor(Color::Reset),
+            SetBackgroundColor(Color::Reset)
+        )?;
+
+        let status = if paused { "PAUSED" } else { "" };
+        queue!(
+            out,
+            MoveTo(0, (self.height / 2) as u16),
+            Print(format!("{:<8}{}", status, HELP)),
+            Clear(ClearType::UntilNewLine)
+        )?;
+        if panels {
+            draw_panels(out, cpu, self.width as u16 + 2)?;
+        }
+        self.stale = false;
+        out.flush()
+    }
+}
+
+// Top and bottom pixel of every cell, row by row
+fn half_blocks(frame: &Frame) -> Vec<(u8, u8)> {
+    let (width, pixels) = (frame.width, frame.pixels);
+    (0..frame.height / 2)
+        .flat_map(|row| (0..width).map(move |col| (row, col)))
+        .map(|(row, col)| {
+            let top = pixels[row * 2 * width + col] & 3;
+            let bottom = pixels[(row * 2 + 1) * width + col] & 3;
+            (top, bottom)
+        })
+        .collect()
+}
+
+// Registers and the stack, in a column to the right of the display
+fn draw_panels(out: &mut impl Write, cpu: &CPU, left: u16) -> io::Result<()> {
+    let pc = cpu.program_counter() as usize;
+    let memory = cpu.memory();
+    let instruction = match memory.get(pc..pc + 2) {
+        Some(bytes) => Instruction::decode(u16::from_be_bytes([bytes[0], bytes[1]])).to_string(),
+        None => String::new(),
+    };
+
+    let mut lines = vec![
+        format!("PC {:04X}  {}", pc, instruction),
+        format!("I  {:04X}", cpu.i_register()),
+        format!(
+            "DT {:02X}    ST {:02X}",
+            cpu.delay_timer(),
+            cpu.sound_timer()
+        ),
+        String::new(),
+    ];
+    for (row, values) in cpu.v_registers().chunks(4).enumerate() {
+        let values: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(i, val)| format!("V{:X} {:02X}", row * 4 + i, val))
+            .collect();
+        lines.push(values.join("  "));
+    }
+    lines.push(String::new());
+    lines.push("Stack".to_string());
+    let stack = cpu.stack();
+    for i in 0..STACK_ROWS {
+        // Most recent call first
+        lines.push(match stack.iter().rev().nth(i) {
+            Some(addr) => format!("  {:04X}", addr),
+            None => String::new(),
+        });
+    }
+
+    for (row, line) in lines.iter().enumerate() {
+        queue!(
+            out,
+            MoveTo(left, row as u16),
+            Print(line),
+            Clear(ClearType::UntilNewLine)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::{Platform, Quirks, Rom};
+
+    #[test]
+    fn cells_cover_two_pixel_rows() {
+        #[rustfmt::skip]
+        let pixels = [
+            0, 1, 2, 3,
+            0, 0, 1, 7,
+            1, 1, 0, 0,
+            0, 1, 0, 1,
+        ];
+        let frame = Frame {
+            width: 4,
+            height: 4,
+            pixels: &pixels,
+        };
+        assert_eq!(
+            half_blocks(&frame),
+            [
+                (0, 0),
+                (1, 0),
+                (2, 1),
+                (3, 3),
+                (1, 0),
+                (1, 1),
+                (0, 0),
+                (0, 1)
+            ]
+        );
+    }
+
+    // Draws the 0 from the font in the top left corner
+    fn cpu() -> CPU {
+        let mut cpu = CPU::new(Platform::Chip8, Quirks::default(), Some(0));
+        let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+        cpu.load_rom(&Rom::new(&rom)).unwrap();
+        cpu
+    }
+
+    fn blocks(out: &[u8]) -> usize {
+        String::from_utf8_lossy(out).matches(HALF_BLOCK).count()
+    }
+
+    #[test]
+    fn only_changed_cells_are_drawn_again() {
+        let mut cpu = cpu();
+        let mut screen = Screen::new(Palette::default());
+        let mut out = Vec::new();
+        screen.draw(&mut out, &cpu, false, false).unwrap();
+        assert_eq!(blocks(&out), 64 * 16);
+
+        out.clear();
+        screen.draw(&mut out, &cpu, false, false).unwrap();
+        assert_eq!(blocks(&out), 0);
+
+        // The 0 covers 4 by 3 cells, but the middle of its second row of
+        // cells is still blank
+        for _ in 0..3 {
+            cpu.tick().unwrap();
+        }
+        out.clear();
+        screen.draw(&mut out, &cpu, false, false).unwrap();
+        assert_eq!(blocks(&out), 4 + 2 + 4);
+
+        screen.invalidate();
+        out.clear();
+        screen.draw(&mut out, &cpu, false, false).unwrap();
+        assert_eq!(blocks(&out), 64 * 16);
+    }
+}
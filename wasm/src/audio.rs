@@ -0,0 +1,56 @@
+use web_sys::{AudioContext, GainNode, OscillatorNode, OscillatorType};
+use wasm_bindgen::JsValue;
+
+// Seconds the gain takes to settle, long enough to avoid clicks
+const ENVELOPE_TIME: f64 = 0.005;
+
+// A square wave oscillator that is faded in while the sound timer runs
+pub struct Beeper {
+    ctx: AudioContext,
+    oscillator: OscillatorNode,
+    gain: GainNode,
+    pub volume: f32,
+    pub muted: bool,
+    active: bool,
+}
+
+impl Beeper {
+    pub fn new(frequency: f32, volume: f32) -> Result<Beeper, JsValue> {
+        let ctx = AudioContext::new()?;
+        let oscillator = ctx.create_oscillator()?;
+        oscillator.set_type(OscillatorType::Square);
+        oscillator.frequency().set_value(frequency);
+
+        let gain = ctx.create_gain()?;
+        gain.gain().set_value(0.);
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&ctx.destination())?;
+        oscillator.start()?;
+
+        Ok(Beeper { ctx, oscillator, gain, volume, muted: false, active: false })
+    }
+
+    // Browsers only allow audio to start from a user gesture
+    pub fn resume(&self) -> Result<(), JsValue> {
+        self.ctx.resume().map(|_| ())
+    }
+
+    pub fn set_frequency(&self, frequency: f32) {
+        self.oscillator.frequency().set_value(frequency);
+    }
+
+    // Fades towards the volume while active, or silence
+    pub fn set_active(&mut self, active: bool) -> Result<(), JsValue> {
+        self.active = active;
+        let target = if self.active && !self.muted { self.volume } else { 0. };
+        self.gain
+            .gain()
+            .set_target_at_time(target, self.ctx.current_time(), ENVELOPE_TIME)?;
+        Ok(())
+    }
+
+    // Applies a volume or mute change straight away
+    pub fn refresh(&mut self) -> Result<(), JsValue> {
+        self.set_active(self.active)
+    }
+}
@@ -1,19 +1,36 @@
+mod audio;
+
+use audio::Beeper;
 use chip8_core::*;
 use wasm_bindgen::{prelude::*, JsCast};
 use web_sys::{KeyboardEvent, CanvasRenderingContext2d, HtmlCanvasElement};
-use js_sys::Uint8Array;
+use js_sys::{Array, Object, Reflect, Uint8Array, JSON};
+use std::time::Duration;
+
+const REWIND_INTERVAL: u32 = 4;
+const REWIND_CAPACITY: usize = 900;
+const DEFAULT_FREQUENCY: f32 = 440.;
+const DEFAULT_VOLUME: f32 = 0.25;
 
 #[wasm_bindgen]
 pub struct CPUWasm {
     chip8: CPU,
-    ctx: CanvasRenderingContext2d
+    rewinder: Rewinder,
+    scheduler: Scheduler,
+    keymap: Keymap,
+    palette: Palette,
+    chosen_palette: Option<Palette>, // Set by the page, wins over the database's
+    database: RomDatabase,
+    ctx: CanvasRenderingContext2d,
+    beeper: Beeper
 }
 
 #[wasm_bindgen]
 impl CPUWasm {
+    // Passing a seed (a BigInt) makes random numbers repeat between runs
     #[wasm_bindgen(constructor)]
-    pub fn new() -> Result<CPUWasm, JsValue>{
-        let chip8 = CPU::new();
+    pub fn new(seed: Option<u64>) -> Result<CPUWasm, JsValue>{
+        let chip8 = CPU::new(Platform::default(), Quirks::default(), seed);
 
         let document = web_sys::window().unwrap().document().unwrap();
         let canvas = document.get_element_by_id("canvas").unwrap();
@@ -27,78 +44,341 @@ impl CPUWasm {
             .dyn_into::<CanvasRenderingContext2d>()
             .unwrap();
         
-        Ok(CPUWasm{chip8, ctx})
+        let rewinder = Rewinder::new(REWIND_INTERVAL, REWIND_CAPACITY);
+        let beeper = Beeper::new(DEFAULT_FREQUENCY, DEFAULT_VOLUME)?;
+
+        let scheduler = Scheduler::new(DEFAULT_IPS);
+
+        Ok(CPUWasm{chip8, rewinder, scheduler, keymap: Keymap::default(), palette: Palette::lime(), chosen_palette: None, database: RomDatabase::builtin(), ctx, beeper})
+    }
+
+    // Runs whatever is due after elapsed milliseconds, ticking timers at
+    // 60 Hz and recording rewind history. Returns false once the program
+    // has exited.
+    #[wasm_bindgen]
+    pub fn run(&mut self, elapsed: f64) -> Result<bool, JsValue> {
+        // Loading a state can change the timing
+        self.scheduler.set_timing(self.chip8.timing());
+        for slice in self.scheduler.advance(to_duration(elapsed)) {
+            for _ in 0..slice.instructions {
+                if self.chip8.is_waiting_for_frame() {
+                    break;
+                }
+                if !self.tick()? {
+                    return Ok(false);
+                }
+            }
+            if slice.tick_timers {
+                self.chip8.tick_timers();
+                self.rewinder.record(&self.chip8);
+            }
+        }
+        self.update_audio()?;
+        Ok(true)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_ips(&mut self, ips: u32) {
+        self.scheduler.set_ips(ips.max(1));
+    }
+
+    // Returns false once the program has exited
+    #[wasm_bindgen]
+    pub fn tick(&mut self) -> Result<bool, JsValue> {
+        let outcome = self.chip8.tick().map_err(to_js_error)?;
+        Ok(outcome != StepOutcome::Exit)
+    }
+
+    // Starts or stops the tone to match the sound timer, call once per frame
+    #[wasm_bindgen]
+    pub fn update_audio(&mut self) -> Result<(), JsValue> {
+        self.beeper.set_active(self.chip8.is_sound_active())
+    }
+
+    // Call from a click handler, browsers keep audio suspended until then
+    #[wasm_bindgen]
+    pub fn resume_audio(&self) -> Result<(), JsValue> {
+        self.beeper.resume()
+    }
+
+    // Silences the tone while emulation isn't running
+    #[wasm_bindgen]
+    pub fn stop_audio(&mut self) -> Result<(), JsValue> {
+        self.beeper.set_active(false)
+    }
+
+    // Volume from 0 to 1
+    #[wasm_bindgen]
+    pub fn set_volume(&mut self, volume: f32) -> Result<(), JsValue> {
+        self.beeper.volume = volume.clamp(0., 1.);
+        self.beeper.refresh()
     }
 
     #[wasm_bindgen]
-    pub fn tick(&mut self) {
-        self.chip8.tick();
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.beeper.set_frequency(frequency);
     }
 
     #[wasm_bindgen]
-    pub fn tick_timers(&mut self) {
-        self.chip8.tick_timers();
+    pub fn set_muted(&mut self, muted: bool) -> Result<(), JsValue> {
+        self.beeper.muted = muted;
+        self.beeper.refresh()
     }
 
     #[wasm_bindgen]
     pub fn reset(&mut self) {
         self.chip8.reset();
+        self.rewinder.clear();
+    }
+
+    // Steps back through history at the rate it was recorded, returns false
+    // once it is used up
+    #[wasm_bindgen]
+    pub fn rewind(&mut self, elapsed: f64) -> bool {
+        let ticks = self.scheduler.advance(to_duration(elapsed))
+            .iter()
+            .filter(|slice| slice.tick_timers)
+            .count();
+        (0..ticks).all(|_| self.rewinder.rewind_frame(&mut self.chip8))
+    }
+
+    #[wasm_bindgen]
+    pub fn set_platform(&mut self, name: &str) -> Result<(), JsValue> {
+        let platform = Platform::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown platform: {}", name)))?;
+        self.chip8.set_platform(platform);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn set_quirks(&mut self, name: &str) -> Result<(), JsValue> {
+        let quirks = Quirks::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown quirks profile: {}", name)))?;
+        self.chip8.set_quirks(quirks);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn set_timing(&mut self, name: &str) -> Result<(), JsValue> {
+        let timing = Timing::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown timing model: {}", name)))?;
+        self.chip8.set_timing(timing);
+        Ok(())
+    }
+
+    // Takes a preset name or comma separated CSS hex colours, either
+    // background and foreground or one for each combination of bitplanes
+    #[wasm_bindgen]
+    pub fn set_palette(&mut self, spec: &str) -> Result<(), JsValue> {
+        let palette = Palette::parse(spec)
+            .ok_or_else(|| JsValue::from_str(&format!("Invalid palette: {}", spec)))?;
+        self.palette = palette;
+        self.chosen_palette = Some(palette);
+        Ok(())
+    }
+
+    // Takes an object, or the same as a JSON string, like
+    // { "layout": "azerty", "keys": { "5": ["z", "ArrowUp"] } }
+    // Host keys are KeyboardEvent.key names and replace the layout's.
+    #[wasm_bindgen]
+    pub fn set_keymap(&mut self, config: JsValue) -> Result<(), JsValue> {
+        let config = match config.as_string() {
+            Some(json) => JSON::parse(&json)?,
+            None => config,
+        };
+
+        let layout = Reflect::get(&config, &"layout".into())?;
+        let mut keymap = match layout.as_string() {
+            Some(name) => Keymap::from_name(&name)
+                .ok_or_else(|| JsValue::from_str(&format!("Unknown keyboard layout: {}", name)))?,
+            None => Keymap::default(),
+        };
+
+        let keys = Reflect::get(&config, &"keys".into())?;
+        if keys.is_object() {
+            for entry in Object::entries(&Object::from(keys)).iter() {
+                let entry = Array::from(&entry);
+                let name = entry.get(0).as_string().unwrap_or_default();
+                let key = Keymap::parse_key(&name)
+                    .ok_or_else(|| JsValue::from_str(&format!("Unknown keypad key: {}", name)))?;
+                // One host key or a list of them
+                let hosts = entry.get(1);
+                let hosts: Vec<String> = if Array::is_array(&hosts) {
+                    Array::from(&hosts).iter().filter_map(|host| host.as_string()).collect()
+                } else {
+                    hosts.as_string().into_iter().collect()
+                };
+                keymap.set(key, &hosts);
+            }
+        }
+
+        self.keymap = keymap;
+        Ok(())
     }
 
     #[wasm_bindgen]
     pub fn keypress(&mut self, event: KeyboardEvent, pressed: bool) {
         let key  = event.key();
-        if let Some(k) = convert_keycode(&key) {
+        if let Some(k) = self.keymap.key(&key) {
             self.chip8.keypress(k, pressed);
         };
     }
 
+    // With use_database, a known rom gets the platform, quirks, speed and
+    // colours recommended for it. Returns what the database knows about the
+    // rom, or null, like
+    // { "title": "Brix", "authors": ["Andreas Gustafsson"], "platform": "chip8",
+    //   "ips": 600, "keys": [{ "action": "left", "key": 4, "hosts": ["q"] }] }
     #[wasm_bindgen]
-    pub fn load_rom(&mut self, data: Uint8Array) {
-        self.chip8.load_rom(&data.to_vec());
+    pub fn load_rom(&mut self, data: Uint8Array, use_database: bool) -> Result<JsValue, JsValue> {
+        let data = data.to_vec();
+        let info = match use_database {
+            true => self.database.lookup(&Rom::new(&data)).cloned(),
+            false => None,
+        };
+
+        let mut rom = Rom::new(&data);
+        if let Some(info) = &info {
+            if let Some(platform) = info.platform {
+                self.chip8.set_platform(platform);
+            }
+            if let Some(quirks) = info.quirks {
+                self.chip8.set_quirks(quirks);
+            }
+            if let Some(ips) = info.ips {
+                self.scheduler.set_ips(ips);
+            }
+            if let Some(address) = info.load_address {
+                rom = Rom::with_load_address(&data, address);
+            }
+        }
+        self.chip8.load_rom(&rom).map_err(to_js_error)?;
+        self.palette = rom_palette(self.chosen_palette, info.as_ref().and_then(|info| info.palette));
+
+        match info {
+            Some(info) => self.rom_info(&info),
+            None => Ok(JsValue::NULL),
+        }
     }
 
+    // Clears to the palette's background then draws the lit pixels
     #[wasm_bindgen]
     pub fn draw_screen(&mut self, scale: usize) {
-        let disp = self.chip8.get_display();
-        for i in 0..(SCREEN_WIDTH * SCREEN_HEIGHT) {
-            if disp[i] {
-                let x = i % SCREEN_WIDTH;
-                let y = i / SCREEN_WIDTH;
-                self.ctx.fill_rect(
-                    (x * scale) as f64,
-                    (y * scale) as f64,
-                    scale as f64, 
-                    scale as f64
-                )
+        let frame = self.chip8.get_display();
+
+        self.ctx.set_fill_style_str(&self.palette.background().to_string());
+        self.ctx.fill_rect(
+            0.,
+            0.,
+            (scale * SCREEN_WIDTH) as f64,
+            (scale * SCREEN_HEIGHT) as f64
+        );
+
+        // Scale is given for the low resolution display
+        let size = (scale * SCREEN_WIDTH) as f64 / frame.width as f64;
+        let colours = self.palette.colors.map(|colour| colour.to_string());
+        for (i, pixel) in frame.pixels.iter().enumerate() {
+            if *pixel == 0 {
+                continue;
             }
+            let x = i % frame.width;
+            let y = i / frame.width;
+            self.ctx.set_fill_style_str(&colours[*pixel as usize & 3]);
+            self.ctx.fill_rect(
+                x as f64 * size,
+                y as f64 * size,
+                size,
+                size
+            )
         }
     }
 
+    #[wasm_bindgen]
+    pub fn save_state(&self) -> Vec<u8> {
+        self.chip8.save_state()
+    }
+
+    #[wasm_bindgen]
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.chip8.load_state(data).map_err(to_js_error)
+    }
+
+    #[wasm_bindgen]
+    pub fn platform(&self) -> String {
+        self.chip8.platform().name().to_string()
+    }
+
+    #[wasm_bindgen]
+    pub fn ips(&self) -> u32 {
+        self.scheduler.ips()
+    }
+
     #[wasm_bindgen]
     pub fn button_press(&mut self, key: usize, pressed: bool) {
         self.chip8.keypress(key, pressed);
     }
 }
 
-fn convert_keycode(key: &str) -> Option<usize> {
-    match key {
-        "1" => Some(0x1),
-        "2" => Some(0x2),
-        "3" => Some(0x3),
-        "4" => Some(0xC),
-        "q" => Some(0x4),
-        "w" => Some(0x5),
-        "e" => Some(0x6),
-        "r" => Some(0xD),
-        "a" => Some(0x7),
-        "s" => Some(0x8),
-        "d" => Some(0x9),
-        "f" => Some(0xE),
-        "z" => Some(0xA),
-        "x" => Some(0x0),
-        "c" => Some(0xB),
-        "v" => Some(0xF),
-        _ => None
-    }
-}
\ No newline at end of file
+impl CPUWasm {
+    // Database entry as a plain object, with the current settings and the
+    // keyboard keys on each keypad key the rom uses
+    fn rom_info(&self, info: &RomInfo) -> Result<JsValue, JsValue> {
+        let object = Object::new();
+        Reflect::set(&object, &"title".into(), &info.title.as_str().into())?;
+        let authors: Array = info.authors.iter().map(|author| JsValue::from_str(author)).collect();
+        Reflect::set(&object, &"authors".into(), &authors)?;
+        if let Some(release) = &info.release {
+            Reflect::set(&object, &"release".into(), &release.as_str().into())?;
+        }
+        if let Some(description) = &info.description {
+            Reflect::set(&object, &"description".into(), &description.as_str().into())?;
+        }
+        Reflect::set(&object, &"platform".into(), &self.platform().into())?;
+        Reflect::set(&object, &"ips".into(), &self.ips().into())?;
+
+        let keys = Array::new();
+        for (action, key) in &info.keys {
+            let entry = Object::new();
+            let hosts: Array = self.keymap.hosts(*key).map(JsValue::from_str).collect();
+            Reflect::set(&entry, &"action".into(), &action.as_str().into())?;
+            Reflect::set(&entry, &"key".into(), &(*key).into())?;
+            Reflect::set(&entry, &"hosts".into(), &hosts)?;
+            keys.push(&entry);
+        }
+        Reflect::set(&object, &"keys".into(), &keys)?;
+        Ok(object.into())
+    }
+}
+
+// Animation frame timestamps are in milliseconds
+fn to_duration(elapsed: f64) -> Duration {
+    Duration::from_secs_f64(elapsed.max(0.) / 1000.)
+}
+
+// The page's palette if it set one, then the one the rom database
+// recommends, like desktop's config
+fn rom_palette(chosen: Option<Palette>, recommended: Option<Palette>) -> Palette {
+    chosen.or(recommended).unwrap_or_else(Palette::lime)
+}
+
+fn to_js_error(err: impl std::fmt::Display) -> JsValue {
+    js_sys::Error::new(&err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chosen_palette_wins_over_the_recommended_one() {
+        let recommended = Some(Palette::octo());
+        assert_eq!(rom_palette(Some(Palette::amber()), recommended), Palette::amber());
+        assert_eq!(rom_palette(Some(Palette::amber()), None), Palette::amber());
+    }
+
+    #[test]
+    fn recommended_palette_fills_in_when_none_is_chosen() {
+        assert_eq!(rom_palette(None, Some(Palette::octo())), Palette::octo());
+        assert_eq!(rom_palette(None, None), Palette::lime());
+    }
+}
@@ -3,8 +3,10 @@ import init, * as wasm from "./wasm.js";
 const WIDTH = 64;
 const HEIGHT = 32;
 let SCALE = Math.floor(window.innerWidth / 80) - Math.floor(window.innerWidth / 800);
-const TICKS_PER_FRAME = 10;
 let anim_frame = 0;
+let last_frame = null;
+let current_rom = null;
+let rewinding = false;
 
 const canvas = document.getElementById("canvas");
 canvas.width = WIDTH * SCALE;
@@ -13,24 +15,114 @@ const ctx = canvas.getContext("2d");
 ctx.fillStyle = "black";
 ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
 const roms = document.getElementById("roms");
+const platform = document.getElementById("platform");
+const quirks = document.getElementById("quirks");
+const timing = document.getElementById("timing");
 const start = document.getElementById("start");
+const save = document.getElementById("save");
+const load = document.getElementById("load");
+const volume = document.getElementById("volume");
+const frequency = document.getElementById("frequency");
+const mute = document.getElementById("mute");
+const ips = document.getElementById("ips");
+const layout = document.getElementById("layout");
+const palette = document.getElementById("palette");
+const colours = document.getElementById("colours");
+const database = document.getElementById("database");
+const rominfo = document.getElementById("rominfo");
 
 async function run() {
     await init();
-    let chip8 = new wasm.CPUWasm();
+    // A ?seed=N query parameter makes runs repeatable
+    const seed = new URLSearchParams(window.location.search).get("seed");
+    let chip8 = new wasm.CPUWasm(seed === null ? undefined : BigInt(seed));
+
+    // A custom keymap can be kept in localStorage as JSON, see set_keymap
+    const keymap = localStorage.getItem("keymap");
+    if (keymap) {
+        try {
+            chip8.set_keymap(keymap);
+            layout.value = JSON.parse(keymap).layout || "qwerty";
+        } catch (err) {
+            alert("Unable to use saved keymap: " + err.message);
+        }
+    }
+
+    // Either a preset name or custom colours like "#000000,#33ff66"
+    const saved_palette = localStorage.getItem("palette");
+    if (saved_palette) {
+        try {
+            chip8.set_palette(saved_palette);
+            if (palette.querySelector(`option[value="${saved_palette}"]`)) {
+                palette.value = saved_palette;
+            } else {
+                colours.value = saved_palette;
+            }
+        } catch (err) {
+            alert("Unable to use saved palette: " + err.message);
+        }
+    }
+
+    palette.addEventListener("change", function(event) {
+        chip8.set_palette(palette.value);
+        colours.value = "";
+        localStorage.setItem("palette", palette.value);
+    });
+
+    colours.addEventListener("change", function(event) {
+        const spec = colours.value.trim() || palette.value;
+        try {
+            chip8.set_palette(spec);
+        } catch (err) {
+            alert(err.message);
+            return;
+        }
+        localStorage.setItem("palette", spec);
+    });
+
+    layout.addEventListener("change", function(event) {
+        chip8.set_keymap({ layout: layout.value });
+        localStorage.setItem("keymap", JSON.stringify({ layout: layout.value }));
+    });
 
     document.addEventListener("keydown", function(event) {
+        if (event.key == "Backspace") {
+            event.preventDefault();
+            rewinding = true;
+            return;
+        }
         chip8.keypress(event, true);
     })
 
     document.addEventListener("keyup", function(event) {
+        if (event.key == "Backspace") {
+            rewinding = false;
+            return;
+        }
         chip8.keypress(event, false);
     })
 
+    volume.addEventListener("input", function(event) {
+        chip8.set_volume(volume.value / 100);
+    });
+
+    frequency.addEventListener("change", function(event) {
+        chip8.set_frequency(Number(frequency.value));
+    });
+
+    mute.addEventListener("change", function(event) {
+        chip8.set_muted(mute.checked);
+    });
+
+    ips.addEventListener("change", function(event) {
+        chip8.set_ips(Number(ips.value));
+    });
+
     start.addEventListener("click", function(event) {
         if (anim_frame != 0) {
             window.cancelAnimationFrame(anim_frame);
         }
+        chip8.resume_audio();
 
         let file = roms.value;
         if (file == "NONE") {
@@ -43,26 +135,122 @@ async function run() {
             .then(buffer => {
                 const rom = new Uint8Array(buffer);
                 chip8.reset();
-                chip8.load_rom(rom);
-                mainloop(chip8);
+                let info;
+                try {
+                    // The page's settings, unless the database knows better
+                    chip8.set_platform(platform.value);
+                    chip8.set_quirks(quirks.value);
+                    chip8.set_timing(timing.value);
+                    chip8.set_ips(Number(ips.value));
+                    chip8.set_palette(colours.value.trim() || palette.value);
+                    info = chip8.load_rom(rom, database.checked);
+                } catch (err) {
+                    alert("Unable to load ROM: " + err.message);
+                    return;
+                }
+                showRomInfo(info);
+                if (info) {
+                    platform.value = info.platform;
+                    ips.value = info.ips;
+                }
+                current_rom = file;
+                last_frame = null;
+                mainloop(chip8, performance.now());
             });
     }, false);
+
+    save.addEventListener("click", function(event) {
+        if (current_rom == null) {
+            alert("Start a ROM before saving");
+            return;
+        }
+        localStorage.setItem("state:" + current_rom, toBase64(chip8.save_state()));
+    }, false);
+
+    load.addEventListener("click", function(event) {
+        const saved = current_rom && localStorage.getItem("state:" + current_rom);
+        if (!saved) {
+            alert("No saved state for this ROM");
+            return;
+        }
+        try {
+            chip8.load_state(fromBase64(saved));
+        } catch (err) {
+            alert("Unable to load state: " + err.message);
+            return;
+        }
+        if (anim_frame == 0) {
+            last_frame = null;
+            mainloop(chip8, performance.now());
+        }
+    }, false);
 }
 
-function mainloop(chip8) {
-    for (let i = 0; i < TICKS_PER_FRAME; i++) {
-        chip8.tick();
+// Title, authors and the keys the game uses from the ROM database
+function showRomInfo(info) {
+    if (!info) {
+        rominfo.textContent = "";
+        return;
+    }
+    let text = info.title;
+    if (info.authors.length > 0) {
+        text += " by " + info.authors.join(", ");
+    }
+    if (info.release) {
+        text += " (" + info.release + ")";
     }
-    chip8.tick_timers();
+    const keys = info.keys.map(k => `${k.action}: ${k.hosts.join("/") || k.key.toString(16)}`);
+    if (keys.length > 0) {
+        text += " \u2014 " + keys.join(", ");
+    }
+    rominfo.textContent = text;
+}
 
-    ctx.fillStyle = "black";
-    ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE);
+// localStorage only holds strings, so states are stored as base64
+function toBase64(bytes) {
+    let binary = "";
+    for (let i = 0; i < bytes.length; i++) {
+        binary += String.fromCharCode(bytes[i]);
+    }
+    return btoa(binary);
+}
+
+function fromBase64(text) {
+    const binary = atob(text);
+    const bytes = new Uint8Array(binary.length);
+    for (let i = 0; i < binary.length; i++) {
+        bytes[i] = binary.charCodeAt(i);
+    }
+    return bytes;
+}
+
+// Speed comes from the time between frames, not the refresh rate
+function mainloop(chip8, timestamp) {
+    const elapsed = last_frame === null ? 0 : timestamp - last_frame;
+    last_frame = timestamp;
+
+    if (rewinding) {
+        chip8.rewind(elapsed);
+        chip8.stop_audio();
+    } else {
+        try {
+            if (!chip8.run(elapsed)) {
+                anim_frame = 0;
+                chip8.stop_audio();
+                return;
+            }
+        } catch (err) {
+            anim_frame = 0;
+            chip8.stop_audio();
+            alert("Emulator stopped: " + err.message);
+            return;
+        }
+    }
 
-    ctx . fillStyle = "lime";
     chip8.draw_screen(SCALE);
 
-    anim_frame = window.requestAnimationFrame(() => {
-        mainloop(chip8);
+    anim_frame = window.requestAnimationFrame((timestamp) => {
+        mainloop(chip8, timestamp);
     })
 }
 